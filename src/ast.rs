@@ -1,9 +1,29 @@
-#[derive(Debug)]
+use std::fmt;
+
+use crate::parse;
+
+// byte range in the source plus the 1 based line and column it starts on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl Span {
+    // span from the start of self to the end of other
+    pub fn to(self, other: Span) -> Span {
+        Span{end: other.end, ..self}
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Register {
     A, B, C, D
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Token {
     Num(usize),
     Reg(Register),
@@ -15,28 +35,43 @@ pub enum Token {
     Deref,
     Jump,
     From,
+    Call,
+    Return,
     Inc,
+    Move,
+    Unmove,
+    Swap,
+    Xor,
+    Mul,
+    Rol,
+    Ror,
+    Neg,
     Halt,
     Backwards,
     Forwards,
     Reverse,
     Io,
+    // only seen by macros::expand, the parser never gets them
+    Macro,
+    EndMacro,
     If, Eq,
     Gt, Gte,
     Lt, Lte,
     And, Or,
     Nop,
+    // only kept by lex::lex_with_comments, the parser skips it like Nop
+    Comment(String),
     Newlines(usize)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Dest {
     Reg(Register),
     Addr(usize),
     Deref(Box<Dest>)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Source {
     Reg(Register),
     Addr(usize),
@@ -44,11 +79,28 @@ pub enum Source {
     Deref(Box<Source>)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
     Inc(Dest, Source),
+    // save the destination's value on its stack and overwrite it going
+    // forwards, unmove does the same going backwards
+    Move(Dest, Source),
+    Unmove(Dest, Source),
+    // both are their own inverse, so they do the same in either direction
+    Swap(Dest, Dest),
+    Xor(Dest, Source),
+    // multiply by an odd constant, dividing it back out going backwards
+    Mul(Dest, usize),
+    // rotate left or right by the source, the other way going backwards
+    Rol(Dest, Source),
+    Ror(Dest, Source),
+    Neg(Dest),
     Jump(Option<String>),
     From(Option<String>),
+    // subroutines share one call stack, going backwards a call re-enters
+    // the callee at the return it left by
+    Call(String),
+    Return,
     Forwards,
     Backwards,
     Reverse,
@@ -56,24 +108,140 @@ pub enum Instruction {
     Io(Source)
 }
 
-#[derive(Debug)]
+// boolean valued if conditions, comparisons are between integer valued sources
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expr {
     Backwards,
     Forwards,
     Or(Box<Expr>, Box<Expr>),
     And(Box<Expr>, Box<Expr>),
-    Gte(Box<Expr>, Box<Expr>),
-    Lte(Box<Expr>, Box<Expr>),
-    Gt(Box<Expr>, Box<Expr>),
-    Lt(Box<Expr>, Box<Expr>),
-    Eq(Box<Expr>, Box<Expr>),
-    Lit(Source)
+    Gte(Source, Source),
+    Lte(Source, Source),
+    Gt(Source, Source),
+    Lt(Source, Source),
+    Eq(Source, Source)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Line {
     pub label: Option<String>,
     pub inst: Instruction,
     pub cond: Option<Expr>,
-    pub stack: std::vec::Vec<usize>
+    pub stack: std::vec::Vec<usize>,
+    pub span: Span
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Register::A => write!(f, "A"),
+            Register::B => write!(f, "B"),
+            Register::C => write!(f, "C"),
+            Register::D => write!(f, "D")
+        }
+    }
+}
+
+impl fmt::Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Dest::Reg(reg) => write!(f, "{}", reg),
+            Dest::Addr(addr) => write!(f, "{}", addr),
+            Dest::Deref(dest) => write!(f, "*{}", dest)
+        }
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Source::Reg(reg) => write!(f, "{}", reg),
+            Source::Addr(addr) => write!(f, "{}", addr),
+            Source::Literal(val) => write!(f, "${}", val),
+            Source::Deref(src) => write!(f, "*{}", src)
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::Inc(dest, src) => write!(f, "inc {} {}", dest, src),
+            Instruction::Move(dest, src) => write!(f, "move {} {}", dest, src),
+            Instruction::Unmove(dest, src) => write!(f, "unmove {} {}", dest, src),
+            Instruction::Swap(left, right) => write!(f, "swap {} {}", left, right),
+            Instruction::Xor(dest, src) => write!(f, "xor {} {}", dest, src),
+            Instruction::Mul(dest, k) => write!(f, "mul {} ${}", dest, k),
+            Instruction::Rol(dest, src) => write!(f, "rol {} {}", dest, src),
+            Instruction::Ror(dest, src) => write!(f, "ror {} {}", dest, src),
+            Instruction::Neg(dest) => write!(f, "neg {}", dest),
+            Instruction::Jump(Some(label)) => write!(f, "jump {}", label),
+            Instruction::Jump(None) => write!(f, "jump"),
+            Instruction::From(Some(label)) => write!(f, "from {}", label),
+            Instruction::From(None) => write!(f, "from"),
+            Instruction::Call(label) => write!(f, "call {}", label),
+            Instruction::Return => write!(f, "return"),
+            Instruction::Forwards => write!(f, "forwards"),
+            Instruction::Backwards => write!(f, "backwards"),
+            Instruction::Reverse => write!(f, "reverse"),
+            Instruction::Halt => write!(f, "halt"),
+            Instruction::Io(src) => write!(f, "io {}", src)
+        }
+    }
+}
+
+impl Expr {
+    // how tightly the expression binds, as the parser sees it
+    pub fn precedence(&self) -> usize {
+        match self {
+            Expr::Or(..) => parse::precedence(&Token::Or),
+            Expr::And(..) => parse::precedence(&Token::And),
+            _ => parse::precedence(&Token::Eq)
+        }
+    }
+}
+
+// and/or are left associative, so only a right operand that binds as
+// loosely as its parent needs parentheses
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let binary = |f: &mut fmt::Formatter, left: &Expr, op: &str, right: &Expr| {
+            if left.precedence() < self.precedence() {
+                write!(f, "({})", left)?;
+            }
+            else {
+                write!(f, "{}", left)?;
+            }
+            if right.precedence() <= self.precedence() {
+                write!(f, " {} ({})", op, right)
+            }
+            else {
+                write!(f, " {} {}", op, right)
+            }
+        };
+        match self {
+            Expr::Backwards => write!(f, "backwards"),
+            Expr::Forwards => write!(f, "forwards"),
+            Expr::Or(left, right) => binary(f, left, "or", right),
+            Expr::And(left, right) => binary(f, left, "and", right),
+            Expr::Gte(left, right) => write!(f, "{} >= {}", left, right),
+            Expr::Lte(left, right) => write!(f, "{} <= {}", left, right),
+            Expr::Gt(left, right) => write!(f, "{} > {}", left, right),
+            Expr::Lt(left, right) => write!(f, "{} < {}", left, right),
+            Expr::Eq(left, right) => write!(f, "{} = {}", left, right)
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            write!(f, "{}: ", label)?;
+        }
+        write!(f, "{}", self.inst)?;
+        if let Some(cond) = &self.cond {
+            write!(f, " if {}", cond)?;
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,531 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::ast;
+use crate::backend::Settings;
+use crate::bytecode::{self, Op, Operand};
+use crate::eval::Place;
+use crate::memory::MemoryModel;
+use crate::program::Program;
+use crate::word::{Overflow, Word};
+
+// runtime shared by every program, MASK, BITS, CELLS and LINES are defined before it
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+
+#if defined(__GNUC__)
+#pragma GCC diagnostic ignored "-Wunused-label"
+#pragma GCC diagnostic ignored "-Wunused-variable"
+#endif
+
+typedef uint64_t word;
+
+static word a, b, c, d;
+static word mem[CELLS ? CELLS : 1];
+static word tmp;
+
+struct stack {
+    word *items;
+    size_t len, cap;
+};
+
+static struct stack stacks[LINES + 1];
+
+static inline void trap(size_t line, const char *msg) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n --> %s\n", msg, where[line]);
+    exit(1);
+}
+
+static inline word *cell(word addr, size_t line) {
+    char msg[64];
+    if (addr >= CELLS) {
+        snprintf(msg, sizeof msg, "address 0x%llx is out of bounds", (unsigned long long)addr);
+        trap(line, msg);
+    }
+    return &mem[addr];
+}
+
+static inline void append(struct stack *s, word v) {
+    if (s->len == s->cap) {
+        s->cap = s->cap ? s->cap * 2 : 8;
+        s->items = realloc(s->items, s->cap * sizeof *s->items);
+        if (!s->items) {
+            fputs("out of memory\n", stderr);
+            exit(1);
+        }
+    }
+    s->items[s->len++] = v;
+}
+
+static inline void push(size_t line, size_t from) {
+    append(&stacks[line], from);
+}
+
+/* the line that last jumped here, or the line itself if none did */
+static inline size_t pop(size_t line) {
+    struct stack *s = &stacks[line];
+    return s->len ? s->items[--s->len] : line;
+}
+
+VALUES
+CALLS
+static inline void input(word *p) {
+    int ch = getchar();
+    if (ch == EOF) {
+        *p = ferror(stdin) ? 255 : 0;
+    }
+    else {
+        *p = (word)ch;
+    }
+}
+
+/* rotations and negation never overflow */
+static inline void rotl(word *p, word n) {
+    word x = *p & MASK;
+    n %= BITS;
+    *p = n ? ((x << n) | (x >> (BITS - n))) & MASK : x;
+}
+
+static inline void rotr(word *p, word n) {
+    rotl(p, (BITS - n % BITS) % BITS);
+}
+
+static inline void neg(word *p) {
+    *p = (0 - *p) & MASK;
+}
+
+static inline void output(word v, size_t line) {
+    char msg[96];
+    if (v >= 0x80) {
+        snprintf(msg, sizeof msg, "cannot output 0x%llx, it is not a single byte UTF-8 character", (unsigned long long)v);
+        trap(line, msg);
+    }
+    if (putchar((int)v) == EOF) {
+        trap(line, "unable to write output");
+    }
+}
+"#;
+
+// value stacks for move and unmove, one for each register and memory cell
+const VALUES: &str = r#"static struct stack values[4 + CELLS];
+
+static inline struct stack *saved(word *p) {
+    if (p >= mem && p < mem + CELLS) {
+        return &values[4 + (p - mem)];
+    }
+    return &values[p == &a ? 0 : p == &b ? 1 : p == &c ? 2 : 3];
+}
+
+/* going the other way put back the last value saved, if there is one */
+static inline void restore(word *p) {
+    struct stack *s = saved(p);
+    if (s->len) {
+        *p = s->items[--s->len];
+    }
+}
+"#;
+
+// the stack call and return share, entry holds the line each call goes to
+const CALLS: &str = r#"static struct stack calls;
+
+/* the line before `line` going backwards, or if it's where the newest call
+   went, the line before that call */
+static inline size_t back(size_t line) {
+    if (calls.len && entry[calls.items[calls.len - 1]] == line) {
+        return calls.items[--calls.len] - 1;
+    }
+    return line - 1;
+}
+"#;
+
+fn overflow_fns(word: &Word) -> String {
+    let (add, sub) = match word.overflow() {
+        Overflow::Wrapping => ("s &= MASK;", "s = (x - y) & MASK;"),
+        Overflow::Trapping => ("trap(line, \"arithmetic overflow\");", "trap(line, \"arithmetic overflow\");"),
+        Overflow::Saturating => ("s = MASK;", "s = 0;")
+    };
+    // mul overflows past limit, MASK / k, and div undoes it the same way
+    // Word::div does
+    let (mul, div) = match word.overflow() {
+        Overflow::Wrapping => ("s = (x * k) & MASK;", "*p = (x * inv) & MASK;"),
+        Overflow::Trapping => ("trap(line, \"arithmetic overflow\");", "if (x % k) {\n        trap(line, \"arithmetic overflow\");\n    }\n    *p = x / k;"),
+        Overflow::Saturating => ("s = MASK;", "*p = x / k;")
+    };
+    format!("
+static inline void add(word *p, word v, size_t line) {{
+    word x = *p & MASK, y = v & MASK, s = x + y;
+    (void)line;
+    if (s < x || s > MASK) {{
+        {}
+    }}
+    *p = s;
+}}
+
+static inline void sub(word *p, word v, size_t line) {{
+    word x = *p & MASK, y = v & MASK, s = 0;
+    (void)line;
+    if (x >= y) {{
+        s = x - y;
+    }}
+    else {{
+        {}
+    }}
+    *p = s;
+}}
+
+static inline void mul(word *p, word k, word limit, size_t line) {{
+    word x = *p & MASK, s = x * k;
+    (void)line;
+    if (x > limit) {{
+        {}
+    }}
+    *p = s;
+}}
+
+static inline void divide(word *p, word k, word inv, size_t line) {{
+    word x = *p & MASK;
+    (void)k;
+    (void)inv;
+    (void)line;
+    {}
+}}
+", add, sub, mul, div)
+}
+
+fn num(val: usize) -> String {
+    format!("UINT64_C({:#x})", val)
+}
+
+fn reg(reg: ast::Register) -> &'static str {
+    match reg {
+        ast::Register::A => "a",
+        ast::Register::B => "b",
+        ast::Register::C => "c",
+        ast::Register::D => "d"
+    }
+}
+
+// a pointer to where an operand lives, None for a literal that isn't dereferenced
+fn place(op: &Operand, line: usize, word: &Word) -> Option<String> {
+    if op.depth == 0 {
+        return match op.base {
+            Place::Reg(r) => Some(format!("&{}", reg(r))),
+            Place::Mem(addr) => Some(format!("cell({}, {})", num(addr), line)),
+            Place::Literal(_) => None
+        };
+    }
+    let inner = Operand{depth: op.depth - 1, ..*op};
+    Some(format!("cell({}, {})", read(&inner, line, word), line))
+}
+
+fn read(op: &Operand, line: usize, word: &Word) -> String {
+    match (place(op, line, word), op.base) {
+        (Some(p), _) => format!("*{}", p),
+        (None, Place::Literal(val)) => num(word.truncate(val)),
+        (None, _) => unreachable!()
+    }
+}
+
+// swap, xor and neg are the same in both sections. both places are worked out
+// before either is written, like the interpreter
+fn self_inverse(out: &mut String, op: Op, line: usize, word: &Word) {
+    match op {
+        Op::Swap(left, right) => {
+            writeln!(out, "    {{
+        word *p = {}, *q = {};", place(&left, line, word).unwrap(), place(&right, line, word).unwrap()).unwrap();
+            writeln!(out, "        tmp = *p;
+        *p = *q;
+        *q = tmp;
+    }}").unwrap();
+        },
+        Op::Xor(dest, src) => {
+            writeln!(out, "    tmp = {};", read(&src, line, word)).unwrap();
+            writeln!(out, "    *{} ^= tmp;", place(&dest, line, word).unwrap()).unwrap();
+        },
+        Op::Neg(dest) => writeln!(out, "    neg({});", place(&dest, line, word).unwrap()).unwrap(),
+        _ => unreachable!()
+    }
+}
+
+// the direction a move or unmove saves in reads the source before finding
+// the destination, the other way only finds the destination
+fn moving(out: &mut String, dest: Operand, src: Operand, save: bool, line: usize, word: &Word) {
+    if save {
+        writeln!(out, "    tmp = {};", read(&src, line, word)).unwrap();
+        writeln!(out, "    {{
+        word *p = {};
+        append(saved(p), *p);
+        *p = tmp;
+    }}", place(&dest, line, word).unwrap()).unwrap();
+    }
+    else {
+        writeln!(out, "    restore({});", place(&dest, line, word).unwrap()).unwrap();
+    }
+}
+
+// direction is known in each section, so forwards and backwards are constants
+fn cond(expr: &ast::Expr, forward: bool, line: usize, word: &Word) -> String {
+    let cmp = |left: &ast::Source, op: &str, right: &ast::Source| {
+        let left = read(&Operand::source(left), line, word);
+        let right = read(&Operand::source(right), line, word);
+        // the comma makes sure the left side is read, and can trap, first
+        format!("(tmp = {}, tmp {} {})", left, op, right)
+    };
+    match expr {
+        ast::Expr::Forwards => (if forward { "1" } else { "0" }).to_string(),
+        ast::Expr::Backwards => (if forward { "0" } else { "1" }).to_string(),
+        ast::Expr::Or(left, right) => format!("({} || {})", cond(left, forward, line, word), cond(right, forward, line, word)),
+        ast::Expr::And(left, right) => format!("({} && {})", cond(left, forward, line, word), cond(right, forward, line, word)),
+        ast::Expr::Eq(left, right) => cmp(left, "==", right),
+        ast::Expr::Gt(left, right) => cmp(left, ">", right),
+        ast::Expr::Gte(left, right) => cmp(left, ">=", right),
+        ast::Expr::Lt(left, right) => cmp(left, "<", right),
+        ast::Expr::Lte(left, right) => cmp(left, "<=", right),
+    }
+}
+
+// labels for continuing at a line in either direction, running off the end
+// or back onto line 0 finishes the program
+struct Labels {
+    len: usize,
+    // lines some call goes to
+    entries: HashSet<usize>
+}
+
+impl Labels {
+    fn forward(&self, line: usize) -> String {
+        if line >= self.len {
+            "done".to_string()
+        }
+        else {
+            format!("f_{}", line)
+        }
+    }
+
+    fn backward(&self, line: usize) -> String {
+        if line == 0 || line >= self.len {
+            "done".to_string()
+        }
+        else {
+            format!("b_{}", line)
+        }
+    }
+
+    // going backwards off a line, which for a line calls go to might mean
+    // returning out of the call first
+    fn back(&self, line: usize) -> String {
+        if self.entries.contains(&line) {
+            format!("r_{}", line)
+        }
+        else {
+            self.backward(line.wrapping_sub(1))
+        }
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// emit a standalone C program with a forward and a backward section,
+// each line gets a label in both and changing direction jumps between them
+pub fn compile(program: &Program, settings: &Settings) -> Result<String, String> {
+    let cells = match settings.memory {
+        MemoryModel::Fixed(cells) => cells,
+        MemoryModel::Sparse => return Err("the c target does not support sparse memory".to_string())
+    };
+    let word = &settings.word;
+    let lines = program.lines();
+    let code = bytecode::compile(lines, program.labels());
+    let entries: HashSet<usize> = code.insns.iter().filter_map(|insn| match insn.op {
+        Op::Call(entry) => Some(entry),
+        _ => None
+    }).collect();
+    let labels = Labels{len: lines.len(), entries};
+    let mut out = String::new();
+
+    writeln!(out, "/* generated by moonwalk from {} */", settings.file).unwrap();
+    writeln!(out, "#define MASK UINT64_C({:#x})", word.max()).unwrap();
+    writeln!(out, "#define BITS {}", word.bits()).unwrap();
+    writeln!(out, "#define CELLS UINT64_C({})", cells).unwrap();
+    writeln!(out, "#define LINES {}", lines.len()).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "static const char *where[] = {{").unwrap();
+    for line in lines {
+        writeln!(out, "    \"{}:{}:{}\",", escape(&settings.file), line.span.line, line.span.column).unwrap();
+    }
+    writeln!(out, "    \"\"\n}};\n").unwrap();
+    let moves = code.insns.iter().any(|insn| matches!(insn.op, Op::Move(..) | Op::Unmove(..)));
+    let mut calls = String::new();
+    if code.insns.iter().any(|insn| matches!(insn.op, Op::Call(_) | Op::Return)) {
+        writeln!(calls, "static const size_t entry[] = {{").unwrap();
+        for insn in &code.insns {
+            match insn.op {
+                Op::Call(entry) => writeln!(calls, "    {},", entry).unwrap(),
+                _ => writeln!(calls, "    LINES,").unwrap()
+            }
+        }
+        writeln!(calls, "    LINES\n}};\n").unwrap();
+        calls.push_str(CALLS);
+    }
+    out.push_str(&PRELUDE
+        .replace("VALUES\n", if moves { VALUES } else { "" })
+        .replace("CALLS\n", &calls));
+    out.push_str(&overflow_fns(word));
+
+    writeln!(out, "\nint main(void) {{").unwrap();
+    writeln!(out, "    size_t pc;").unwrap();
+    writeln!(out, "    goto {};", labels.forward(0)).unwrap();
+
+    writeln!(out, "\n    /* forwards */").unwrap();
+    for (i, (line, insn)) in lines.iter().zip(&code.insns).enumerate() {
+        let next = labels.forward(i + 1);
+        writeln!(out, "f_{}:", i).unwrap();
+        if let Some(expr) = &line.cond {
+            writeln!(out, "    if (!{}) goto {};", cond(expr, true, i, word), next).unwrap();
+        }
+        match insn.op {
+            Op::Inc(dest, src) => {
+                writeln!(out, "    tmp = {};", read(&src, i, word)).unwrap();
+                writeln!(out, "    add({}, tmp, {});", place(&dest, i, word).unwrap(), i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Swap(..) | Op::Xor(..) | Op::Neg(_) => {
+                self_inverse(&mut out, insn.op, i, word);
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Mul(dest, k) => {
+                let k = word.truncate(k);
+                writeln!(out, "    mul({}, {}, {}, {});", place(&dest, i, word).unwrap(), num(k), num(word.max() / k), i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Rol(dest, src) | Op::Ror(dest, src) => {
+                let f = if matches!(insn.op, Op::Rol(..)) { "rotl" } else { "rotr" };
+                writeln!(out, "    tmp = {};", read(&src, i, word)).unwrap();
+                writeln!(out, "    {}({}, tmp);", f, place(&dest, i, word).unwrap()).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Jump(Some(target)) => {
+                writeln!(out, "    push({}, {});", target.line, i).unwrap();
+                let to = if target.bare { target.line + 1 } else { target.line };
+                writeln!(out, "    goto {};", labels.forward(to)).unwrap();
+            },
+            Op::Jump(None) => writeln!(out, "    pc = pop({}) + 1;\n    goto dispatch_f;", i).unwrap(),
+            Op::From(_) | Op::Forwards => writeln!(out, "    goto {};", next).unwrap(),
+            Op::Backwards | Op::Reverse => writeln!(out, "    goto {};", labels.back(i)).unwrap(),
+            Op::Halt => writeln!(out, "    goto halted;").unwrap(),
+            Op::Move(dest, src) | Op::Unmove(dest, src) => {
+                moving(&mut out, dest, src, matches!(insn.op, Op::Move(..)), i, word);
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Call(entry) => writeln!(out, "    append(&calls, {});\n    goto {};", i, labels.forward(entry)).unwrap(),
+            // back to the line after the newest call, which remembers the
+            // return for going back in
+            Op::Return => {
+                writeln!(out, "    if (calls.len) {{
+        pc = calls.items[--calls.len];
+        push(pc, {});
+        pc += 1;
+        goto dispatch_f;
+    }}", i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Io(src) => {
+                match place(&src, i, word) {
+                    Some(p) => writeln!(out, "    input({});", p).unwrap(),
+                    None => writeln!(out, "    trap({}, \"cannot place input into a literal\");", i).unwrap()
+                }
+                writeln!(out, "    goto {};", next).unwrap();
+            }
+        }
+    }
+
+    // line 0 is never run backwards, reaching it finishes the program
+    writeln!(out, "\n    /* backwards */").unwrap();
+    for (i, (line, insn)) in lines.iter().zip(&code.insns).enumerate().skip(1) {
+        let next = labels.back(i);
+        writeln!(out, "b_{}:", i).unwrap();
+        if let Some(expr) = &line.cond {
+            writeln!(out, "    if (!{}) goto {};", cond(expr, false, i, word), next).unwrap();
+        }
+        match insn.op {
+            Op::Inc(dest, src) => {
+                writeln!(out, "    tmp = {};", read(&src, i, word)).unwrap();
+                writeln!(out, "    sub({}, tmp, {});", place(&dest, i, word).unwrap(), i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Swap(..) | Op::Xor(..) | Op::Neg(_) => {
+                self_inverse(&mut out, insn.op, i, word);
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Mul(dest, k) => {
+                let k = word.truncate(k);
+                let inv = word.inverse(k).unwrap_or(0);
+                writeln!(out, "    divide({}, {}, {}, {});", place(&dest, i, word).unwrap(), num(k), num(inv), i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Rol(dest, src) | Op::Ror(dest, src) => {
+                let f = if matches!(insn.op, Op::Rol(..)) { "rotr" } else { "rotl" };
+                writeln!(out, "    tmp = {};", read(&src, i, word)).unwrap();
+                writeln!(out, "    {}({}, tmp);", f, place(&dest, i, word).unwrap()).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::From(Some(target)) => {
+                writeln!(out, "    push({}, {});", target.line, i).unwrap();
+                let to = if target.bare { target.line.wrapping_sub(1) } else { target.line };
+                writeln!(out, "    goto {};", labels.backward(to)).unwrap();
+            },
+            // nothing to pop leaves the line the way any other does
+            Op::From(None) if labels.entries.contains(&i) => {
+                writeln!(out, "    pc = pop({});\n    if (pc == {}) goto {};", i, i, next).unwrap();
+                writeln!(out, "    pc -= 1;\n    goto dispatch_b;").unwrap();
+            },
+            Op::From(None) => writeln!(out, "    pc = pop({}) - 1;\n    goto dispatch_b;", i).unwrap(),
+            Op::Jump(_) | Op::Backwards | Op::Return => writeln!(out, "    goto {};", next).unwrap(),
+            Op::Forwards | Op::Reverse => writeln!(out, "    goto {};", labels.forward(i + 1)).unwrap(),
+            Op::Halt => writeln!(out, "    goto halted;").unwrap(),
+            Op::Move(dest, src) | Op::Unmove(dest, src) => {
+                moving(&mut out, dest, src, matches!(insn.op, Op::Unmove(..)), i, word);
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            // back into the callee at the return it left by
+            Op::Call(_) => {
+                writeln!(out, "    if (stacks[{}].len) {{
+        pc = pop({});
+        append(&calls, {});
+        goto dispatch_b;
+    }}", i, i, i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            },
+            Op::Io(src) => {
+                writeln!(out, "    output({}, {});", read(&src, i, word), i).unwrap();
+                writeln!(out, "    goto {};", next).unwrap();
+            }
+        }
+    }
+
+    // going backwards off a line a call goes to
+    let mut entries: Vec<_> = labels.entries.iter().collect();
+    entries.sort();
+    for entry in entries {
+        writeln!(out, "r_{}:\n    pc = back({});\n    goto dispatch_b;", entry, entry).unwrap();
+    }
+
+    // bare jump and from return to a line only known at runtime
+    writeln!(out, "\ndispatch_f:\n    switch (pc) {{").unwrap();
+    for i in 0..lines.len() {
+        writeln!(out, "    case {}: goto f_{};", i, i).unwrap();
+    }
+    writeln!(out, "    default: goto done;\n    }}").unwrap();
+    writeln!(out, "dispatch_b:\n    switch (pc) {{").unwrap();
+    for i in 1..lines.len() {
+        writeln!(out, "    case {}: goto b_{};", i, i).unwrap();
+    }
+    writeln!(out, "    default: goto done;\n    }}").unwrap();
+
+    writeln!(out, "halted:\n    fflush(stdout);\n    puts(\"Program Halted\");\n    return 0;").unwrap();
+    writeln!(out, "done:\n    fflush(stdout);\n    return 0;\n}}").unwrap();
+    Ok(out)
+}
@@ -0,0 +1,71 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::memory::MemoryModel;
+use crate::program::Program;
+use crate::word::Word;
+
+pub mod c;
+pub mod wasm;
+pub mod x86_64;
+
+// what `moonwalk compile` produces, chosen with --target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    C,
+    // GNU assembler for linux, assemble with `as` and link with `ld`
+    X86_64Asm,
+    // webassembly text and binary modules, see wasm::compile for the interface
+    Wat,
+    Wasm
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s {
+            "c" => Ok(Target::C),
+            "x86_64-asm" => Ok(Target::X86_64Asm),
+            "wat" => Ok(Target::Wat),
+            "wasm" => Ok(Target::Wasm),
+            _ => Err(format!("unknown target {}, expected c, x86_64-asm, wat or wasm", s))
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Target::C => write!(f, "c"),
+            Target::X86_64Asm => write!(f, "x86_64-asm"),
+            Target::Wat => write!(f, "wat"),
+            Target::Wasm => write!(f, "wasm")
+        }
+    }
+}
+
+// the parts of the machine that get baked into compiled code
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub word: Word,
+    pub memory: MemoryModel,
+    // source file name, used when reporting runtime errors
+    pub file: String
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings{word: Word::default(), memory: MemoryModel::default(), file: "<source>".to_string()}
+    }
+}
+
+// generate code for a program, errors are settings the target can't support
+pub fn compile(program: &Program, target: Target, settings: &Settings) -> Result<Vec<u8>, String> {
+    match target {
+        Target::C => c::compile(program, settings).map(String::into_bytes),
+        Target::X86_64Asm => x86_64::compile(program, settings).map(String::into_bytes),
+        Target::Wat => wasm::compile(program, settings).map(String::into_bytes),
+        Target::Wasm => wasm::compile_binary(program, settings)
+    }
+}
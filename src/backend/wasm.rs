@@ -0,0 +1,436 @@
+use std::fmt::Write;
+
+use crate::ast;
+use crate::backend::Settings;
+use crate::bytecode::{self, Op, Operand};
+use crate::eval::Place;
+use crate::memory::MemoryModel;
+use crate::program::Program;
+use crate::word::{Overflow, Word};
+
+// values of the exported `error` global, set just before a runtime error traps
+pub const ERROR_OVERFLOW: i32 = 1;
+pub const ERROR_OUT_OF_BOUNDS: i32 = 2;
+pub const ERROR_WRITE_TO_LITERAL: i32 = 3;
+pub const ERROR_INVALID_UTF8: i32 = 4;
+
+// helpers shared by every module, the capitalized names are filled in
+const RUNTIME: &str = r#"
+  ;; record a runtime error for the host and stop
+  (func $fail (param $kind i32) (param $value i64)
+    (global.set $error (local.get $kind))
+    (global.set $error_value (local.get $value))
+    unreachable)
+
+  ;; byte offset of a memory cell
+  (func $cell (param $addr i64) (result i32)
+    (if (i64.ge_u (local.get $addr) (i64.const CELLS))
+      (then (call $fail (i32.const ERROR_OUT_OF_BOUNDS) (local.get $addr))))
+    (i32.wrap_i64 (i64.shl (local.get $addr) (i64.const 3))))
+
+  ;; stacks are linked lists of 16 byte nodes {next, value} with the top
+  ;; node's address at $head, popped nodes are reused. HEADS holds the heads
+  ;; of each line's jump stack and VALUES those of the value stacks
+  (func $link (param $head i32) (param $value i64)
+    (local $node i32)
+    (if (global.get $free)
+      (then
+        (local.set $node (global.get $free))
+        (global.set $free (i32.load (local.get $node))))
+      (else
+        (local.set $node (global.get $heap))
+        (global.set $heap (i32.add (global.get $heap) (i32.const 16)))
+        (if (i32.gt_u (global.get $heap) (i32.shl (memory.size) (i32.const 16)))
+          (then
+            (if (i32.eq (memory.grow (i32.const 1)) (i32.const -1))
+              (then unreachable))))))
+    (i32.store (local.get $node) (i32.load (local.get $head)))
+    (i64.store offset=8 (local.get $node) (local.get $value))
+    (i32.store (local.get $head) (local.get $node)))
+
+  ;; the top value of the stack at $head, or $empty if there's none
+  (func $unlink (param $head i32) (param $empty i64) (result i64)
+    (local $node i32)
+    (local.set $node (i32.load (local.get $head)))
+    (if (i32.eqz (local.get $node))
+      (then (return (local.get $empty))))
+    (i32.store (local.get $head) (i32.load (local.get $node)))
+    (i32.store (local.get $node) (global.get $free))
+    (global.set $free (local.get $node))
+    (i64.load offset=8 (local.get $node)))
+
+  (func $push (param $line i32) (param $from i32)
+    (call $link (i32.add (i32.const HEADS) (i32.shl (local.get $line) (i32.const 2))) (i64.extend_i32_u (local.get $from))))
+
+  ;; the line that last jumped to $line, or $line itself if none did
+  (func $pop (param $line i32) (result i32)
+    (i32.wrap_i64 (call $unlink (i32.add (i32.const HEADS) (i32.shl (local.get $line) (i32.const 2))) (i64.extend_i32_u (local.get $line)))))
+"#;
+
+// only in modules with calls, the newest caller is on top of the stack at
+// CALLS and ENTRIES holds the line each line calls
+const CALLS: &str = r#"
+  ;; the line before $line going backwards, or if $line is where the newest
+  ;; call went, the line before that call
+  (func $back (param $line i32) (result i32)
+    (local $node i32)
+    (local $caller i32)
+    (local.set $node (i32.load (i32.const CALLS)))
+    (if (local.get $node)
+      (then
+        (local.set $caller (i32.wrap_i64 (i64.load offset=8 (local.get $node))))
+        (if (i32.eq (i32.load (i32.add (i32.const ENTRIES) (i32.shl (local.get $caller) (i32.const 2)))) (local.get $line))
+          (then
+            (drop (call $unlink (i32.const CALLS) (i64.const 0)))
+            (return (i32.sub (local.get $caller) (i32.const 1)))))))
+    (i32.sub (local.get $line) (i32.const 1)))
+"#;
+
+fn overflow_fns(word: &Word) -> String {
+    let (add, sub) = match word.overflow() {
+        Overflow::Wrapping => ("(i64.and (local.get $s) (i64.const MASK))", "(i64.and (i64.sub (local.get $x) (local.get $y)) (i64.const MASK))"),
+        Overflow::Trapping => ("(call $fail (i32.const ERROR_OVERFLOW) (i64.const 0)) (i64.const 0)", "(call $fail (i32.const ERROR_OVERFLOW) (i64.const 0)) (i64.const 0)"),
+        Overflow::Saturating => ("(i64.const MASK)", "(i64.const 0)")
+    };
+    let (mul, div) = match word.overflow() {
+        Overflow::Wrapping => ("(i64.and (i64.mul (local.get $x) (local.get $k)) (i64.const MASK))", "(i64.and (i64.mul (local.get $x) (local.get $inv)) (i64.const MASK))"),
+        Overflow::Trapping => ("(call $fail (i32.const ERROR_OVERFLOW) (i64.const 0)) (i64.const 0)",
+            "(if (i64.ne (i64.rem_u (local.get $x) (local.get $k)) (i64.const 0)) (then (call $fail (i32.const ERROR_OVERFLOW) (i64.const 0))))\n    (i64.div_u (local.get $x) (local.get $k))"),
+        Overflow::Saturating => ("(i64.const MASK)", "(i64.div_u (local.get $x) (local.get $k))")
+    };
+    format!(r#"
+  (func $add (param $x i64) (param $y i64) (result i64)
+    (local $s i64)
+    (local.set $s (i64.add (local.get $x) (local.get $y)))
+    (if (result i64) (i32.or (i64.lt_u (local.get $s) (local.get $x)) (i64.gt_u (local.get $s) (i64.const MASK)))
+      (then {})
+      (else (local.get $s))))
+
+  (func $sub (param $x i64) (param $y i64) (result i64)
+    (if (result i64) (i64.ge_u (local.get $x) (local.get $y))
+      (then (i64.sub (local.get $x) (local.get $y)))
+      (else {})))
+
+  ;; mul overflows past limit, MASK / k, and div undoes it like Word::div
+  (func $mul (param $x i64) (param $k i64) (param $limit i64) (result i64)
+    (if (result i64) (i64.gt_u (local.get $x) (local.get $limit))
+      (then {})
+      (else (i64.mul (local.get $x) (local.get $k)))))
+
+  (func $div (param $x i64) (param $k i64) (param $inv i64) (result i64)
+    {})
+
+  ;; rotations wrap the count at the word's width
+  (func $rotl (param $x i64) (param $n i64) (result i64)
+    (local.set $n (i64.rem_u (local.get $n) (i64.const BITS)))
+    (if (result i64) (i64.eqz (local.get $n))
+      (then (local.get $x))
+      (else (i64.and (i64.or (i64.shl (local.get $x) (local.get $n)) (i64.shr_u (local.get $x) (i64.sub (i64.const BITS) (local.get $n)))) (i64.const MASK)))))
+
+  (func $rotr (param $x i64) (param $n i64) (result i64)
+    (call $rotl (local.get $x) (i64.sub (i64.const BITS) (i64.rem_u (local.get $n) (i64.const BITS)))))
+"#, add, sub, mul, div)
+}
+
+fn reg(reg: ast::Register) -> &'static str {
+    match reg {
+        ast::Register::A => "$a",
+        ast::Register::B => "$b",
+        ast::Register::C => "$c",
+        ast::Register::D => "$d"
+    }
+}
+
+// byte offset of a memory operand
+fn pointer(op: &Operand, word: &Word) -> String {
+    if op.depth == 0 {
+        return match op.base {
+            Place::Mem(addr) => format!("(call $cell (i64.const {}))", addr),
+            _ => unreachable!()
+        };
+    }
+    format!("(call $cell {})", read(&Operand{depth: op.depth - 1, ..*op}, word))
+}
+
+fn read(op: &Operand, word: &Word) -> String {
+    match (op.depth, op.base) {
+        (0, Place::Reg(r)) => format!("(global.get {})", reg(r)),
+        (0, Place::Literal(val)) => format!("(i64.const {})", word.truncate(val)),
+        _ => format!("(i64.load {})", pointer(op, word))
+    }
+}
+
+// how to get and set a destination, memory ones keep their address in the
+// local ptr so it's only worked out once
+fn access(out: &mut String, op: &Operand, ptr: &str, word: &Word) -> (String, String) {
+    match (op.depth, op.base) {
+        (0, Place::Reg(r)) => (format!("(global.get {})", reg(r)), format!("global.set {}", reg(r))),
+        _ => {
+            writeln!(out, "        (local.set {} {})", ptr, pointer(op, word)).unwrap();
+            (format!("(i64.load (local.get {}))", ptr), format!("i64.store (local.get {})", ptr))
+        }
+    }
+}
+
+// and/or short circuit with ifs, like the interpreter
+fn cond(expr: &ast::Expr, word: &Word) -> String {
+    let cmp = |left: &ast::Source, op: &str, right: &ast::Source| {
+        format!("({} {} {})", op, read(&Operand::source(left), word), read(&Operand::source(right), word))
+    };
+    match expr {
+        ast::Expr::Forwards => "(global.get $forward)".to_string(),
+        ast::Expr::Backwards => "(i32.eqz (global.get $forward))".to_string(),
+        ast::Expr::Or(left, right) => format!("(if (result i32) {} (then (i32.const 1)) (else {}))", cond(left, word), cond(right, word)),
+        ast::Expr::And(left, right) => format!("(if (result i32) {} (then {}) (else (i32.const 0)))", cond(left, word), cond(right, word)),
+        ast::Expr::Eq(left, right) => cmp(left, "i64.eq", right),
+        ast::Expr::Gt(left, right) => cmp(left, "i64.gt_u", right),
+        ast::Expr::Gte(left, right) => cmp(left, "i64.ge_u", right),
+        ast::Expr::Lt(left, right) => cmp(left, "i64.lt_u", right),
+        ast::Expr::Lte(left, right) => cmp(left, "i64.le_u", right),
+    }
+}
+
+// the pc after jumping to a target, past it if it's a bare jump or from
+fn land(target: bytecode::Target, forward: bool) -> String {
+    match (target.bare, forward) {
+        (false, _) => format!("(i32.const {})", target.line),
+        (true, true) => format!("(i32.const {})", target.line + 1),
+        (true, false) => format!("(i32.const {})", target.line as i32 - 1)
+    }
+}
+
+// move saves dest's old value on its stack and writes the source, which is
+// read first, restoring pops it back if there is one. a cell's stack head
+// is found from its byte offset, register ones come before the cells'
+fn moving(out: &mut String, dest: &Operand, src: &Operand, save: bool, values: usize, word: &Word) {
+    if save {
+        writeln!(out, "        (local.set $v {})", read(src, word)).unwrap();
+    }
+    let (get, set) = access(out, dest, "$p", word);
+    let head = match (dest.depth, dest.base) {
+        (0, Place::Reg(r)) => format!("(i32.const {})", values + 4 * r as usize),
+        _ => format!("(i32.add (i32.const {}) (i32.shr_u (local.get $p) (i32.const 1)))", values + 16)
+    };
+    if save {
+        writeln!(out, "        (call $link {} {})", head, get).unwrap();
+        writeln!(out, "        ({} (local.get $v))", set).unwrap();
+    }
+    else {
+        writeln!(out, "        ({} (call $unlink {} {}))", set, head, get).unwrap();
+    }
+}
+
+// where the stacks that aren't per line keep their heads
+struct Heads {
+    calls: usize,
+    values: usize
+}
+
+fn line(out: &mut String, i: usize, line: &ast::Line, op: &Op, heads: &Heads, word: &Word) {
+    let w = |out: &mut String, s: &str| writeln!(out, "        {}", s).unwrap();
+    if let Some(expr) = &line.cond {
+        w(out, &format!("(br_if $next (i32.eqz {}))", cond(expr, word)));
+    }
+    match op {
+        Op::Inc(dest, src) => {
+            w(out, &format!("(local.set $v {})", read(src, word)));
+            let (get, set) = access(out, dest, "$p", word);
+            w(out, &format!("({} (if (result i64) (global.get $forward) (then (call $add {} (local.get $v))) (else (call $sub {} (local.get $v)))))", set, get, get));
+        },
+        // both places are worked out before either is written
+        Op::Swap(left, right) => {
+            let (lget, lset) = access(out, left, "$p", word);
+            let (rget, rset) = access(out, right, "$q", word);
+            w(out, &format!("(local.set $v {})", lget));
+            w(out, &format!("({} {})", lset, rget));
+            w(out, &format!("({} (local.get $v))", rset));
+        },
+        Op::Xor(dest, src) => {
+            w(out, &format!("(local.set $v {})", read(src, word)));
+            let (get, set) = access(out, dest, "$p", word);
+            w(out, &format!("({} (i64.xor {} (local.get $v)))", set, get));
+        },
+        Op::Mul(dest, k) => {
+            let k = word.truncate(*k);
+            let (get, set) = access(out, dest, "$p", word);
+            let inv = word.inverse(k).unwrap_or(0);
+            w(out, &format!("({} (if (result i64) (global.get $forward) (then (call $mul {} (i64.const {}) (i64.const {}))) (else (call $div {} (i64.const {}) (i64.const {})))))",
+                set, get, k, word.max() / k, get, k, inv));
+        },
+        Op::Rol(dest, src) | Op::Ror(dest, src) => {
+            let (left, right) = if matches!(op, Op::Rol(..)) { ("$rotl", "$rotr") } else { ("$rotr", "$rotl") };
+            w(out, &format!("(local.set $v {})", read(src, word)));
+            let (get, set) = access(out, dest, "$p", word);
+            w(out, &format!("({} (if (result i64) (global.get $forward) (then (call {} {} (local.get $v))) (else (call {} {} (local.get $v)))))", set, left, get, right, get));
+        },
+        Op::Neg(dest) => {
+            let (get, set) = access(out, dest, "$p", word);
+            w(out, &format!("({} (i64.and (i64.sub (i64.const 0) {}) (i64.const {})))", set, get, word.max()));
+        },
+        Op::Jump(Some(target)) => {
+            w(out, "(br_if $next (i32.eqz (global.get $forward)))");
+            w(out, &format!("(call $push (i32.const {}) (i32.const {}))", target.line, i));
+            w(out, &format!("(global.set $pc {})", land(*target, true)));
+            return w(out, "(br $step)");
+        },
+        Op::From(Some(target)) => {
+            w(out, "(br_if $next (global.get $forward))");
+            w(out, &format!("(call $push (i32.const {}) (i32.const {}))", target.line, i));
+            w(out, &format!("(global.set $pc {})", land(*target, false)));
+            return w(out, "(br $step)");
+        },
+        Op::Jump(None) => w(out, &format!("(if (global.get $forward) (then (global.set $pc (call $pop (i32.const {})))))", i)),
+        Op::From(None) => w(out, &format!("(if (i32.eqz (global.get $forward)) (then (global.set $pc (call $pop (i32.const {})))))", i)),
+        Op::Forwards => w(out, "(global.set $forward (i32.const 1))"),
+        Op::Backwards => w(out, "(global.set $forward (i32.const 0))"),
+        Op::Reverse => w(out, "(global.set $forward (i32.eqz (global.get $forward)))"),
+        Op::Halt => w(out, "(return (i32.const 1))"),
+        Op::Move(dest, src) | Op::Unmove(dest, src) => {
+            // a move saves going forwards and an unmove going backwards
+            let save = if matches!(op, Op::Move(..)) { "(global.get $forward)" } else { "(i32.eqz (global.get $forward))" };
+            w(out, &format!("(if {} (then", save));
+            moving(out, dest, src, true, heads.values, word);
+            w(out, ") (else");
+            moving(out, dest, src, false, heads.values, word);
+            w(out, "))");
+        },
+        // going backwards go back into the callee at the return it left by,
+        // a call line is never a return so popping its own line means empty
+        Op::Call(entry) => {
+            w(out, &format!("(if (global.get $forward) (then (local.set $r (i32.const {})) (call $link (i32.const {}) (i64.const {}))) (else", entry, heads.calls, i));
+            w(out, &format!("  (local.set $r (call $pop (i32.const {})))", i));
+            w(out, &format!("  (br_if $next (i32.eq (local.get $r) (i32.const {})))", i));
+            w(out, &format!("  (call $link (i32.const {}) (i64.const {}))))", heads.calls, i));
+            w(out, "(global.set $pc (local.get $r))");
+            return w(out, "(br $step)");
+        },
+        // back to the line after the newest call, which remembers the return
+        // for going back in
+        Op::Return => {
+            w(out, "(br_if $next (i32.eqz (global.get $forward)))");
+            w(out, &format!("(local.set $v (call $unlink (i32.const {}) (i64.const -1)))", heads.calls));
+            w(out, "(br_if $next (i64.eq (local.get $v) (i64.const -1)))");
+            w(out, &format!("(call $push (i32.wrap_i64 (local.get $v)) (i32.const {}))", i));
+            w(out, "(global.set $pc (i32.add (i32.wrap_i64 (local.get $v)) (i32.const 1)))");
+            return w(out, "(br $step)");
+        },
+        Op::Io(src) => {
+            let input = match (src.depth, src.base) {
+                (0, Place::Literal(_)) => format!("(call $fail (i32.const {}) (i64.const 0))", ERROR_WRITE_TO_LITERAL),
+                (0, Place::Reg(r)) => format!("(global.set {} (i64.extend_i32_u (call $getc)))", reg(r)),
+                // where the input goes is checked before reading it
+                _ => format!("(local.set $p {}) (i64.store (local.get $p) (i64.extend_i32_u (call $getc)))", pointer(src, word))
+            };
+            w(out, &format!("(if (global.get $forward) (then {}) (else", input));
+            w(out, &format!("  (local.set $v {})", read(src, word)));
+            w(out, &format!("  (if (i64.ge_u (local.get $v) (i64.const 0x80)) (then (call $fail (i32.const {}) (local.get $v))))", ERROR_INVALID_UTF8));
+            w(out, "  (call $putc (i32.wrap_i64 (local.get $v)))))");
+        }
+    }
+    w(out, "(br $next)");
+}
+
+// emit a module in the text format. it imports getc and putc from
+// "moonwalk" and exports memory, the registers, the direction, the pc and a
+// `run` function returning 0 when the program finishes and 1 when it halts.
+// runtime errors set the `error` and `error_value` globals and trap
+pub fn compile(program: &Program, settings: &Settings) -> Result<String, String> {
+    let cells = match settings.memory {
+        MemoryModel::Fixed(cells) if cells <= 1 << 28 => cells,
+        MemoryModel::Fixed(_) => return Err("the wasm target supports at most 268435456 memory cells".to_string()),
+        MemoryModel::Sparse => return Err("the wasm target does not support sparse memory".to_string())
+    };
+    let word = &settings.word;
+    let lines = program.lines();
+    let code = bytecode::compile(lines, program.labels());
+    let heads = cells * 8;
+    // the call stack's head follows the jump stack ones, then with calls
+    // the entry table, then the value stack heads for the registers and each
+    // cell if something moves
+    let calls = code.insns.iter().any(|insn| matches!(insn.op, Op::Call(_) | Op::Return));
+    let entries = heads + lines.len() * 4 + 4;
+    let values = entries + if calls { lines.len() * 4 } else { 0 };
+    let moves = code.insns.iter().any(|insn| matches!(insn.op, Op::Move(..) | Op::Unmove(..)));
+    // nodes start after the stack heads, never at 0 which marks an empty stack
+    let heap = (values + if moves { (4 + cells) * 4 } else { 0 }).next_multiple_of(8).max(8);
+    let pages = heap.div_ceil(0x10000);
+
+    let mut out = String::new();
+    writeln!(out, ";; generated by moonwalk from {}", settings.file).unwrap();
+    writeln!(out, "(module").unwrap();
+    writeln!(out, "  (import \"moonwalk\" \"getc\" (func $getc (result i32)))").unwrap();
+    writeln!(out, "  (import \"moonwalk\" \"putc\" (func $putc (param i32)))").unwrap();
+    writeln!(out, "  (memory (export \"memory\") {})", pages.max(1)).unwrap();
+    for r in &["a", "b", "c", "d"] {
+        writeln!(out, "  (global ${} (export \"{}\") (mut i64) (i64.const 0))", r, r).unwrap();
+    }
+    writeln!(out, "  (global $forward (export \"forward\") (mut i32) (i32.const 1))").unwrap();
+    writeln!(out, "  (global $pc (export \"pc\") (mut i32) (i32.const 0))").unwrap();
+    writeln!(out, "  (global $error (export \"error\") (mut i32) (i32.const 0))").unwrap();
+    writeln!(out, "  (global $error_value (export \"error_value\") (mut i64) (i64.const 0))").unwrap();
+    writeln!(out, "  (global $heap (mut i32) (i32.const {}))", heap).unwrap();
+    writeln!(out, "  (global $free (mut i32) (i32.const 0))").unwrap();
+    out.push_str(&RUNTIME
+        .replace("CELLS", &cells.to_string())
+        .replace("HEADS", &heads.to_string())
+        .replace("ERROR_OUT_OF_BOUNDS", &ERROR_OUT_OF_BOUNDS.to_string()));
+    if calls {
+        out.push_str(&CALLS
+            .replace("CALLS", &(entries - 4).to_string())
+            .replace("ENTRIES", &entries.to_string()));
+        // the line each call goes to, the number of lines for any other line
+        let table: String = code.insns.iter().map(|insn| match insn.op {
+            Op::Call(entry) => entry,
+            _ => lines.len()
+        }).flat_map(|entry| (entry as u32).to_le_bytes()).map(|byte| format!("\\{:02x}", byte)).collect();
+        writeln!(out, "  (data (i32.const {}) \"{}\")", entries, table).unwrap();
+    }
+    out.push_str(&overflow_fns(word)
+        .replace("MASK", &word.max().to_string())
+        .replace("BITS", &word.bits().to_string())
+        .replace("ERROR_OVERFLOW", &ERROR_OVERFLOW.to_string()));
+
+    writeln!(out, "\n  (func (export \"run\") (result i32)").unwrap();
+    if lines.is_empty() {
+        writeln!(out, "    (i32.const 0))\n)").unwrap();
+        return Ok(out);
+    }
+    writeln!(out, "    (local $v i64)\n    (local $p i32)\n    (local $q i32)\n    (local $r i32)\n    (local $line i32)").unwrap();
+    writeln!(out, "    (loop $step").unwrap();
+    // running off the end, or back onto line 0, finishes the program
+    writeln!(out, "      (if (i32.or (i32.ge_u (global.get $pc) (i32.const {})) (i32.and (i32.eqz (global.get $pc)) (i32.eqz (global.get $forward))))", lines.len()).unwrap();
+    writeln!(out, "        (then (return (i32.const 0))))").unwrap();
+    writeln!(out, "      (local.set $line (global.get $pc))").unwrap();
+    writeln!(out, "      (block $next").unwrap();
+    for i in (0..lines.len()).rev() {
+        writeln!(out, "      (block $l{}", i).unwrap();
+    }
+    let table: Vec<String> = (0..lines.len()).map(|i| format!("$l{}", i)).collect();
+    writeln!(out, "        (br_table {} $next (global.get $pc)))", table.join(" ")).unwrap();
+    for (i, (l, insn)) in lines.iter().zip(&code.insns).enumerate() {
+        writeln!(out, "        ;; line {}", l.span.line).unwrap();
+        line(&mut out, i, l, &insn.op, &Heads{calls: entries - 4, values}, word);
+        if i + 1 < lines.len() {
+            writeln!(out, "      )").unwrap();
+        }
+    }
+    writeln!(out, "      )").unwrap();
+    // everything that didn't jump moves one line in the current direction,
+    // and going backwards off the line it started on might return out of a call
+    if calls {
+        writeln!(out, "      (global.set $pc (if (result i32) (i32.or (global.get $forward) (i32.ne (global.get $pc) (local.get $line)))").unwrap();
+        writeln!(out, "        (then (i32.add (global.get $pc) (select (i32.const 1) (i32.const -1) (global.get $forward))))").unwrap();
+        writeln!(out, "        (else (call $back (global.get $pc)))))").unwrap();
+    }
+    else {
+        writeln!(out, "      (global.set $pc (i32.add (global.get $pc) (select (i32.const 1) (i32.const -1) (global.get $forward))))").unwrap();
+    }
+    writeln!(out, "      (br $step))\n    unreachable)\n)").unwrap();
+    Ok(out)
+}
+
+// the binary format, assembled from the text
+pub fn compile_binary(program: &Program, settings: &Settings) -> Result<Vec<u8>, String> {
+    let text = compile(program, settings)?;
+    wat::parse_str(&text).map_err(|e| format!("generated invalid wasm: {}", e))
+}
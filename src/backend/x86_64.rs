@@ -0,0 +1,970 @@
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::ast;
+use crate::backend::Settings;
+use crate::bytecode::{self, Op, Operand};
+use crate::eval::Place;
+use crate::memory::MemoryModel;
+use crate::program::Program;
+use crate::word::{Overflow, Word};
+
+// runtime routines, linked into every program. everything is done with raw
+// linux syscalls so the output only needs `as` and `ld`
+const RUNTIME: &str = r#"
+# write %rdx bytes at %rsi to fd %rdi, %rax is 0 on success
+mw_write:
+1:  test %rdx, %rdx
+    jz 2f
+    mov $1, %eax
+    syscall
+    cmp $0, %rax
+    jle 3f
+    add %rax, %rsi
+    sub %rax, %rdx
+    jmp 1b
+2:  xor %eax, %eax
+    ret
+3:  mov $-1, %rax
+    ret
+
+mw_err:
+    mov $2, %edi
+    jmp mw_write
+
+# write out buffered output, %rax is 0 on success
+mw_flush:
+    xor %eax, %eax
+    mov mw_outlen(%rip), %rdx
+    test %rdx, %rdx
+    jz 1f
+    movq $0, mw_outlen(%rip)
+    mov $1, %edi
+    lea mw_outbuf(%rip), %rsi
+    call mw_write
+1:  ret
+
+# io going backwards, %rdi is the value and %rsi the line
+mw_putc:
+    cmp $0x80, %rdi
+    jae mw_notascii
+    mov mw_outlen(%rip), %rax
+    lea mw_outbuf(%rip), %rcx
+    mov %dil, (%rcx,%rax)
+    inc %rax
+    mov %rax, mw_outlen(%rip)
+    cmp $4096, %rax
+    jb 1f
+    push %rsi
+    call mw_flush
+    pop %rsi
+    test %rax, %rax
+    jnz mw_outfail
+1:  ret
+
+# io going forwards, the byte read into %rax, 0 at the end of input
+mw_getc:
+    sub $8, %rsp
+    xor %eax, %eax
+    xor %edi, %edi
+    mov %rsp, %rsi
+    mov $1, %edx
+    syscall
+    cmp $0, %rax
+    jl 2f
+    je 1f
+    movzbq (%rsp), %rax
+    add $8, %rsp
+    ret
+1:  add $8, %rsp
+    ret
+2:  mov $255, %eax
+    add $8, %rsp
+    ret
+
+# push %rsi onto the stack at %rdi, jump and value stacks are {items, len, cap}
+mw_push:
+    mov 8(%rdi), %rax
+    cmp 16(%rdi), %rax
+    jb 3f
+    push %rsi
+    push %rdi
+    mov 16(%rdi), %rsi
+    test %rsi, %rsi
+    jnz 1f
+    mov $9, %eax
+    xor %edi, %edi
+    mov $4096, %esi
+    mov $3, %edx
+    mov $0x22, %r10d
+    mov $-1, %r8
+    xor %r9d, %r9d
+    syscall
+    jmp 2f
+1:  mov (%rdi), %rdi
+    shl $3, %rsi
+    lea (%rsi,%rsi), %rdx
+    mov $25, %eax
+    mov $1, %r10d
+    syscall
+2:  pop %rdi
+    pop %rsi
+    cmp $-4096, %rax
+    ja mw_nomem
+    mov %rax, (%rdi)
+    mov 16(%rdi), %rcx
+    shl $1, %rcx
+    jnz 4f
+    mov $512, %ecx
+4:  mov %rcx, 16(%rdi)
+    mov 8(%rdi), %rax
+3:  mov (%rdi), %rcx
+    mov %rsi, (%rcx,%rax,8)
+    inc %rax
+    mov %rax, 8(%rdi)
+    ret
+
+# pop the stack at %rdi into %rax, %rsi if it is empty
+mw_pop:
+    mov 8(%rdi), %rax
+    test %rax, %rax
+    jz 1f
+    dec %rax
+    mov %rax, 8(%rdi)
+    mov (%rdi), %rcx
+    mov (%rcx,%rax,8), %rax
+    ret
+1:  mov %rsi, %rax
+    ret
+
+# write %rdi to stderr in hex
+mw_hex:
+    sub $32, %rsp
+    lea 32(%rsp), %rsi
+    mov %rdi, %rax
+    lea mw_digits(%rip), %rdx
+1:  dec %rsi
+    mov %rax, %rcx
+    and $15, %ecx
+    movb (%rdx,%rcx), %cl
+    movb %cl, (%rsi)
+    shr $4, %rax
+    jnz 1b
+    dec %rsi
+    movb $'x', (%rsi)
+    dec %rsi
+    movb $'0', (%rsi)
+    lea 32(%rsp), %rdx
+    sub %rsi, %rdx
+    call mw_err
+    add $32, %rsp
+    ret
+
+# finish a runtime error with the location of line %rsi and exit
+mw_fail:
+    push %rsi
+    lea mw_at(%rip), %rsi
+    mov $mw_at_len, %edx
+    call mw_err
+    pop %rax
+    lea mw_locs(%rip), %rcx
+    movslq (%rcx,%rax,8), %rsi
+    add %rcx, %rsi
+    movl 4(%rcx,%rax,8), %edx
+    call mw_err
+    mov $60, %eax
+    mov $1, %edi
+    syscall
+
+# report message %rdi of length %rdx at line %rsi
+mw_report:
+    push %rsi
+    push %rdx
+    push %rdi
+    call mw_flush
+    pop %rsi
+    pop %rdx
+    call mw_err
+    pop %rsi
+    jmp mw_fail
+
+mw_overflow:
+    lea mw_overflow_msg(%rip), %rdi
+    mov $mw_overflow_msg_len, %edx
+    jmp mw_report
+
+mw_literal:
+    lea mw_literal_msg(%rip), %rdi
+    mov $mw_literal_msg_len, %edx
+    jmp mw_report
+
+mw_outfail:
+    lea mw_outfail_msg(%rip), %rdi
+    mov $mw_outfail_msg_len, %edx
+    jmp mw_report
+
+# address %rdi at line %rsi is out of bounds
+mw_oob:
+    push %rsi
+    push %rdi
+    call mw_flush
+    lea mw_oob_msg(%rip), %rsi
+    mov $mw_oob_msg_len, %edx
+    call mw_err
+    pop %rdi
+    call mw_hex
+    lea mw_oob_end(%rip), %rsi
+    mov $mw_oob_end_len, %edx
+    call mw_err
+    pop %rsi
+    jmp mw_fail
+
+# value %rdi at line %rsi can't be output
+mw_notascii:
+    push %rsi
+    push %rdi
+    call mw_flush
+    lea mw_notascii_msg(%rip), %rsi
+    mov $mw_notascii_msg_len, %edx
+    call mw_err
+    pop %rdi
+    call mw_hex
+    lea mw_notascii_end(%rip), %rsi
+    mov $mw_notascii_end_len, %edx
+    call mw_err
+    pop %rsi
+    jmp mw_fail
+
+mw_nomem:
+    lea mw_nomem_msg(%rip), %rsi
+    mov $mw_nomem_msg_len, %edx
+    call mw_err
+    mov $60, %eax
+    mov $1, %edi
+    syscall
+
+mw_finish:
+    call mw_flush
+    test %rax, %rax
+    jnz 1f
+    mov $60, %eax
+    xor %edi, %edi
+    syscall
+1:  mov $LINES, %esi
+    jmp mw_outfail
+
+mw_halt:
+    call mw_flush
+    test %rax, %rax
+    jnz 1f
+    mov $1, %edi
+    lea mw_halted(%rip), %rsi
+    mov $mw_halted_len, %edx
+    call mw_write
+    test %rax, %rax
+    jnz 1f
+    mov $60, %eax
+    xor %edi, %edi
+    syscall
+1:  mov $LINES, %esi
+    jmp mw_outfail
+
+    .section .rodata
+mw_digits:
+    .ascii "0123456789abcdef"
+mw_at:
+    .ascii "\n --> "
+    .set mw_at_len, . - mw_at
+mw_halted:
+    .ascii "Program Halted\n"
+    .set mw_halted_len, . - mw_halted
+mw_overflow_msg:
+    .ascii "error: arithmetic overflow"
+    .set mw_overflow_msg_len, . - mw_overflow_msg
+mw_literal_msg:
+    .ascii "error: cannot place input into a literal"
+    .set mw_literal_msg_len, . - mw_literal_msg
+mw_outfail_msg:
+    .ascii "error: unable to write output"
+    .set mw_outfail_msg_len, . - mw_outfail_msg
+mw_oob_msg:
+    .ascii "error: address "
+    .set mw_oob_msg_len, . - mw_oob_msg
+mw_oob_end:
+    .ascii " is out of bounds"
+    .set mw_oob_end_len, . - mw_oob_end
+mw_notascii_msg:
+    .ascii "error: cannot output "
+    .set mw_notascii_msg_len, . - mw_notascii_msg
+mw_notascii_end:
+    .ascii ", it is not a single byte UTF-8 character"
+    .set mw_notascii_end_len, . - mw_notascii_end
+mw_nomem_msg:
+    .ascii "out of memory\n"
+    .set mw_nomem_msg_len, . - mw_nomem_msg
+"#;
+
+// the stack call and return share, only linked into programs with calls
+const CALLS: &str = r#"
+# the line before %rsi going backwards in %rax, or if %rsi is where the
+# newest call went, the line before that call
+mw_back:
+    lea -1(%rsi), %rax
+    mov mw_calls+8(%rip), %rcx
+    test %rcx, %rcx
+    jz 1f
+    mov mw_calls(%rip), %rdx
+    mov -8(%rdx,%rcx,8), %rdx
+    lea mw_entries(%rip), %r11
+    cmp %rsi, (%r11,%rdx,8)
+    jne 1f
+    dec %rcx
+    mov %rcx, mw_calls+8(%rip)
+    lea -1(%rdx), %rax
+1:  ret
+"#;
+
+fn reg(reg: ast::Register) -> &'static str {
+    match reg {
+        ast::Register::A => "%r12",
+        ast::Register::B => "%r13",
+        ast::Register::C => "%r14",
+        ast::Register::D => "%r15"
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+struct Asm<'a> {
+    text: String,
+    // out of line code for runtime errors, kept away from the hot path
+    stubs: String,
+    count: usize,
+    cells: usize,
+    lines: usize,
+    // lines some call goes to
+    entries: HashSet<usize>,
+    word: &'a Word
+}
+
+impl<'a> Asm<'a> {
+    fn ins(&mut self, ins: &str) {
+        writeln!(self.text, "    {}", ins).unwrap();
+    }
+
+    fn label(&mut self, label: &str) {
+        writeln!(self.text, "{}:", label).unwrap();
+    }
+
+    fn fresh(&mut self) -> String {
+        self.count += 1;
+        format!(".L{}", self.count)
+    }
+
+    // continuing at a line, running off the end or back onto line 0 finishes
+    fn forward(&self, line: usize) -> String {
+        if line >= self.lines {
+            ".Ldone".to_string()
+        }
+        else {
+            format!(".Lf_{}", line)
+        }
+    }
+
+    fn backward(&self, line: usize) -> String {
+        if line == 0 || line >= self.lines {
+            ".Ldone".to_string()
+        }
+        else {
+            format!(".Lb_{}", line)
+        }
+    }
+
+    // going backwards off a line, which for a line calls go to might mean
+    // returning out of the call first
+    fn back(&self, line: usize) -> String {
+        if self.entries.contains(&line) {
+            format!(".Lr_{}", line)
+        }
+        else {
+            self.backward(line.wrapping_sub(1))
+        }
+    }
+
+    // trap unless the address in reg is inside memory
+    fn check(&mut self, reg: &str, line: usize) {
+        let stub = self.fresh();
+        self.ins(&format!("movabs ${}, %r11", self.cells));
+        self.ins(&format!("cmp %r11, {}", reg));
+        self.ins(&format!("jae {}", stub));
+        writeln!(self.stubs, "{}:\n    mov {}, %rdi\n    mov ${}, %esi\n    jmp mw_oob", stub, reg, line).unwrap();
+    }
+
+    // a constant address, out of bounds ones trap as soon as they're reached
+    fn check_const(&mut self, addr: usize, line: usize) -> bool {
+        if addr >= self.cells {
+            self.ins(&format!("movabs ${}, %rdi", addr));
+            self.ins(&format!("mov ${}, %esi", line));
+            self.ins("jmp mw_oob");
+            return false;
+        }
+        true
+    }
+
+    fn load(&mut self, op: &Operand, reg: &str, line: usize) {
+        if op.depth == 0 {
+            match op.base {
+                Place::Reg(r) => self.ins(&format!("mov {}, {}", self::reg(r), reg)),
+                Place::Literal(val) => self.ins(&format!("movabs ${}, {}", self.word.truncate(val), reg)),
+                Place::Mem(addr) => {
+                    if self.check_const(addr, line) {
+                        self.ins(&format!("mov mw_mem+{}(%rip), {}", addr * 8, reg));
+                    }
+                }
+            }
+            return;
+        }
+        self.load(&Operand{depth: op.depth - 1, ..*op}, reg, line);
+        self.check(reg, line);
+        self.ins("lea mw_mem(%rip), %r11");
+        self.ins(&format!("mov (%r11,{},8), {}", reg, reg));
+    }
+
+    // leave the address of a memory operand in reg
+    fn pointer(&mut self, op: &Operand, reg: &str, line: usize) {
+        if op.depth == 0 {
+            if let Place::Mem(addr) = op.base {
+                if self.check_const(addr, line) {
+                    self.ins(&format!("lea mw_mem+{}(%rip), {}", addr * 8, reg));
+                }
+            }
+            return;
+        }
+        self.load(&Operand{depth: op.depth - 1, ..*op}, reg, line);
+        self.check(reg, line);
+        self.ins("lea mw_mem(%rip), %r11");
+        self.ins(&format!("lea (%r11,{},8), {}", reg, reg));
+    }
+
+    // %rax += %rcx, or -= going backwards, under the overflow policy
+    fn arith(&mut self, forward: bool, line: usize) {
+        let narrow = self.word.bits() < 64;
+        if forward {
+            self.ins("add %rcx, %rax");
+        }
+        else {
+            self.ins("sub %rcx, %rax");
+        }
+        if narrow {
+            self.ins(&format!("movabs ${}, %r11", self.word.max()));
+        }
+        match (self.word.overflow(), forward, narrow) {
+            (Overflow::Wrapping, _, false) => (),
+            (Overflow::Wrapping, _, true) => self.ins("and %r11, %rax"),
+            (Overflow::Trapping, _, _) => {
+                let stub = self.fresh();
+                // narrow words can't carry out of 64 bits, so compare against the max
+                let jump = if narrow && forward { "ja" } else { "jc" };
+                if narrow && forward {
+                    self.ins("cmp %r11, %rax");
+                }
+                self.ins(&format!("{} {}", jump, stub));
+                writeln!(self.stubs, "{}:\n    mov ${}, %esi\n    jmp mw_overflow", stub, line).unwrap();
+            },
+            (Overflow::Saturating, true, false) => {
+                self.ins("jnc 1f");
+                self.ins("mov $-1, %rax");
+                self.label("1");
+            },
+            (Overflow::Saturating, true, true) => {
+                self.ins("cmp %r11, %rax");
+                self.ins("jbe 1f");
+                self.ins("mov %r11, %rax");
+                self.label("1");
+            },
+            (Overflow::Saturating, false, _) => {
+                self.ins("jnc 1f");
+                self.ins("xor %eax, %eax");
+                self.label("1");
+            }
+        }
+    }
+
+    fn inc(&mut self, dest: &Operand, src: &Operand, forward: bool, line: usize) {
+        self.load(src, "%rcx", line);
+        match (dest.depth, dest.base) {
+            (0, Place::Reg(r)) => {
+                self.ins(&format!("mov {}, %rax", reg(r)));
+                self.arith(forward, line);
+                self.ins(&format!("mov %rax, {}", reg(r)));
+            },
+            _ => {
+                self.pointer(dest, "%rdx", line);
+                self.ins("mov (%rdx), %rax");
+                self.arith(forward, line);
+                self.ins("mov %rax, (%rdx)");
+            }
+        }
+    }
+
+    // %rax ^= %rcx the way inc does arithmetic, xor is the same both ways
+    fn xor(&mut self, dest: &Operand, src: &Operand, line: usize) {
+        self.load(src, "%rcx", line);
+        match (dest.depth, dest.base) {
+            (0, Place::Reg(r)) => self.ins(&format!("xor %rcx, {}", reg(r))),
+            _ => {
+                self.pointer(dest, "%rdx", line);
+                self.ins("xor %rcx, (%rdx)");
+            }
+        }
+    }
+
+    // registers are swapped in place, memory operands through pointers in
+    // %rdx and %r8 that are both worked out before anything is written
+    fn swap(&mut self, left: &Operand, right: &Operand, line: usize) {
+        let mut at = |op: &Operand, ptr: &str| match (op.depth, op.base) {
+            (0, Place::Reg(r)) => reg(r).to_string(),
+            _ => {
+                self.pointer(op, ptr, line);
+                format!("({})", ptr)
+            }
+        };
+        let (l, r) = (at(left, "%rdx"), at(right, "%r8"));
+        self.ins(&format!("mov {}, %rax", l));
+        self.ins(&format!("mov {}, %rcx", r));
+        self.ins(&format!("mov %rcx, {}", l));
+        self.ins(&format!("mov %rax, {}", r));
+    }
+
+    // load a destination into %rax, let body change it, and store it back.
+    // memory destinations keep their address in %r8 since div needs %rdx
+    fn update(&mut self, dest: &Operand, line: usize, body: impl FnOnce(&mut Self)) {
+        let at = match (dest.depth, dest.base) {
+            (0, Place::Reg(r)) => reg(r).to_string(),
+            _ => {
+                self.pointer(dest, "%r8", line);
+                "(%r8)".to_string()
+            }
+        };
+        self.ins(&format!("mov {}, %rax", at));
+        body(self);
+        self.ins(&format!("mov %rax, {}", at));
+    }
+
+    fn mask(&mut self) {
+        if self.word.bits() < 64 {
+            self.ins(&format!("movabs ${}, %r11", self.word.max()));
+            self.ins("and %r11, %rax");
+        }
+    }
+
+    // %rax *= k, or going backwards divide it back out like Word::div
+    fn mul(&mut self, k: usize, forward: bool, line: usize) {
+        let k = self.word.truncate(k);
+        let stub = |asm: &mut Asm| {
+            let stub = asm.fresh();
+            writeln!(asm.stubs, "{}:\n    mov ${}, %esi\n    jmp mw_overflow", stub, line).unwrap();
+            stub
+        };
+        match (self.word.overflow(), forward) {
+            (Overflow::Wrapping, _) => {
+                let by = if forward { k } else { self.word.inverse(k).unwrap_or(0) };
+                self.ins(&format!("movabs ${}, %rcx", by));
+                self.ins("imul %rcx, %rax");
+                self.mask();
+            },
+            (Overflow::Trapping, true) => {
+                let stub = stub(self);
+                self.ins(&format!("movabs ${}, %r11", self.word.max() / k));
+                self.ins("cmp %r11, %rax");
+                self.ins(&format!("ja {}", stub));
+                self.ins(&format!("movabs ${}, %rcx", k));
+                self.ins("imul %rcx, %rax");
+            },
+            (Overflow::Saturating, true) => {
+                self.ins(&format!("movabs ${}, %r11", self.word.max() / k));
+                self.ins("cmp %r11, %rax");
+                self.ins("jbe 1f");
+                self.ins(&format!("movabs ${}, %rax", self.word.max()));
+                self.ins("jmp 2f");
+                self.label("1");
+                self.ins(&format!("movabs ${}, %rcx", k));
+                self.ins("imul %rcx, %rax");
+                self.label("2");
+            },
+            (overflow, false) => {
+                self.ins("xor %edx, %edx");
+                self.ins(&format!("movabs ${}, %rcx", k));
+                self.ins("div %rcx");
+                // only multiples of k could have come from mul
+                if overflow == Overflow::Trapping {
+                    let stub = stub(self);
+                    self.ins("test %rdx, %rdx");
+                    self.ins(&format!("jnz {}", stub));
+                }
+            }
+        }
+    }
+
+    // rotate %rax by %cl at the word's width, the count wraps at the width
+    fn rotate(&mut self, left: bool) {
+        let r = match self.word.bits() {
+            8 => "%al",
+            16 => "%ax",
+            32 => "%eax",
+            _ => "%rax"
+        };
+        self.ins(&format!("{} %cl, {}", if left { "rol" } else { "ror" }, r));
+    }
+
+    // jump to yes or no, and/or short circuit like the interpreter
+    fn cond(&mut self, expr: &ast::Expr, forward: bool, yes: &str, no: &str, line: usize) {
+        let cmp = |asm: &mut Asm, left: &ast::Source, jump: &str, right: &ast::Source| {
+            asm.load(&Operand::source(left), "%rax", line);
+            asm.load(&Operand::source(right), "%rcx", line);
+            asm.ins("cmp %rcx, %rax");
+            asm.ins(&format!("{} {}", jump, yes));
+            asm.ins(&format!("jmp {}", no));
+        };
+        match expr {
+            ast::Expr::Forwards => self.ins(&format!("jmp {}", if forward { yes } else { no })),
+            ast::Expr::Backwards => self.ins(&format!("jmp {}", if forward { no } else { yes })),
+            ast::Expr::Or(left, right) => {
+                let mid = self.fresh();
+                self.cond(left, forward, yes, &mid, line);
+                self.label(&mid);
+                self.cond(right, forward, yes, no, line);
+            },
+            ast::Expr::And(left, right) => {
+                let mid = self.fresh();
+                self.cond(left, forward, &mid, no, line);
+                self.label(&mid);
+                self.cond(right, forward, yes, no, line);
+            },
+            ast::Expr::Eq(left, right) => cmp(self, left, "je", right),
+            ast::Expr::Gt(left, right) => cmp(self, left, "ja", right),
+            ast::Expr::Gte(left, right) => cmp(self, left, "jae", right),
+            ast::Expr::Lt(left, right) => cmp(self, left, "jb", right),
+            ast::Expr::Lte(left, right) => cmp(self, left, "jbe", right),
+        }
+    }
+
+    // move saves the old value of dest on its value stack and writes the
+    // source, read first into %rbx, restoring pops it back if there is one.
+    // a memory cell's stack is found from its address in %rbp
+    fn moving(&mut self, dest: &Operand, src: &Operand, save: bool, line: usize) {
+        if save {
+            self.load(src, "%rbx", line);
+        }
+        let (routine, result) = if save { ("mw_push", "%rbx") } else { ("mw_pop", "%rax") };
+        match (dest.depth, dest.base) {
+            (0, Place::Reg(r)) => {
+                self.ins(&format!("lea mw_values+{}(%rip), %rdi", r as usize * 24));
+                self.ins(&format!("mov {}, %rsi", reg(r)));
+                self.ins(&format!("call {}", routine));
+                self.ins(&format!("mov {}, {}", result, reg(r)));
+            },
+            _ => {
+                self.pointer(dest, "%rbp", line);
+                self.ins("lea mw_mem(%rip), %r11");
+                self.ins("mov %rbp, %rdi");
+                self.ins("sub %r11, %rdi");
+                self.ins("lea (%rdi,%rdi,2), %rdi");
+                self.ins("lea mw_values+96(%rip), %r11");
+                self.ins("add %r11, %rdi");
+                self.ins("mov (%rbp), %rsi");
+                self.ins(&format!("call {}", routine));
+                self.ins(&format!("mov {}, (%rbp)", result));
+            }
+        }
+    }
+
+    fn stack(&mut self, line: usize) {
+        self.ins(&format!("lea mw_stacks+{}(%rip), %rdi", line * 24));
+    }
+}
+
+// emit GNU assembler for x86-64 linux, each line has a forward and a backward
+// code path and changing direction jumps from one to the other
+pub fn compile(program: &Program, settings: &Settings) -> Result<String, String> {
+    let cells = match settings.memory {
+        MemoryModel::Fixed(cells) if cells <= 1 << 27 => cells,
+        MemoryModel::Fixed(_) => return Err("the x86_64-asm target supports at most 134217728 memory cells".to_string()),
+        MemoryModel::Sparse => return Err("the x86_64-asm target does not support sparse memory".to_string())
+    };
+    let lines = program.lines();
+    let code = bytecode::compile(lines, program.labels());
+    let entries = code.insns.iter().filter_map(|insn| match insn.op {
+        Op::Call(entry) => Some(entry),
+        _ => None
+    }).collect();
+    let mut asm = Asm{text: String::new(), stubs: String::new(), count: 0, cells, lines: lines.len(), entries, word: &settings.word};
+
+    for (i, (line, insn)) in lines.iter().zip(&code.insns).enumerate() {
+        let next = asm.forward(i + 1);
+        asm.label(&format!(".Lf_{}", i));
+        if let Some(expr) = &line.cond {
+            let body = asm.fresh();
+            asm.cond(expr, true, &body, &next, i);
+            asm.label(&body);
+        }
+        match insn.op {
+            Op::Inc(dest, src) => {
+                asm.inc(&dest, &src, true, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Swap(left, right) => {
+                asm.swap(&left, &right, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Xor(dest, src) => {
+                asm.xor(&dest, &src, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Mul(dest, k) => {
+                asm.update(&dest, i, |asm| asm.mul(k, true, i));
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Rol(dest, src) | Op::Ror(dest, src) => {
+                let left = matches!(insn.op, Op::Rol(..));
+                asm.load(&src, "%rcx", i);
+                asm.update(&dest, i, |asm| asm.rotate(left));
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Neg(dest) => {
+                asm.update(&dest, i, |asm| {
+                    asm.ins("neg %rax");
+                    asm.mask();
+                });
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Jump(Some(target)) => {
+                asm.stack(target.line);
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_push");
+                let to = if target.bare { target.line + 1 } else { target.line };
+                asm.ins(&format!("jmp {}", asm.forward(to)));
+            },
+            Op::Jump(None) => {
+                asm.stack(i);
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_pop");
+                asm.ins("inc %rax");
+                asm.ins("jmp .Ldispatch_f");
+            },
+            Op::From(_) | Op::Forwards => asm.ins(&format!("jmp {}", next)),
+            Op::Backwards | Op::Reverse => asm.ins(&format!("jmp {}", asm.back(i))),
+            Op::Halt => asm.ins("jmp mw_halt"),
+            Op::Move(dest, src) | Op::Unmove(dest, src) => {
+                asm.moving(&dest, &src, matches!(insn.op, Op::Move(..)), i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Call(entry) => {
+                asm.ins("lea mw_calls(%rip), %rdi");
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_push");
+                asm.ins(&format!("jmp {}", asm.forward(entry)));
+            },
+            // back to the line after the newest call, which remembers the
+            // return for going back in
+            Op::Return => {
+                asm.ins("lea mw_calls(%rip), %rdi");
+                asm.ins("mov $-1, %rsi");
+                asm.ins("call mw_pop");
+                asm.ins("cmp $-1, %rax");
+                asm.ins(&format!("je {}", next));
+                asm.ins("mov %rax, %rbx");
+                asm.ins("lea (%rax,%rax,2), %rax");
+                asm.ins("lea mw_stacks(%rip), %rdi");
+                asm.ins("lea (%rdi,%rax,8), %rdi");
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_push");
+                asm.ins("lea 1(%rbx), %rax");
+                asm.ins("jmp .Ldispatch_f");
+            },
+            Op::Io(src) => {
+                match (src.depth, src.base) {
+                    (0, Place::Literal(_)) => {
+                        asm.ins(&format!("mov ${}, %esi", i));
+                        asm.ins("jmp mw_literal");
+                    },
+                    (0, Place::Reg(r)) => {
+                        asm.ins("call mw_getc");
+                        asm.ins(&format!("mov %rax, {}", reg(r)));
+                    },
+                    _ => {
+                        // where the input goes is checked before reading it
+                        asm.pointer(&src, "%rbx", i);
+                        asm.ins("call mw_getc");
+                        asm.ins("mov %rax, (%rbx)");
+                    }
+                }
+                asm.ins(&format!("jmp {}", next));
+            }
+        }
+    }
+
+    // line 0 is never run backwards, reaching it finishes the program
+    for (i, (line, insn)) in lines.iter().zip(&code.insns).enumerate().skip(1) {
+        let next = asm.back(i);
+        asm.label(&format!(".Lb_{}", i));
+        if let Some(expr) = &line.cond {
+            let body = asm.fresh();
+            asm.cond(expr, false, &body, &next, i);
+            asm.label(&body);
+        }
+        match insn.op {
+            Op::Inc(dest, src) => {
+                asm.inc(&dest, &src, false, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Swap(left, right) => {
+                asm.swap(&left, &right, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Xor(dest, src) => {
+                asm.xor(&dest, &src, i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Mul(dest, k) => {
+                asm.update(&dest, i, |asm| asm.mul(k, false, i));
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Rol(dest, src) | Op::Ror(dest, src) => {
+                let left = matches!(insn.op, Op::Ror(..));
+                asm.load(&src, "%rcx", i);
+                asm.update(&dest, i, |asm| asm.rotate(left));
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::Neg(dest) => {
+                asm.update(&dest, i, |asm| {
+                    asm.ins("neg %rax");
+                    asm.mask();
+                });
+                asm.ins(&format!("jmp {}", next));
+            },
+            Op::From(Some(target)) => {
+                asm.stack(target.line);
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_push");
+                let to = if target.bare { target.line.wrapping_sub(1) } else { target.line };
+                asm.ins(&format!("jmp {}", asm.backward(to)));
+            },
+            Op::From(None) => {
+                asm.stack(i);
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_pop");
+                // nothing to pop leaves the line the way any other does
+                if asm.entries.contains(&i) {
+                    asm.ins(&format!("cmp ${}, %rax", i));
+                    asm.ins(&format!("je {}", next));
+                }
+                asm.ins("dec %rax");
+                asm.ins("jmp .Ldispatch_b");
+            },
+            Op::Jump(_) | Op::Backwards | Op::Return => asm.ins(&format!("jmp {}", next)),
+            Op::Forwards | Op::Reverse => asm.ins(&format!("jmp {}", asm.forward(i + 1))),
+            Op::Halt => asm.ins("jmp mw_halt"),
+            Op::Move(dest, src) | Op::Unmove(dest, src) => {
+                asm.moving(&dest, &src, matches!(insn.op, Op::Unmove(..)), i);
+                asm.ins(&format!("jmp {}", next));
+            },
+            // back into the callee at the return it left by
+            Op::Call(_) => {
+                asm.stack(i);
+                asm.ins("mov $-1, %rsi");
+                asm.ins("call mw_pop");
+                asm.ins("cmp $-1, %rax");
+                asm.ins(&format!("je {}", next));
+                asm.ins("mov %rax, %rbx");
+                asm.ins("lea mw_calls(%rip), %rdi");
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_push");
+                asm.ins("mov %rbx, %rax");
+                asm.ins("jmp .Ldispatch_b");
+            },
+            Op::Io(src) => {
+                asm.load(&src, "%rdi", i);
+                asm.ins(&format!("mov ${}, %esi", i));
+                asm.ins("call mw_putc");
+                asm.ins(&format!("jmp {}", next));
+            }
+        }
+    }
+
+    let mut out = String::new();
+    writeln!(out, "# generated by moonwalk from {}", settings.file).unwrap();
+    writeln!(out, "    .set LINES, {}", lines.len()).unwrap();
+    writeln!(out, "    .text\n    .globl _start\n_start:").unwrap();
+    for r in &["%r12", "%r13", "%r14", "%r15"] {
+        writeln!(out, "    xor {}, {}", r, r).unwrap();
+    }
+    writeln!(out, "    jmp {}\n", asm.forward(0)).unwrap();
+    out.push_str(&asm.text);
+
+    // bare jump and from return to a line only known at runtime, in %rax
+    writeln!(out, "\n.Ldispatch_f:").unwrap();
+    writeln!(out, "    cmp ${}, %rax\n    jae .Ldone", lines.len()).unwrap();
+    writeln!(out, "    lea .Lftable(%rip), %r11\n    movslq (%r11,%rax,4), %rax\n    add %r11, %rax\n    jmp *%rax").unwrap();
+    writeln!(out, ".Ldispatch_b:").unwrap();
+    writeln!(out, "    cmp ${}, %rax\n    jae .Ldone", lines.len()).unwrap();
+    writeln!(out, "    lea .Lbtable(%rip), %r11\n    movslq (%r11,%rax,4), %rax\n    add %r11, %rax\n    jmp *%rax").unwrap();
+    // going backwards off a line a call goes to
+    let mut entries: Vec<_> = asm.entries.iter().collect();
+    entries.sort();
+    for entry in entries {
+        writeln!(out, ".Lr_{}:\n    mov ${}, %esi\n    call mw_back\n    jmp .Ldispatch_b", entry, entry).unwrap();
+    }
+    writeln!(out, ".Ldone:\n    jmp mw_finish\n").unwrap();
+    out.push_str(&asm.stubs);
+    let calls = code.insns.iter().any(|insn| matches!(insn.op, Op::Call(_) | Op::Return));
+    if calls {
+        out.push_str(CALLS);
+    }
+    out.push_str(RUNTIME);
+
+    writeln!(out, ".Lftable:").unwrap();
+    for i in 0..lines.len() {
+        writeln!(out, "    .long .Lf_{} - .Lftable", i).unwrap();
+    }
+    writeln!(out, ".Lbtable:").unwrap();
+    for i in 0..lines.len() {
+        writeln!(out, "    .long {} - .Lbtable", asm.backward(i)).unwrap();
+    }
+    // where each line is for error messages, the extra entry is the file alone
+    if calls {
+        // the line each call goes to, the number of lines for any other line
+        writeln!(out, "mw_entries:").unwrap();
+        for insn in &code.insns {
+            match insn.op {
+                Op::Call(entry) => writeln!(out, "    .quad {}", entry).unwrap(),
+                _ => writeln!(out, "    .quad LINES").unwrap()
+            }
+        }
+    }
+    writeln!(out, "mw_locs:").unwrap();
+    for i in 0..=lines.len() {
+        writeln!(out, "    .long .Lloc_{} - mw_locs, .Lloc_{}_end - .Lloc_{}", i, i, i).unwrap();
+    }
+    let file = escape(&settings.file);
+    for (i, line) in lines.iter().enumerate() {
+        writeln!(out, ".Lloc_{}:\n    .ascii \"{}:{}:{}\\n\"\n.Lloc_{}_end:", i, file, line.span.line, line.span.column, i).unwrap();
+    }
+    writeln!(out, ".Lloc_{}:\n    .ascii \"{}\\n\"\n.Lloc_{}_end:", lines.len(), file, lines.len()).unwrap();
+
+    writeln!(out, "\n    .bss\n    .align 8").unwrap();
+    writeln!(out, "mw_mem:\n    .skip {}", cells.max(1) * 8).unwrap();
+    writeln!(out, "mw_stacks:\n    .skip {}", (lines.len() + 1) * 24).unwrap();
+    writeln!(out, "mw_outlen:\n    .skip 8").unwrap();
+    writeln!(out, "mw_outbuf:\n    .skip 4096").unwrap();
+    if calls {
+        writeln!(out, "mw_calls:\n    .skip 24").unwrap();
+    }
+    // value stacks for the registers then every cell, last since it's big
+    if code.insns.iter().any(|insn| matches!(insn.op, Op::Move(..) | Op::Unmove(..))) {
+        writeln!(out, "mw_values:\n    .skip {}", (4 + cells.max(1)) * 24).unwrap();
+    }
+    Ok(out)
+}
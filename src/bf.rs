@@ -1,9 +1,11 @@
+use std::fmt;
+use std::io::{Read, Write};
+use std::str::FromStr;
 
-use std::io::{self, Read};
-use std::env;
-use std::fs;
+use crate::ast::Span;
+use crate::diagnostic::{Diagnostic, Diagnostics};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Instruction {
     Inc,
     Dec,
@@ -11,105 +13,211 @@ pub enum Instruction {
     Left,
     In,
     Out,
-    Nop,
-    Loop(Vec<Instruction>)
+    // brackets hold the index of their match, so loops jump straight there
+    Open(usize),
+    Close(usize)
 }
 
-pub fn match_loop(input: &String, offset: usize) -> Option<usize> {
-    let mut i = match input[offset..].find('[') {
-        None => return None,
-        Some(num) => num + offset
-    };
-    let mut count = 0;
-    while i < input.len() {
-        count += match input.chars().nth(i) {
-            None => return None,
-            Some(c) => match c {
-                '[' =>  1,
-                ']' => -1,
-                _   =>  0
-            }
+// instructions in order, with where each one is in the source
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Program {
+    pub insts: Vec<Instruction>,
+    pub spans: Vec<Span>
+}
+
+impl Program {
+    // a runtime error pointing at the instruction the context stopped on
+    pub fn error(&self, error: Error, ctx: &Context) -> Diagnostic {
+        Diagnostic::error(self.spans.get(ctx.pc).copied(), error.to_string())
+    }
+}
+
+// parse in a single pass, every other character is a comment
+pub fn parse(input: &str) -> Result<Program, Diagnostics> {
+    let mut diagnostics = Diagnostics::new();
+    // the loops still open, by the index of their [
+    let mut open = Vec::new();
+    let mut program = Program::default();
+    let (mut line, mut column) = (1, 1);
+    for (i, c) in input.char_indices() {
+        let span = Span{start: i, end: i + c.len_utf8(), line, column};
+        if c == '\n' {
+            line += 1;
+            column = 1;
+        }
+        else {
+            column += 1;
+        }
+        let inst = match c {
+            '+' => Instruction::Inc,
+            '-' => Instruction::Dec,
+            '>' => Instruction::Right,
+            '<' => Instruction::Left,
+            ',' => Instruction::In,
+            '.' => Instruction::Out,
+            '[' => {
+                open.push(program.insts.len());
+                Instruction::Open(0)
+            },
+            ']' => match open.pop() {
+                Some(start) => {
+                    program.insts[start] = Instruction::Open(program.insts.len());
+                    Instruction::Close(start)
+                },
+                None => {
+                    diagnostics.push(Diagnostic::error(span, "unmatched ]"));
+                    continue;
+                }
+            },
+            _ => continue
         };
-        if count == 0 {
-            return Some(i)
+        program.insts.push(inst);
+        program.spans.push(span);
+    }
+    for start in open {
+        diagnostics.push(Diagnostic::error(program.spans[start], "unmatched ["));
+    }
+    if diagnostics.is_empty() {
+        Ok(program)
+    }
+    else {
+        Err(diagnostics)
+    }
+}
+
+// what `,` leaves in the cell once input runs out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eof {
+    #[default]
+    Zero,
+    // 255, which is -1 in a wrapping byte
+    Max,
+    Unchanged
+}
+
+impl FromStr for Eof {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Eof, String> {
+        match s {
+            "zero" => Ok(Eof::Zero),
+            "max" => Ok(Eof::Max),
+            "unchanged" => Ok(Eof::Unchanged),
+            _ => Err(format!("unknown eof behavior {}, expected zero, max or unchanged", s))
+        }
+    }
+}
+
+impl fmt::Display for Eof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Eof::Zero => write!(f, "zero"),
+            Eof::Max => write!(f, "max"),
+            Eof::Unchanged => write!(f, "unchanged")
         }
-        i += 1;
     }
-    return None;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    // the pointer moved left of cell 0
+    LeftOfTape,
+    // the pointer moved right of the last cell
+    RightOfTape{cells: usize},
+    InputFailed,
+    OutputFailed
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::LeftOfTape => write!(f, "pointer moved left of cell 0"),
+            Error::RightOfTape{cells} => write!(f, "pointer moved past the end of the tape, which has {} cells", cells),
+            Error::InputFailed => write!(f, "unable to read input"),
+            Error::OutputFailed => write!(f, "unable to write output")
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub const DEFAULT_TAPE: usize = 30000;
+pub const MAX_TAPE: usize = 1 << 28;
+
+// a tape of wrapping byte cells and where to read and write them, with the
+// instruction running
 pub struct Context {
+    pc: usize,
     ptr: usize,
-    heap: [u8; 65536]
+    tape: Vec<u8>,
+    eof: Eof,
+    input: Box<dyn Read>,
+    output: Box<dyn Write>
 }
 
-pub fn parse(input: String) -> Vec<Instruction> {
-    let mut program: Vec<Instruction> = vec![];
-    let mut i = 0;
-    while i < input.len() {
-        program.push(match input.chars().nth(i) {
-            Some(c) => match c {
-                '+' => Instruction::Inc,
-                '-' => Instruction::Dec,
-                '>' => Instruction::Right,
-                '<' => Instruction::Left,
-                ',' => Instruction::In,
-                '.' => Instruction::Out,
-                '[' => match match_loop(&input, i) {
-                    None => Instruction::Nop,
-                    Some(end) => {
-                        let begin = i + 1;
-                        i = end;
-                        Instruction::Loop(parse(input[begin .. end].to_string()))
-                    }
-                },
-                _   => Instruction::Nop
-            },
-            None => Instruction::Nop
-        });
-        i+=1;
+impl Context {
+    // the tape always has at least the cell the pointer starts on, and at
+    // most MAX_TAPE
+    pub fn new(cells: usize, eof: Eof, input: Box<dyn Read>, output: Box<dyn Write>) -> Context {
+        Context{pc: 0, ptr: 0, tape: vec![0; cells.clamp(1, MAX_TAPE)], eof, input, output}
+    }
+
+    // the instruction a failed run stopped on
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
     }
-    return program;
-}
 
-pub fn getc() -> char {
-    let mut buf: [u8; 1] = [0; 1];
-    return match io::stdin().read(&mut buf) {
-        Ok(_) => buf[0] as char,
-        Err(_) => 255 as char
+    pub fn tape(&self) -> &[u8] {
+        &self.tape
+    }
+
+    fn getc(&mut self) -> Result<(), Error> {
+        // make sure any prompt is visible before blocking on input
+        self.output.flush().map_err(|_| Error::OutputFailed)?;
+        let mut buf = [0; 1];
+        let cell = &mut self.tape[self.ptr];
+        match self.input.read(&mut buf) {
+            Ok(0) => match self.eof {
+                Eof::Zero => *cell = 0,
+                Eof::Max => *cell = 255,
+                Eof::Unchanged => ()
+            },
+            Ok(_) => *cell = buf[0],
+            Err(_) => return Err(Error::InputFailed)
+        }
+        Ok(())
     }
 }
 
-pub fn eval(program: &Vec<Instruction>, in_ctx: Context) -> Context {
-    let mut ctx = in_ctx;
-    for op in program {
-        //print!("{:?}\n", op);
-        match op {
-            Instruction::Inc => ctx.heap[ctx.ptr] += 1,
-            Instruction::Dec => ctx.heap[ctx.ptr] -= 1,
+fn exec(program: &Program, ctx: &mut Context) -> Result<(), Error> {
+    while let Some(&inst) = program.insts.get(ctx.pc) {
+        match inst {
+            Instruction::Inc => ctx.tape[ctx.ptr] = ctx.tape[ctx.ptr].wrapping_add(1),
+            Instruction::Dec => ctx.tape[ctx.ptr] = ctx.tape[ctx.ptr].wrapping_sub(1),
+            Instruction::Right if ctx.ptr + 1 == ctx.tape.len() => return Err(Error::RightOfTape{cells: ctx.tape.len()}),
             Instruction::Right => ctx.ptr += 1,
-            Instruction::Left  => ctx.ptr -= 1,
-            Instruction::Loop(content) => {
-                while ctx.heap[ctx.ptr] != 0 {
-                    ctx = eval(&content, ctx);
-                }
-            }
-            Instruction::In => ctx.heap[ctx.ptr] = getc() as u8,
-            Instruction::Out => print!("{}", ctx.heap[ctx.ptr] as char),
-            Instruction::Nop => ()
+            Instruction::Left if ctx.ptr == 0 => return Err(Error::LeftOfTape),
+            Instruction::Left => ctx.ptr -= 1,
+            // land on the matching bracket, the step past it leaves or
+            // repeats the loop
+            Instruction::Open(end) if ctx.tape[ctx.ptr] == 0 => ctx.pc = end,
+            Instruction::Close(start) if ctx.tape[ctx.ptr] != 0 => ctx.pc = start,
+            Instruction::Open(_) | Instruction::Close(_) => (),
+            Instruction::In => ctx.getc()?,
+            Instruction::Out => ctx.output.write_all(&[ctx.tape[ctx.ptr]]).map_err(|_| Error::OutputFailed)?
         }
+        ctx.pc += 1;
     }
-    return ctx;
+    Ok(())
 }
 
-pub fn bfmain() {
-    let args: Vec<String> = env::args().collect();
-    let message = format!("Unable to read file {}", args[1]);
-    let line = fs::read_to_string(&args[1]).expect(&message);
-    let program = parse(line);
-    let init_ctx = Context {
-        ptr: 0,
-        heap: [0; 65536]
-    };
-    eval(&program, init_ctx);
+// run a program to the end, flushing output even if it fails part way
+pub fn eval(program: &Program, ctx: &mut Context) -> Result<(), Error> {
+    let result = exec(program, ctx);
+    let flushed = ctx.output.flush().map_err(|_| Error::OutputFailed);
+    result.and(flushed)
 }
@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::ast;
+use crate::eval::{self, Context, Place, RuntimeError, Status};
+
+// which implementation runs a program, chosen on the command line with --engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Engine {
+    // walk the ast, needed for stepping and history
+    #[default]
+    Tree,
+    // compile to bytecode first, faster for running to completion
+    Bytecode
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "tree" => Ok(Engine::Tree),
+            "bytecode" => Ok(Engine::Bytecode),
+            _ => Err(format!("unknown engine {}, expected tree or bytecode", s))
+        }
+    }
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Tree => write!(f, "tree"),
+            Engine::Bytecode => write!(f, "bytecode")
+        }
+    }
+}
+
+// a source or destination with its derefs counted instead of boxed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operand {
+    pub base: Place,
+    pub depth: usize
+}
+
+impl Operand {
+    pub fn source(src: &ast::Source) -> Operand {
+        match src {
+            ast::Source::Reg(reg) => Operand{base: Place::Reg(*reg), depth: 0},
+            ast::Source::Addr(addr) => Operand{base: Place::Mem(*addr), depth: 0},
+            ast::Source::Literal(val) => Operand{base: Place::Literal(*val), depth: 0},
+            ast::Source::Deref(src) => {
+                let inner = Operand::source(src);
+                Operand{depth: inner.depth + 1, ..inner}
+            }
+        }
+    }
+
+    pub fn dest(dest: &ast::Dest) -> Operand {
+        match dest {
+            ast::Dest::Reg(reg) => Operand{base: Place::Reg(*reg), depth: 0},
+            ast::Dest::Addr(addr) => Operand{base: Place::Mem(*addr), depth: 0},
+            ast::Dest::Deref(dest) => {
+                let inner = Operand::dest(dest);
+                Operand{depth: inner.depth + 1, ..inner}
+            }
+        }
+    }
+
+    fn place(&self, ctx: &Context) -> Result<Place, RuntimeError> {
+        eval::resolve(self.base, self.depth, ctx)
+    }
+
+    fn read(&self, ctx: &Context) -> Result<usize, RuntimeError> {
+        eval::read_place(self.place(ctx)?, ctx)
+    }
+}
+
+// a resolved jump or from target
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub line: usize,
+    // the target is a bare jump or from, so execution carries on past it
+    pub bare: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Inc(Operand, Operand),
+    Move(Operand, Operand),
+    Unmove(Operand, Operand),
+    Swap(Operand, Operand),
+    Xor(Operand, Operand),
+    Mul(Operand, usize),
+    Rol(Operand, Operand),
+    Ror(Operand, Operand),
+    Neg(Operand),
+    Jump(Option<Target>),
+    From(Option<Target>),
+    // the line the callee starts on
+    Call(usize),
+    Return,
+    Forwards,
+    Backwards,
+    Reverse,
+    Halt,
+    Io(Operand)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Gt,
+    Gte,
+    Lt,
+    Lte
+}
+
+// a condition flattened into a sequence that sets a single flag, and/or
+// skip ahead to short circuit just like the tree walker
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CondOp {
+    Forwards,
+    Backwards,
+    Cmp(Cmp, Operand, Operand),
+    // skip the next n ops if the flag is already true
+    Or(usize),
+    // skip the next n ops if the flag is already false
+    And(usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insn {
+    pub op: Op,
+    // range of Bytecode::conds to test before running op
+    pub cond: Option<(usize, usize)>
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Bytecode {
+    pub insns: Vec<Insn>,
+    pub conds: Vec<CondOp>
+}
+
+fn compile_cond(expr: &ast::Expr, conds: &mut Vec<CondOp>) {
+    match expr {
+        ast::Expr::Forwards => conds.push(CondOp::Forwards),
+        ast::Expr::Backwards => conds.push(CondOp::Backwards),
+        ast::Expr::Or(left, right) | ast::Expr::And(left, right) => {
+            compile_cond(left, conds);
+            let jump = conds.len();
+            conds.push(CondOp::Or(0));
+            compile_cond(right, conds);
+            let skip = conds.len() - jump - 1;
+            conds[jump] = match expr {
+                ast::Expr::Or(..) => CondOp::Or(skip),
+                _ => CondOp::And(skip)
+            };
+        },
+        ast::Expr::Eq(left, right) => conds.push(CondOp::Cmp(Cmp::Eq, Operand::source(left), Operand::source(right))),
+        ast::Expr::Gt(left, right) => conds.push(CondOp::Cmp(Cmp::Gt, Operand::source(left), Operand::source(right))),
+        ast::Expr::Gte(left, right) => conds.push(CondOp::Cmp(Cmp::Gte, Operand::source(left), Operand::source(right))),
+        ast::Expr::Lt(left, right) => conds.push(CondOp::Cmp(Cmp::Lt, Operand::source(left), Operand::source(right))),
+        ast::Expr::Lte(left, right) => conds.push(CondOp::Cmp(Cmp::Lte, Operand::source(left), Operand::source(right))),
+    }
+}
+
+fn target(label: &Option<String>, lines: &[ast::Line], labels: &HashMap<String, usize>) -> Option<Target> {
+    let line = labels[label.as_ref()?.as_str()];
+    let bare = matches!(lines[line].inst, ast::Instruction::Jump(None) | ast::Instruction::From(None));
+    Some(Target{line, bare})
+}
+
+// lower a program into bytecode with every label resolved to a line
+pub fn compile(lines: &[ast::Line], labels: &HashMap<String, usize>) -> Bytecode {
+    let mut code = Bytecode::default();
+    for line in lines {
+        let op = match &line.inst {
+            ast::Instruction::Inc(dest, src) => Op::Inc(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Move(dest, src) => Op::Move(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Unmove(dest, src) => Op::Unmove(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Swap(left, right) => Op::Swap(Operand::dest(left), Operand::dest(right)),
+            ast::Instruction::Xor(dest, src) => Op::Xor(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Mul(dest, k) => Op::Mul(Operand::dest(dest), *k),
+            ast::Instruction::Rol(dest, src) => Op::Rol(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Ror(dest, src) => Op::Ror(Operand::dest(dest), Operand::source(src)),
+            ast::Instruction::Neg(dest) => Op::Neg(Operand::dest(dest)),
+            ast::Instruction::Jump(label) => Op::Jump(target(label, lines, labels)),
+            ast::Instruction::From(label) => Op::From(target(label, lines, labels)),
+            ast::Instruction::Call(label) => Op::Call(labels[label]),
+            ast::Instruction::Return => Op::Return,
+            ast::Instruction::Forwards => Op::Forwards,
+            ast::Instruction::Backwards => Op::Backwards,
+            ast::Instruction::Reverse => Op::Reverse,
+            ast::Instruction::Halt => Op::Halt,
+            ast::Instruction::Io(src) => Op::Io(Operand::source(src))
+        };
+        let cond = line.cond.as_ref().map(|expr| {
+            let start = code.conds.len();
+            compile_cond(expr, &mut code.conds);
+            (start, code.conds.len())
+        });
+        code.insns.push(Insn{op, cond});
+    }
+    code
+}
+
+fn advance(pc: usize, forward: bool) -> usize {
+    if forward {
+        pc + 1
+    }
+    else {
+        pc.wrapping_sub(1)
+    }
+}
+
+// runs bytecode against a context, each line keeps its jump stack here
+// instead of on the ast
+pub struct Vm {
+    code: Bytecode,
+    stacks: Vec<Vec<usize>>
+}
+
+impl Vm {
+    pub fn new(code: Bytecode) -> Vm {
+        let stacks = vec![Vec::new(); code.insns.len()];
+        Vm{code, stacks}
+    }
+
+    pub fn code(&self) -> &Bytecode {
+        &self.code
+    }
+
+    pub fn stacks(&self) -> &[Vec<usize>] {
+        &self.stacks
+    }
+
+    pub fn stacks_mut(&mut self) -> &mut [Vec<usize>] {
+        &mut self.stacks
+    }
+
+    fn test(&self, start: usize, end: usize, ctx: &Context) -> Result<bool, RuntimeError> {
+        let mut flag = false;
+        let mut i = start;
+        while i < end {
+            match self.code.conds[i] {
+                CondOp::Forwards => flag = ctx.forward,
+                CondOp::Backwards => flag = !ctx.forward,
+                CondOp::Cmp(cmp, left, right) => {
+                    let (left, right) = (left.read(ctx)?, right.read(ctx)?);
+                    flag = match cmp {
+                        Cmp::Eq => left == right,
+                        Cmp::Gt => left > right,
+                        Cmp::Gte => left >= right,
+                        Cmp::Lt => left < right,
+                        Cmp::Lte => left <= right
+                    };
+                },
+                CondOp::Or(skip) if flag => i += skip,
+                CondOp::And(skip) if !flag => i += skip,
+                CondOp::Or(_) | CondOp::And(_) => ()
+            }
+            i += 1;
+        }
+        Ok(flag)
+    }
+
+    // execute the instruction at the program counter and advance it
+    pub fn step(&mut self, ctx: &mut Context) -> Result<Status, RuntimeError> {
+        let pc = ctx.pc;
+        if pc >= self.code.insns.len() || (pc == 0 && !ctx.forward) {
+            return Ok(Status::Finished);
+        }
+        let insn = self.code.insns[pc];
+        if let Some((start, end)) = insn.cond {
+            if !self.test(start, end, ctx)? {
+                ctx.pc = self.next(ctx);
+                return Ok(Status::Running);
+            }
+        }
+        match insn.op {
+            Op::Inc(dest, src) => {
+                let srcval = src.read(ctx)?;
+                eval::inc(dest.place(ctx)?, srcval, ctx)?;
+            },
+            Op::Move(dest, src) | Op::Unmove(dest, src) => {
+                if ctx.forward == matches!(insn.op, Op::Move(..)) {
+                    let srcval = src.read(ctx)?;
+                    eval::save(dest.place(ctx)?, srcval, ctx)?;
+                }
+                else {
+                    eval::restore(dest.place(ctx)?, ctx)?;
+                }
+            },
+            Op::Swap(left, right) => {
+                let left = left.place(ctx)?;
+                eval::swap(left, right.place(ctx)?, ctx)?;
+            },
+            Op::Xor(dest, src) => {
+                let srcval = src.read(ctx)?;
+                eval::xor(dest.place(ctx)?, srcval, ctx)?;
+            },
+            Op::Mul(dest, k) => eval::mul(dest.place(ctx)?, k, ctx)?,
+            Op::Rol(dest, src) | Op::Ror(dest, src) => {
+                let amount = src.read(ctx)?;
+                let left = ctx.forward == matches!(insn.op, Op::Rol(..));
+                eval::rotate(dest.place(ctx)?, amount, left, ctx)?;
+            },
+            Op::Neg(dest) => eval::neg(dest.place(ctx)?, ctx)?,
+            Op::Jump(Some(target)) if ctx.forward => return Ok(self.goto(pc, target, ctx)),
+            Op::From(Some(target)) if !ctx.forward => return Ok(self.goto(pc, target, ctx)),
+            // bare jump and from go back to wherever last jumped to them
+            Op::Jump(None) if ctx.forward => ctx.pc = self.stacks[pc].pop().unwrap_or(pc),
+            Op::From(None) if !ctx.forward => ctx.pc = self.stacks[pc].pop().unwrap_or(pc),
+            Op::Jump(_) | Op::From(_) => (),
+            Op::Call(entry) if ctx.forward => {
+                eval::push_call(pc, ctx);
+                ctx.pc = entry;
+                return Ok(Status::Running);
+            },
+            // going backwards re-enter the callee at the return it left by
+            Op::Call(_) => if let Some(ret) = self.stacks[pc].pop() {
+                eval::push_call(pc, ctx);
+                ctx.pc = ret;
+                return Ok(Status::Running);
+            },
+            Op::Return if ctx.forward => if let Some(caller) = eval::pop_call(ctx) {
+                self.stacks[caller].push(pc);
+                ctx.pc = caller + 1;
+                return Ok(Status::Running);
+            },
+            Op::Return => (),
+            Op::Forwards => ctx.forward = true,
+            Op::Backwards => ctx.forward = false,
+            Op::Reverse => ctx.forward = !ctx.forward,
+            Op::Halt => return Ok(Status::Halted),
+            Op::Io(operand) => {
+                if ctx.forward {
+                    eval::input(operand.place(ctx)?, ctx)?;
+                }
+                else {
+                    eval::output(operand.read(ctx)?, ctx)?;
+                }
+            }
+        }
+        ctx.pc = if ctx.pc == pc {
+            self.next(ctx)
+        }
+        else {
+            advance(ctx.pc, ctx.forward)
+        };
+        Ok(Status::Running)
+    }
+
+    // the line after pc, except that going backwards off the first line of
+    // the newest callee returns out to the line before its call
+    fn next(&self, ctx: &mut Context) -> usize {
+        let entry = ctx.calls.last().and_then(|&caller| match self.code.insns[caller].op {
+            Op::Call(entry) => Some(entry),
+            _ => None
+        });
+        if !ctx.forward && entry == Some(ctx.pc) {
+            return eval::pop_call(ctx).unwrap_or(0).wrapping_sub(1);
+        }
+        advance(ctx.pc, ctx.forward)
+    }
+
+    fn goto(&mut self, pc: usize, target: Target, ctx: &mut Context) -> Status {
+        self.stacks[target.line].push(pc);
+        ctx.pc = if target.bare {
+            advance(target.line, ctx.forward)
+        }
+        else {
+            target.line
+        };
+        Status::Running
+    }
+
+    // run until the program halts or runs off either end
+    pub fn run(&mut self, ctx: &mut Context) -> Result<Status, RuntimeError> {
+        loop {
+            let result = self.step(ctx);
+            match result {
+                Ok(Status::Running) => continue,
+                _ => {
+                    ctx.output.flush().map_err(|_| RuntimeError::OutputFailed{pc: ctx.pc})?;
+                    return result;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use crate::ast;
+
+// a line entered going one way, every line can be entered in both
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Node {
+    pub line: usize,
+    pub forward: bool
+}
+
+// where an edge leaves the program, or the node it goes to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exit {
+    Node(Node),
+    Finish,
+    Halt
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    // on to the next line in the current direction
+    Next,
+    // the condition on the line didn't hold
+    Skip,
+    // a jump or from to its label, or a call into its callee
+    Jump,
+    // a bare jump or from back to a line that jumped to it, or a way out of
+    // a callee
+    Return,
+    // forwards, backwards or reverse changed direction
+    Turn
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edge {
+    pub from: Node,
+    pub to: Exit,
+    pub kind: EdgeKind,
+    // the if expression this edge is taken under, for conditional lines
+    pub cond: Option<String>
+}
+
+// the lines of a program reachable from the start, in both directions
+#[derive(Debug, Clone, Default)]
+pub struct Cfg {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>
+}
+
+// where moving one line on from line leads, running off either end or back
+// onto line 0 finishes the program
+fn next(line: usize, forward: bool, len: usize) -> Exit {
+    let line = if forward { line + 1 } else { line.wrapping_sub(1) };
+    match line {
+        0 if !forward => Exit::Finish,
+        _ if line >= len => Exit::Finish,
+        _ => Exit::Node(Node{line, forward})
+    }
+}
+
+// whether a condition holds when all that's known is the direction
+fn holds(expr: &ast::Expr, forward: bool) -> Option<bool> {
+    match expr {
+        ast::Expr::Forwards => Some(forward),
+        ast::Expr::Backwards => Some(!forward),
+        ast::Expr::And(left, right) => match (holds(left, forward), holds(right, forward)) {
+            (Some(false), _) | (_, Some(false)) => Some(false),
+            (Some(true), Some(true)) => Some(true),
+            _ => None
+        },
+        ast::Expr::Or(left, right) => match (holds(left, forward), holds(right, forward)) {
+            (Some(true), _) | (_, Some(true)) => Some(true),
+            (Some(false), Some(false)) => Some(false),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// everything the line can do when its condition holds
+fn effects(lines: &[ast::Line], labels: &HashMap<String, usize>, callers: &[Vec<usize>], calls: &[Vec<usize>], node: Node) -> Vec<(Exit, EdgeKind)> {
+    let Node{line, forward} = node;
+    let len = lines.len();
+    let goto = |label: &str| {
+        let target = labels[label];
+        match lines[target].inst {
+            ast::Instruction::Jump(None) | ast::Instruction::From(None) => next(target, forward, len),
+            _ => Exit::Node(Node{line: target, forward})
+        }
+    };
+    // a bare jump or from goes on from the line that jumped to it, or from
+    // itself if nothing did
+    let back = || {
+        let mut exits: Vec<_> = callers[line].iter().map(|&from| (next(from, forward, len), EdgeKind::Return)).collect();
+        exits.push((next(line, forward, len), EdgeKind::Next));
+        exits
+    };
+    let mut exits = match &lines[line].inst {
+        ast::Instruction::Jump(Some(label)) if forward => vec![(goto(label), EdgeKind::Jump)],
+        ast::Instruction::From(Some(label)) if !forward => vec![(goto(label), EdgeKind::Jump)],
+        ast::Instruction::Jump(None) if forward => back(),
+        ast::Instruction::From(None) if !forward => back(),
+        ast::Instruction::Forwards if !forward => vec![(next(line, true, len), EdgeKind::Turn)],
+        ast::Instruction::Backwards if forward => vec![(next(line, false, len), EdgeKind::Turn)],
+        ast::Instruction::Reverse => vec![(next(line, !forward, len), EdgeKind::Turn)],
+        ast::Instruction::Halt => vec![(Exit::Halt, EdgeKind::Next)],
+        ast::Instruction::Call(label) if forward => vec![(Exit::Node(Node{line: labels[label], forward}), EdgeKind::Jump)],
+        // going backwards a call re-enters its callee through any return,
+        // the call stack decides which one
+        ast::Instruction::Call(_) => lines.iter().enumerate()
+            .filter(|(_, line)| line.inst == ast::Instruction::Return)
+            .map(|(ret, _)| (Exit::Node(Node{line: ret, forward}), EdgeKind::Jump))
+            .chain([(next(line, forward, len), EdgeKind::Next)])
+            .collect(),
+        ast::Instruction::Return if forward => calls.iter().flatten()
+            .map(|&call| (next(call, forward, len), EdgeKind::Return))
+            .chain([(next(line, forward, len), EdgeKind::Next)])
+            .collect(),
+        _ => vec![(next(line, forward, len), EdgeKind::Next)]
+    };
+    // stepping back off the first line of a callee returns out of it
+    if exits.contains(&(next(line, false, len), EdgeKind::Next)) && !forward {
+        exits.extend(calls[line].iter().map(|&call| (next(call, forward, len), EdgeKind::Return)));
+    }
+    exits
+}
+
+// build the graph by walking out from line 0 going forwards
+pub fn build(lines: &[ast::Line], labels: &HashMap<String, usize>) -> Cfg {
+    let mut cfg = Cfg::default();
+    if lines.is_empty() {
+        return cfg;
+    }
+    // lines that jump or from to each line, and so can end up on its stack
+    let mut callers = vec![Vec::new(); lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        if let ast::Instruction::Jump(Some(label)) | ast::Instruction::From(Some(label)) = &line.inst {
+            callers[labels[label]].push(i);
+        }
+    }
+    // call lines by the line they call
+    let mut calls = vec![Vec::new(); lines.len()];
+    for (i, line) in lines.iter().enumerate() {
+        if let ast::Instruction::Call(label) = &line.inst {
+            calls[labels[label]].push(i);
+        }
+    }
+
+    let start = Node{line: 0, forward: true};
+    let mut seen = HashSet::from([start]);
+    let mut queue = VecDeque::from(vec![start]);
+    while let Some(node) = queue.pop_front() {
+        cfg.nodes.push(node);
+        let skip = Edge{from: node, to: next(node.line, node.forward, lines.len()), kind: EdgeKind::Skip, cond: None};
+        // only conditions the direction doesn't settle label their edges
+        let edges: Vec<Edge> = match &lines[node.line].cond {
+            Some(expr) if holds(expr, node.forward) == Some(false) => vec![Edge{kind: EdgeKind::Next, ..skip}],
+            Some(expr) if holds(expr, node.forward).is_none() => {
+                let cond = Some(expr.to_string());
+                let mut edges: Vec<Edge> = effects(lines, labels, &callers, &calls, node).into_iter()
+                    .map(|(to, kind)| Edge{from: node, to, kind, cond: cond.clone()})
+                    .collect();
+                edges.push(Edge{cond, ..skip});
+                edges
+            },
+            _ => effects(lines, labels, &callers, &calls, node).into_iter()
+                .map(|(to, kind)| Edge{from: node, to, kind, cond: None})
+                .collect()
+        };
+        for edge in edges {
+            if let Exit::Node(to) = edge.to {
+                if seen.insert(to) {
+                    queue.push_back(to);
+                }
+            }
+            cfg.edges.push(edge);
+        }
+    }
+    cfg.nodes.sort();
+    cfg
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_id(node: Node) -> String {
+    format!("{}{}", if node.forward { "f" } else { "b" }, node.line)
+}
+
+fn exit_id(exit: Exit) -> String {
+    match exit {
+        Exit::Node(node) => node_id(node),
+        Exit::Finish => "finish".to_string(),
+        Exit::Halt => "halt".to_string()
+    }
+}
+
+// render as graphviz, with a cluster for each direction and the lines
+// that change direction drawn as diamonds
+pub fn to_dot(cfg: &Cfg, lines: &[ast::Line]) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph moonwalk {{").unwrap();
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+    writeln!(out, "    start [shape=point];").unwrap();
+    writeln!(out, "    finish [shape=doublecircle];").unwrap();
+    writeln!(out, "    halt [shape=octagon];").unwrap();
+    for (forward, name) in [(true, "forwards"), (false, "backwards")] {
+        if !cfg.nodes.iter().any(|node| node.forward == forward) {
+            continue;
+        }
+        writeln!(out, "    subgraph cluster_{} {{", name).unwrap();
+        writeln!(out, "        label=\"{}\";", name).unwrap();
+        for node in cfg.nodes.iter().filter(|node| node.forward == forward) {
+            let line = &lines[node.line];
+            let shape = match line.inst {
+                ast::Instruction::Forwards | ast::Instruction::Backwards | ast::Instruction::Reverse => ", shape=diamond",
+                _ => ""
+            };
+            let text = format!("{}: {}", line.span.line, line);
+            writeln!(out, "        {} [label=\"{}\"{}];", node_id(*node), escape(&text), shape).unwrap();
+        }
+        writeln!(out, "    }}").unwrap();
+    }
+    if let Some(first) = cfg.nodes.iter().find(|node| node.line == 0 && node.forward) {
+        writeln!(out, "    start -> {};", node_id(*first)).unwrap();
+    }
+    for edge in &cfg.edges {
+        let mut attrs = Vec::new();
+        match (&edge.cond, edge.kind) {
+            (Some(cond), EdgeKind::Skip) => attrs.push(format!("label=\"unless {}\"", escape(cond))),
+            (Some(cond), _) => attrs.push(format!("label=\"if {}\"", escape(cond))),
+            _ => ()
+        }
+        match edge.kind {
+            EdgeKind::Skip => attrs.push("style=dashed".to_string()),
+            EdgeKind::Jump => attrs.push("style=bold".to_string()),
+            EdgeKind::Return => attrs.push("style=dotted".to_string()),
+            EdgeKind::Turn => attrs.push("color=red".to_string()),
+            EdgeKind::Next => ()
+        }
+        let attrs = if attrs.is_empty() { String::new() } else { format!(" [{}]", attrs.join(", ")) };
+        writeln!(out, "    {} -> {}{};", node_id(edge.from), exit_id(edge.to), attrs).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
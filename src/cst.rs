@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use crate::ast::{self, Span};
+use crate::diagnostic::Diagnostic;
+use crate::lex::{self, SpannedToken};
+use crate::parse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TriviaKind {
+    // spaces and tabs, never newlines since those end a line
+    Whitespace,
+    Comment
+}
+
+// source text the grammar ignores
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub text: String,
+    pub span: Span
+}
+
+// a token with its exact text and the trivia in front of it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub token: ast::Token,
+    pub text: String,
+    pub span: Span,
+    pub leading: Vec<Trivia>
+}
+
+// what a line is to macros::expand, which runs before the grammar sees
+// the source. Only code lines have an ast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineKind {
+    Code,
+    // `macro name params`, the lines up to its endmacro, and the endmacro
+    Macro,
+    Body,
+    EndMacro,
+    // a line naming a macro, after any label
+    Call
+}
+
+// one line of source, up to and including the newlines that end it. Blank
+// lines belong to the newlines token before them
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Line {
+    pub tokens: Vec<Token>
+}
+
+impl Line {
+    // the grammar's tokens, without the newlines that end the line
+    pub fn code(&self) -> impl Iterator<Item = &Token> {
+        self.tokens.iter().filter(|t| !matches!(t.token, ast::Token::Newlines(_)))
+    }
+
+    // a trailing comment, or a line that is only a comment
+    pub fn comment(&self) -> Option<&Trivia> {
+        self.tokens.iter().flat_map(|t| &t.leading).find(|t| t.kind == TriviaKind::Comment)
+    }
+}
+
+// a lossless tree of the source, writing it back out gives the exact input
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cst {
+    pub lines: Vec<Line>,
+    // whatever follows the last token
+    pub trailing: Vec<Trivia>
+}
+
+impl Cst {
+    pub fn parse(source: &str) -> Result<Cst, Diagnostic> {
+        let mut cst = Cst::default();
+        let mut line = Line::default();
+        let mut trivia = Vec::new();
+        let mut pos = 0;
+        for SpannedToken{token, span} in lex::lex_with_comments(source)? {
+            // the lexer skips plain spaces without a token, and they can't
+            // cross a line, so the gap ends where this token starts
+            if span.start > pos {
+                let len = span.start - pos;
+                let gap = Span{start: pos, end: span.start, line: span.line, column: span.column - len};
+                trivia.push(Trivia{kind: TriviaKind::Whitespace, text: source[pos..span.start].to_string(), span: gap});
+            }
+            pos = span.end;
+            let text = source[span.start..span.end].to_string();
+            match token {
+                ast::Token::Nop => trivia.push(Trivia{kind: TriviaKind::Whitespace, text, span}),
+                ast::Token::Comment(_) => trivia.push(Trivia{kind: TriviaKind::Comment, text, span}),
+                token => {
+                    let ends = matches!(token, ast::Token::Newlines(_));
+                    line.tokens.push(Token{token, text, span, leading: std::mem::take(&mut trivia)});
+                    if ends {
+                        cst.lines.push(std::mem::take(&mut line));
+                    }
+                }
+            }
+        }
+        if pos < source.len() {
+            let (line_no, column) = match trivia.last() {
+                Some(t) => (t.span.line, t.span.column + t.text.chars().count()),
+                None => last_position(&cst, &line)
+            };
+            let gap = Span{start: pos, end: source.len(), line: line_no, column};
+            trivia.push(Trivia{kind: TriviaKind::Whitespace, text: source[pos..].to_string(), span: gap});
+        }
+        // the last line may not end in a newline
+        if !line.tokens.is_empty() {
+            cst.lines.push(line);
+        }
+        cst.trailing = trivia;
+        Ok(cst)
+    }
+
+    // every token in source order
+    pub fn tokens(&self) -> impl Iterator<Item = &Token> {
+        self.lines.iter().flat_map(|line| &line.tokens)
+    }
+
+    pub fn to_source(&self) -> String {
+        let mut out = String::new();
+        for token in self.tokens() {
+            for trivia in &token.leading {
+                out += &trivia.text;
+            }
+            out += &token.text;
+        }
+        for trivia in &self.trailing {
+            out += &trivia.text;
+        }
+        out
+    }
+
+    // the kind of every line, macros can be called before they're defined
+    pub fn kinds(&self) -> Vec<LineKind> {
+        let names: HashSet<&str> = self.lines.iter().filter_map(|line| {
+            let mut code = line.code();
+            match (code.next().map(|t| &t.token), code.next().map(|t| &t.token)) {
+                (Some(ast::Token::Macro), Some(ast::Token::Identifier(name))) => Some(name.as_str()),
+                _ => None
+            }
+        }).collect();
+        let mut inside = false;
+        self.lines.iter().map(|line| {
+            let code: Vec<_> = line.code().map(|t| &t.token).collect();
+            let at = match code.as_slice() {
+                [ast::Token::Identifier(_), ast::Token::Label, ..] => 2,
+                _ => 0
+            };
+            match (code.first(), code.get(at), inside) {
+                (Some(ast::Token::Macro), _, false) => {
+                    inside = true;
+                    LineKind::Macro
+                },
+                (Some(ast::Token::EndMacro), _, true) => {
+                    inside = false;
+                    LineKind::EndMacro
+                },
+                (_, _, true) => LineKind::Body,
+                (_, Some(ast::Token::Identifier(name)), false) if names.contains(name.as_str()) => LineKind::Call,
+                _ => LineKind::Code
+            }
+        }).collect()
+    }
+
+    // the ast, parsed from the tokens that aren't trivia on code lines
+    pub fn to_ast(&self) -> Result<Vec<ast::Line>, Diagnostic> {
+        let tokens = self.lines.iter().zip(self.kinds())
+            .filter(|(_, kind)| *kind == LineKind::Code)
+            .flat_map(|(line, _)| &line.tokens)
+            .map(|t| SpannedToken{token: t.token.clone(), span: t.span})
+            .collect();
+        parse::parse(tokens)
+    }
+}
+
+// where the text after the last token starts
+fn last_position(cst: &Cst, line: &Line) -> (usize, usize) {
+    let last = line.tokens.last().or_else(|| cst.lines.last().and_then(|l| l.tokens.last()));
+    match last {
+        None => (1, 1),
+        Some(Token{token: ast::Token::Newlines(n), span, ..}) => (span.line + n, 1),
+        Some(Token{span, text, ..}) => (span.line, span.column + text.chars().count())
+    }
+}
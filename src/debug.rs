@@ -0,0 +1,421 @@
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use crate::ast;
+use crate::eval::{Place, Status};
+use crate::machine::Machine;
+
+const HELP: &str = "\
+commands:
+  break <label|line>    stop before executing a labeled line or source line
+  delete <label|line>   remove a breakpoint
+  breakpoints           list breakpoints
+  step [n]              execute n lines (default 1)
+  continue              run until a breakpoint or the program stops
+  turn                  run until the execution direction changes
+  back [n]              undo n interpreter steps (default 1)
+  rcontinue             undo steps until a breakpoint or the start
+  goto <step>           travel to a step number, replaying or undoing
+  history               print the current step number and log length
+  regs                  print registers, direction and pc
+  mem <addr> [end]      print memory from addr up to end (exclusive), at
+                        most 4096 cells at a time
+  stacks                print the jump stack of every jump/from line, the
+                        call stack and the value stack of every place move
+                        has saved
+  where                 print the line about to execute
+  quit                  leave the debugger
+";
+
+// the most cells one mem command prints, sparse memory has a value at every
+// address so a range is never cut short
+pub const MEM_LIMIT: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Label(String),
+    Line(usize)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(Location),
+    Delete(Location),
+    Breakpoints,
+    Step(usize),
+    Continue,
+    Turn,
+    Back(usize),
+    ReverseContinue,
+    Goto(usize),
+    History,
+    Regs,
+    Mem(usize, usize),
+    Stacks,
+    Where,
+    Help,
+    Quit
+}
+
+fn parse_num(s: &str) -> Result<usize, String> {
+    let parsed = match s.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => s.parse()
+    };
+    parsed.map_err(|_| format!("expected a number, found {}", s))
+}
+
+fn parse_location(arg: Option<&str>) -> Result<Location, String> {
+    match arg {
+        None => Err("expected a label or line number".to_string()),
+        Some(arg) => match arg.parse() {
+            Ok(line) => Ok(Location::Line(line)),
+            Err(_) => Ok(Location::Label(arg.to_string()))
+        }
+    }
+}
+
+pub fn parse_command(input: &str) -> Result<Command, String> {
+    let mut words = input.split_whitespace();
+    let cmd = match words.next() {
+        Some(cmd) => cmd,
+        None => return Err("empty command".to_string())
+    };
+    Ok(match cmd {
+        "break" | "b" => Command::Break(parse_location(words.next())?),
+        "delete" | "d" => Command::Delete(parse_location(words.next())?),
+        "breakpoints" | "bl" => Command::Breakpoints,
+        "step" | "s" => match words.next() {
+            Some(n) => Command::Step(parse_num(n)?),
+            None => Command::Step(1)
+        },
+        "continue" | "c" => Command::Continue,
+        "turn" | "t" => Command::Turn,
+        "back" | "bs" => match words.next() {
+            Some(n) => Command::Back(parse_num(n)?),
+            None => Command::Back(1)
+        },
+        "rcontinue" | "rc" => Command::ReverseContinue,
+        "goto" | "g" => match words.next() {
+            Some(n) => Command::Goto(parse_num(n)?),
+            None => return Err("expected a step number".to_string())
+        },
+        "history" => Command::History,
+        "regs" | "r" => Command::Regs,
+        "mem" | "m" => {
+            let start = match words.next() {
+                Some(n) => parse_num(n)?,
+                None => return Err("expected an address".to_string())
+            };
+            let end = match words.next() {
+                Some(n) => parse_num(n)?,
+                None => start.checked_add(1).ok_or_else(|| format!("no memory after {:#x}", start))?
+            };
+            // end is exclusive, so it has to be past start to show anything
+            if end <= start {
+                return Err(format!("end {:#x} has to come after start {:#x}", end, start));
+            }
+            if end - start > MEM_LIMIT {
+                return Err(format!("{} cells is too many to print, at most {} at a time", end - start, MEM_LIMIT));
+            }
+            Command::Mem(start, end)
+        },
+        "stacks" => Command::Stacks,
+        "where" | "w" => Command::Where,
+        "help" | "h" | "?" => Command::Help,
+        "quit" | "q" => Command::Quit,
+        _ => return Err(format!("unknown command {}, try help", cmd))
+    })
+}
+
+// why execution stopped when the debugger handed control back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Turned,
+    Exited(Status),
+    // stepped back to the first step
+    Start
+}
+
+pub struct Debugger {
+    machine: Machine,
+    source: String,
+    // indices into the program's lines
+    breakpoints: BTreeSet<usize>
+}
+
+impl Debugger {
+    pub fn new(mut machine: Machine, source: &str) -> Debugger {
+        machine.record_history();
+        Debugger{machine, source: source.to_string(), breakpoints: BTreeSet::new()}
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    // map a label or source line number to the index of the line it names
+    pub fn resolve(&self, location: &Location) -> Result<usize, String> {
+        match location {
+            Location::Label(label) => match self.machine.context().labels.get(label) {
+                Some(index) => Ok(*index),
+                None => Err(format!("no label named {}", label))
+            },
+            Location::Line(lineno) => {
+                match self.machine.lines().iter().position(|line| line.span.line >= *lineno) {
+                    Some(index) => Ok(index),
+                    None => Err(format!("no instruction on or after line {}", lineno))
+                }
+            }
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, location: &Location) -> Result<usize, String> {
+        let index = self.resolve(location)?;
+        self.breakpoints.insert(index);
+        Ok(index)
+    }
+
+    pub fn remove_breakpoint(&mut self, location: &Location) -> Result<usize, String> {
+        let index = self.resolve(location)?;
+        match self.breakpoints.remove(&index) {
+            true => Ok(index),
+            false => Err("no breakpoint there".to_string())
+        }
+    }
+
+    // execute up to n lines, stopping early if the program does
+    pub fn step(&mut self, n: usize) -> Stop {
+        for _ in 0..n {
+            match self.machine.step() {
+                Status::Running => (),
+                status => return Stop::Exited(status)
+            }
+        }
+        Stop::Stepped
+    }
+
+    // step at least once, then keep going until should_stop says so
+    fn run_until<F>(&mut self, mut should_stop: F) -> Stop
+        where F: FnMut(&Machine, &BTreeSet<usize>) -> Option<Stop>
+    {
+        loop {
+            if let Stop::Exited(status) = self.step(1) {
+                return Stop::Exited(status);
+            }
+            if let Some(stop) = should_stop(&self.machine, &self.breakpoints) {
+                return stop;
+            }
+        }
+    }
+
+    // undo up to n steps
+    pub fn back(&mut self, n: usize) -> Stop {
+        for _ in 0..n {
+            if !self.machine.step_back() {
+                return Stop::Start;
+            }
+        }
+        Stop::Stepped
+    }
+
+    pub fn reverse_continue(&mut self) -> Stop {
+        loop {
+            if !self.machine.step_back() {
+                return Stop::Start;
+            }
+            if let Some(stop) = Debugger::at_breakpoint(&self.machine, &self.breakpoints) {
+                return stop;
+            }
+        }
+    }
+
+    pub fn goto(&mut self, step: usize) -> Stop {
+        match self.machine.rewind_to(step) {
+            Status::Running => Stop::Stepped,
+            status => Stop::Exited(status)
+        }
+    }
+
+    fn at_breakpoint(machine: &Machine, breakpoints: &BTreeSet<usize>) -> Option<Stop> {
+        match breakpoints.contains(&machine.pc()) {
+            true => Some(Stop::Breakpoint(machine.pc())),
+            false => None
+        }
+    }
+
+    pub fn continue_(&mut self) -> Stop {
+        self.run_until(Debugger::at_breakpoint)
+    }
+
+    // run until the direction flips, breakpoints still stop us
+    pub fn turn(&mut self) -> Stop {
+        let forward = self.machine.forward();
+        self.run_until(|machine, breakpoints| {
+            if machine.forward() != forward {
+                return Some(Stop::Turned);
+            }
+            Debugger::at_breakpoint(machine, breakpoints)
+        })
+    }
+
+    fn source_line(&self, line: &ast::Line) -> &str {
+        self.source.lines().nth(line.span.line - 1).unwrap_or("").trim()
+    }
+
+    fn write_where<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let direction = if self.machine.forward() { "forwards" } else { "backwards" };
+        match self.machine.lines().get(self.machine.pc()) {
+            Some(line) => writeln!(out, "line {} (pc {}, {}): {}",
+                line.span.line, self.machine.pc(), direction, self.source_line(line)),
+            None => writeln!(out, "pc {} ({}) is past the end of the program",
+                self.machine.pc(), direction)
+        }
+    }
+
+    fn write_stop<W: Write>(&self, stop: Stop, out: &mut W) -> io::Result<()> {
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint(_) => writeln!(out, "breakpoint")?,
+            Stop::Turned => writeln!(out, "direction changed")?,
+            Stop::Start => writeln!(out, "at the first step")?,
+            Stop::Exited(Status::Halted) => return writeln!(out, "program halted"),
+            Stop::Exited(Status::Trapped) => {
+                if let Some(diagnostic) = self.machine.error_diagnostic() {
+                    write!(out, "{}", diagnostic.render(&self.source))?;
+                }
+            },
+            Stop::Exited(_) => return writeln!(out, "program finished")
+        }
+        self.write_where(out)
+    }
+
+    fn write_breakpoint<W: Write>(&self, index: usize, out: &mut W) -> io::Result<()> {
+        let line = &self.machine.lines()[index];
+        let label = match &line.label {
+            Some(label) => format!(" ({})", label),
+            None => String::new()
+        };
+        writeln!(out, "line {}{}: {}", line.span.line, label, self.source_line(line))
+    }
+
+    // run one command, returns false when the user wants to quit
+    pub fn execute<W: Write>(&mut self, cmd: Command, out: &mut W) -> io::Result<bool> {
+        match cmd {
+            Command::Break(location) => match self.add_breakpoint(&location) {
+                Ok(index) => {
+                    write!(out, "breakpoint set at ")?;
+                    self.write_breakpoint(index, out)?;
+                },
+                Err(e) => writeln!(out, "{}", e)?
+            },
+            Command::Delete(location) => match self.remove_breakpoint(&location) {
+                Ok(_) => writeln!(out, "breakpoint removed")?,
+                Err(e) => writeln!(out, "{}", e)?
+            },
+            Command::Breakpoints => {
+                for index in self.breakpoints.iter() {
+                    self.write_breakpoint(*index, out)?;
+                }
+            },
+            Command::Step(n) => {
+                let stop = self.step(n);
+                self.write_stop(stop, out)?;
+            },
+            Command::Continue => {
+                let stop = self.continue_();
+                self.write_stop(stop, out)?;
+            },
+            Command::Turn => {
+                let stop = self.turn();
+                self.write_stop(stop, out)?;
+            },
+            Command::Back(n) => {
+                let stop = self.back(n);
+                self.write_stop(stop, out)?;
+            },
+            Command::ReverseContinue => {
+                let stop = self.reverse_continue();
+                self.write_stop(stop, out)?;
+            },
+            Command::Goto(step) => {
+                let stop = self.goto(step);
+                self.write_stop(stop, out)?;
+            },
+            Command::History => {
+                if let Some(history) = self.machine.history() {
+                    writeln!(out, "step {} of {} recorded", history.position(), history.len())?;
+                }
+            },
+            Command::Regs => {
+                let ctx = self.machine.context();
+                writeln!(out, "A = {}\nB = {}\nC = {}\nD = {}", ctx.a, ctx.b, ctx.c, ctx.d)?;
+                writeln!(out, "forward = {}\npc = {}", ctx.forward, ctx.pc)?;
+            },
+            Command::Mem(start, end) => {
+                let mem = self.machine.mem();
+                for addr in start..end {
+                    match mem.get(addr) {
+                        Some(val) => writeln!(out, "{:#06x}: {}", addr, val)?,
+                        None => {
+                            writeln!(out, "{:#06x}: out of bounds", addr)?;
+                            break;
+                        }
+                    }
+                }
+            },
+            Command::Stacks => {
+                // stacks hold the pc of the line that jumped here
+                for (pc, line) in self.machine.lines().iter().enumerate() {
+                    if !line.stack.is_empty() {
+                        writeln!(out, "line {} (pc {}): {:?}", line.span.line, pc, line.stack)?;
+                    }
+                }
+                // the calls that haven't returned, newest last
+                if !self.machine.context().calls.is_empty() {
+                    let lines = self.machine.context().calls.iter().map(|&pc| self.machine.lines()[pc].span.line);
+                    writeln!(out, "calls: {:?}", lines.collect::<Vec<_>>())?;
+                }
+                // and value stacks the old values move overwrote
+                for (place, values) in &self.machine.context().values {
+                    match place {
+                        _ if values.is_empty() => (),
+                        Place::Reg(reg) => writeln!(out, "{}: {:?}", reg, values)?,
+                        Place::Mem(addr) => writeln!(out, "{:#06x}: {:?}", addr, values)?,
+                        Place::Literal(_) => ()
+                    }
+                }
+            },
+            Command::Where => self.write_where(out)?,
+            Command::Help => write!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    pub fn repl<R: BufRead, W: Write>(&mut self, input: R, mut out: W) -> io::Result<()> {
+        self.write_where(&mut out)?;
+        write!(out, "(mwdb) ")?;
+        out.flush()?;
+        for line in input.lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                let keep_going = match parse_command(&line) {
+                    Ok(cmd) => self.execute(cmd, &mut out)?,
+                    Err(e) => {
+                        writeln!(out, "{}", e)?;
+                        true
+                    }
+                };
+                if !keep_going {
+                    return Ok(());
+                }
+            }
+            write!(out, "(mwdb) ")?;
+            out.flush()?;
+        }
+        writeln!(out)
+    }
+}
@@ -0,0 +1,204 @@
+use std::fmt;
+
+use crate::ast::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning")
+        }
+    }
+}
+
+// somewhere else in the source that explains a diagnostic, like the call
+// of the macro a line came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Note {
+    pub span: Span,
+    pub message: String
+}
+
+// a problem found in a source file, located by span when there is one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<String>,
+    pub span: Option<Span>,
+    pub message: String,
+    pub notes: Vec<Note>
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, span: impl Into<Option<Span>>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic{severity, file: None, span: span.into(), message: message.into(), notes: Vec::new()}
+    }
+
+    pub fn error(span: impl Into<Option<Span>>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, span, message)
+    }
+
+    pub fn warning(span: impl Into<Option<Span>>, message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, span, message)
+    }
+
+    pub fn with_note(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.notes.push(Note{span, message: message.into()});
+        self
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.span.map(|s| s.line)
+    }
+
+    pub fn column(&self) -> Option<usize> {
+        self.span.map(|s| s.column)
+    }
+
+    fn file(&self) -> &str {
+        self.file.as_deref().unwrap_or("<source>")
+    }
+
+    fn location(&self) -> Option<String> {
+        match (&self.file, self.span) {
+            (_, Some(span)) => Some(format!("{}:{}:{}", self.file(), span.line, span.column)),
+            (Some(file), None) => Some(file.clone()),
+            (None, None) => None
+        }
+    }
+
+    // render like rustc, with the offending source line and a caret
+    // underline, then the same for each note
+    pub fn render(&self, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+        if let Some(location) = self.location() {
+            out += &format!(" --> {}\n", location);
+        }
+        if let Some(span) = self.span {
+            out += &snippet(source, span);
+        }
+        for note in &self.notes {
+            out += &format!("note: {}\n", note.message);
+            out += &format!(" --> {}:{}:{}\n", self.file(), note.span.line, note.span.column);
+            out += &snippet(source, note.span);
+        }
+        out
+    }
+}
+
+fn snippet(source: &str, span: Span) -> String {
+    let start = span.start.min(source.len());
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+    let text = source[line_start..line_end].trim_end_matches('\r');
+    let gutter = span.line.to_string();
+    let pad = " ".repeat(gutter.len());
+    // underline at least one column, and stop at the end of the line
+    let indent = source[line_start..start].chars().count();
+    let width = source[start..span.end.clamp(start, line_end)].chars().count().max(1);
+    let mut out = format!("{} |\n", pad);
+    out += &format!("{} | {}\n", gutter, text);
+    out += &format!("{} | {}{}\n", pad, " ".repeat(indent), "^".repeat(width));
+    out
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.location() {
+            Some(location) => write!(f, "{}: {}: {}", location, self.severity, self.message)?,
+            None => write!(f, "{}: {}", self.severity, self.message)?
+        }
+        for note in &self.notes {
+            write!(f, "\n{}:{}:{}: note: {}", self.file(), note.span.line, note.span.column, note.message)?;
+        }
+        Ok(())
+    }
+}
+
+// everything the front end had to say about a source file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Diagnostics(Vec<Diagnostic>);
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics(Vec::new())
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.0.push(diagnostic);
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.0.iter().any(|d| d.severity == Severity::Error)
+    }
+
+    // attribute every diagnostic to a file name for rendering
+    pub fn set_file(&mut self, file: &str) {
+        for diagnostic in self.0.iter_mut() {
+            diagnostic.file = Some(file.to_string());
+        }
+    }
+
+    pub fn render(&self, source: &str) -> String {
+        self.0.iter().map(|d| d.render(source)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+impl From<Diagnostic> for Diagnostics {
+    fn from(diagnostic: Diagnostic) -> Diagnostics {
+        Diagnostics(vec![diagnostic])
+    }
+}
+
+impl Extend<Diagnostic> for Diagnostics {
+    fn extend<I: IntoIterator<Item = Diagnostic>>(&mut self, diagnostics: I) {
+        self.0.extend(diagnostics);
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diagnostic in &self.0 {
+            writeln!(f, "{}", diagnostic)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Diagnostics {}
@@ -1,68 +1,153 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::str;
-use std::io::{self, Read};
+use std::io::{self, Read, Write};
 
 use crate::ast;
+use crate::history::Effect;
+use crate::memory::Memory;
+use crate::word::Word;
 
 pub struct Context {
     pub a: usize,
     pub b: usize,
     pub c: usize,
     pub d: usize,
-    pub mem: [usize; 65536],
+    pub mem: Memory,
     pub forward: bool,
     pub pc: usize,
-    pub labels: HashMap<String, usize>
+    pub labels: HashMap<String, usize>,
+    // values move has overwritten, kept per register or address so going
+    // the other way can put them back
+    pub values: BTreeMap<Place, Vec<usize>>,
+    // lines of the calls that haven't returned yet, shared by every call
+    pub calls: Vec<usize>,
+    pub word: Word,
+    pub input: Box<dyn Read>,
+    pub output: Box<dyn Write>,
+    // when set, every state change made by a step is logged here
+    pub journal: Option<Vec<Effect>>
 }
 impl Context {
     pub fn new(labels: HashMap<String, usize>) -> Context {
-        return Context {
+        Context::with_io(labels, Box::new(io::stdin()), Box::new(io::stdout()))
+    }
+
+    pub fn with_io(labels: HashMap<String, usize>, input: Box<dyn Read>, output: Box<dyn Write>) -> Context {
+        Context {
             a: 0,
             b: 0,
             c: 0,
             d: 0,
-            mem: [0; 65536],
+            mem: Memory::default(),
             forward: true,
             pc: 0,
-            labels: labels
+            labels,
+            values: BTreeMap::new(),
+            calls: Vec::new(),
+            word: Word::default(),
+            input,
+            output,
+            journal: None
         }
     }
 }
 
-pub fn getc() -> char {
+pub fn getc(ctx: &mut Context) -> char {
     let mut buf: [u8; 1] = [0; 1];
-    return match io::stdin().read(&mut buf) {
-        Ok(_) => buf[0] as char,
-        Err(_) => 255 as char
+    let c = match ctx.input.read(&mut buf) {
+        Ok(0) => 0,
+        Ok(_) => buf[0],
+        Err(_) => 255
+    };
+    record(ctx, Effect::Input(c));
+    c as char
+}
+
+pub fn record(ctx: &mut Context, effect: Effect) {
+    if let Some(journal) = &mut ctx.journal {
+        journal.push(effect);
     }
 }
 
-//TODO: Check rules on deref
-pub fn deref_source(loc:&ast::Source, ctx: &Context) -> usize{
-    let location = source_to_val(loc, ctx);
-    ctx.mem[location]
+pub fn get_mem_val(addr: usize, ctx: &Context) -> Result<usize, RuntimeError>{
+    ctx.mem.get(addr).ok_or(RuntimeError::OutOfBounds{pc: ctx.pc, addr})
 }
 
+// where an operand lives once its derefs have been followed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Place {
+    Reg(ast::Register),
+    Mem(usize),
+    // only sources can be literals, writing to one is an error
+    Literal(usize)
+}
+
+// follow `depth` levels of indirection from `place`, each level reads the
+// current place and uses its value as the address of the next, so `*A` is
+// the cell A points to, `**A` the cell that one points to and `*0x9` the
+// cell whose address is stored at 0x9
+pub fn resolve(mut place: Place, depth: usize, ctx: &Context) -> Result<Place, RuntimeError> {
+    for _ in 0..depth {
+        place = Place::Mem(read_place(place, ctx)?);
+    }
+    Ok(place)
+}
 
-pub fn deref_dest(loc:&ast::Dest, ctx: &Context) -> usize{
-    let location = match loc{
-        ast::Dest::Reg(reg) => get_reg_val(reg, ctx),
-        ast::Dest::Addr(loc) => ctx.mem[*loc],
-        ast::Dest::Deref(dst) => deref_dest(loc, ctx),//TODO deref_dest function
+pub fn source_place(src: &ast::Source, ctx: &Context) -> Result<Place, RuntimeError> {
+    let mut depth = 0;
+    let mut src = src;
+    while let ast::Source::Deref(inner) = src {
+        depth += 1;
+        src = inner;
+    }
+    let place = match src {
+        ast::Source::Reg(reg) => Place::Reg(*reg),
+        ast::Source::Addr(addr) => Place::Mem(*addr),
+        ast::Source::Literal(val) => Place::Literal(*val),
+        ast::Source::Deref(_) => unreachable!()
+    };
+    resolve(place, depth, ctx)
+}
+
+pub fn dest_place(dest: &ast::Dest, ctx: &Context) -> Result<Place, RuntimeError> {
+    let mut depth = 0;
+    let mut dest = dest;
+    while let ast::Dest::Deref(inner) = dest {
+        depth += 1;
+        dest = inner;
+    }
+    let place = match dest {
+        ast::Dest::Reg(reg) => Place::Reg(*reg),
+        ast::Dest::Addr(addr) => Place::Mem(*addr),
+        ast::Dest::Deref(_) => unreachable!()
     };
-    location
+    resolve(place, depth, ctx)
 }
 
+pub fn read_place(place: Place, ctx: &Context) -> Result<usize, RuntimeError> {
+    match place {
+        Place::Reg(reg) => Ok(get_reg_val(&reg, ctx)),
+        Place::Mem(addr) => get_mem_val(addr, ctx),
+        Place::Literal(val) => Ok(ctx.word.truncate(val))
+    }
+}
 
-pub fn source_to_val(src: &ast::Source, ctx: &Context) -> usize{
-    match src{
-        ast::Source::Reg(reg) => get_reg_val(reg, ctx),
-        ast::Source::Addr(loc) => ctx.mem[*loc],
-        ast::Source::Literal(val) => *val,
-        ast::Source::Deref(src) => deref_source(src, ctx),//SHOULD I ERROR OR JUST REPEAT?
+pub fn write_place(place: Place, val: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    match place {
+        Place::Reg(reg) => {
+            set_reg_val(&reg, val, ctx);
+            Ok(())
+        },
+        Place::Mem(addr) => set_mem_val(addr, val, ctx),
+        Place::Literal(_) => Err(RuntimeError::WriteToLiteral{pc: ctx.pc})
     }
 }
 
+pub fn source_to_val(src: &ast::Source, ctx: &Context) -> Result<usize, RuntimeError>{
+    read_place(source_place(src, ctx)?, ctx)
+}
+
 pub fn get_reg_val(reg: &ast::Register, ctx: &Context) -> usize{
     match reg{
         ast::Register::A => ctx.a,
@@ -73,6 +158,8 @@ pub fn get_reg_val(reg: &ast::Register, ctx: &Context) -> usize{
 }
 
 pub fn set_reg_val(reg: &ast::Register, val: usize, ctx: &mut Context){
+    let old = get_reg_val(reg, ctx);
+    record(ctx, Effect::Reg(*reg, old, val));
     match reg{
         ast::Register::A => ctx.a = val,
         ast::Register::B => ctx.b = val,
@@ -80,28 +167,37 @@ pub fn set_reg_val(reg: &ast::Register, val: usize, ctx: &mut Context){
         ast::Register::D => ctx.d = val,
     }
 }
+pub fn set_mem_val(addr: usize, val: usize, ctx: &mut Context) -> Result<(), RuntimeError>{
+    let old = get_mem_val(addr, ctx)?;
+    record(ctx, Effect::Mem(addr, old, val));
+    ctx.mem.set(addr, val);
+    Ok(())
+}
+
+pub fn set_forward(forward: bool, ctx: &mut Context){
+    record(ctx, Effect::Direction(ctx.forward, forward));
+    ctx.forward = forward;
+}
 // this is a separate eval for expressions it needs
 // to be separate so that an expression can recursively evaualted
-pub fn eval_expr(expr: &ast::Expr, ctx: &Context) -> bool {
-    match expr{
+pub fn eval_expr(expr: &ast::Expr, ctx: &Context) -> Result<bool, RuntimeError> {
+    Ok(match expr{
         ast::Expr::Backwards => !ctx.forward,
         ast::Expr::Forwards => ctx.forward,
-        ast::Expr::Or(left, right) => eval_expr(left, &ctx) || eval_expr(right,&ctx),
-        ast::Expr::And(left, right) => eval_expr(left, &ctx) && eval_expr(right,&ctx),
-        ast::Expr::Gte(left, right) => eval_expr(left, &ctx) >= eval_expr(right,&ctx),
-        ast::Expr::Lte(left, right) => eval_expr(left, &ctx) <= eval_expr(right,&ctx),
-        ast::Expr::Gt(left, right) => eval_expr(left, &ctx) > eval_expr(right,&ctx),
-        ast::Expr::Lt(left, right) => eval_expr(left, &ctx) < eval_expr(right,&ctx),
-        ast::Expr::Eq(left, right) => eval_expr(left, &ctx) == eval_expr(right,&ctx),
-        ast::Expr::Lit(val) => source_to_val(val, ctx) != 0,
-    }
+        ast::Expr::Or(left, right) => eval_expr(left, ctx)? || eval_expr(right, ctx)?,
+        ast::Expr::And(left, right) => eval_expr(left, ctx)? && eval_expr(right, ctx)?,
+        ast::Expr::Gte(left, right) => source_to_val(left, ctx)? >= source_to_val(right, ctx)?,
+        ast::Expr::Lte(left, right) => source_to_val(left, ctx)? <= source_to_val(right, ctx)?,
+        ast::Expr::Gt(left, right) => source_to_val(left, ctx)? > source_to_val(right, ctx)?,
+        ast::Expr::Lt(left, right) => source_to_val(left, ctx)? < source_to_val(right, ctx)?,
+        ast::Expr::Eq(left, right) => source_to_val(left, ctx)? == source_to_val(right, ctx)?,
+    })
 }
 
-pub fn jump_to_label(label: &String , ctx: &mut Context){
-    match ctx.labels.get(label){
-        Some(lineno) => {ctx.pc = *lineno;},
-        None =>(),
-    }
+// scan_labels turns a jump to an unknown label into an error, so every
+// label a program can jump to is in the table
+pub fn jump_to_label(label: &str, ctx: &mut Context){
+    ctx.pc = ctx.labels[label];
 }
 
 pub enum ScanResult {
@@ -111,190 +207,423 @@ pub enum ScanResult {
     Ok(HashMap<String, usize>)
 }
 
-// scan a program to get label lookup table
-pub fn scan_labels(program: &Vec<ast::Line>) -> ScanResult {
+// the label a jump, from or call goes to, if it isn't bare
+pub fn jump_target(inst: &ast::Instruction) -> Option<&str> {
+    match inst {
+        ast::Instruction::Jump(Some(label)) | ast::Instruction::From(Some(label)) => Some(label),
+        ast::Instruction::Call(label) => Some(label),
+        _ => None
+    }
+}
+
+// scan a program to get label lookup table, and check it against every
+// jump, from and call. Labels are listed in the order they first appear
+pub fn scan_labels(program: &[ast::Line]) -> ScanResult {
     let mut map = HashMap::new();
-    let mut c =0;
-    for line in program{
-        match &line.label{
-            Some(lbl) => {
-                let cpy = lbl.clone();
-                if(map.contains_key(lbl)){
-                    return ScanResult::Duplicate(cpy);
-                }
-                map.insert(cpy, (c as usize));
-            },
-            None=>()
+    for (c, line) in program.iter().enumerate() {
+        if let Some(lbl) = &line.label {
+            let cpy = lbl.clone();
+            if map.contains_key(lbl) {
+                return ScanResult::Duplicate(cpy);
+            }
+            map.insert(cpy, c);
+        }
+    }
+    let mut missing: Vec<String> = Vec::new();
+    let mut used = HashSet::new();
+    for label in program.iter().filter_map(|line| jump_target(&line.inst)) {
+        if !map.contains_key(label) && !missing.iter().any(|m| m == label) {
+            missing.push(label.to_string());
+        }
+        used.insert(label);
+    }
+    if !missing.is_empty() {
+        return ScanResult::Missing(missing);
+    }
+    let unused: Vec<String> = program.iter()
+        .filter_map(|line| line.label.as_ref())
+        .filter(|label| !used.contains(label.as_str()))
+        .cloned()
+        .collect();
+    if unused.is_empty() {
+        ScanResult::Ok(map)
+    }
+    else {
+        ScanResult::Unused(unused, map)
+    }
+}
+
+
+// errors that stop a running program, pc is the line that caused it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeError {
+    Overflow{pc: usize},
+    OutOfBounds{pc: usize, addr: usize},
+    // io going forwards has nowhere to put what it read
+    WriteToLiteral{pc: usize},
+    // io going backwards can only print single byte characters
+    InvalidUtf8{pc: usize, value: usize},
+    OutputFailed{pc: usize}
+}
+
+impl RuntimeError {
+    pub fn pc(&self) -> usize {
+        match self {
+            RuntimeError::Overflow{pc} => *pc,
+            RuntimeError::OutOfBounds{pc, ..} => *pc,
+            RuntimeError::WriteToLiteral{pc} => *pc,
+            RuntimeError::InvalidUtf8{pc, ..} => *pc,
+            RuntimeError::OutputFailed{pc} => *pc
         }
-        c +=1;
     }
-    return ScanResult::Ok(map);
 }
 
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::Overflow{..} => write!(f, "arithmetic overflow"),
+            RuntimeError::OutOfBounds{addr, ..} => write!(f, "address {:#x} is out of bounds", addr),
+            RuntimeError::WriteToLiteral{..} => write!(f, "cannot place input into a literal"),
+            RuntimeError::InvalidUtf8{value, ..} => write!(f, "cannot output {:#x}, it is not a single byte UTF-8 character", value),
+            RuntimeError::OutputFailed{..} => write!(f, "unable to write output")
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// add to or, going backwards, subtract from a resolved destination
+pub fn inc(place: Place, srcval: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let destval = read_place(place, ctx)?;
+    let newval = if ctx.forward {
+        ctx.word.add(destval, srcval)
+    }
+    else{
+        ctx.word.sub(destval, srcval)
+    };
+    let newval = newval.ok_or(RuntimeError::Overflow{pc: ctx.pc})?;
+    write_place(place, newval, ctx)
+}
+
+// io going forwards, read a byte into place
+pub fn input(place: Place, ctx: &mut Context) -> Result<(), RuntimeError> {
+    // work out whether the input has somewhere to go before consuming any of it
+    match place {
+        Place::Literal(_) => return Err(RuntimeError::WriteToLiteral{pc: ctx.pc}),
+        Place::Mem(addr) => {
+            get_mem_val(addr, ctx)?;
+        },
+        Place::Reg(_) => ()
+    }
+    let inp = getc(ctx);
+    write_place(place, inp as usize, ctx)
+}
+
+// io going backwards, write value as a character
+pub fn output(value: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let val = [value as u8];
+    let output = match str::from_utf8(&val) {
+        Ok(output) if value < 0x80 => output,
+        _ => return Err(RuntimeError::InvalidUtf8{pc: ctx.pc, value})
+    };
+    write!(ctx.output, "{}", output).map_err(|_| RuntimeError::OutputFailed{pc: ctx.pc})?;
+    record(ctx, Effect::Output(val[0]));
+    Ok(())
+}
+
+// move in the direction it's active, save what place holds then overwrite it
+pub fn save(place: Place, val: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let old = read_place(place, ctx)?;
+    write_place(place, val, ctx)?;
+    ctx.values.entry(place).or_default().push(old);
+    record(ctx, Effect::Save(place, old));
+    Ok(())
+}
+
+// move the other way, put back the last value saved for place. Like a bare
+// jump with nothing on its stack, nothing happens if nothing was saved
+pub fn restore(place: Place, ctx: &mut Context) -> Result<(), RuntimeError> {
+    read_place(place, ctx)?;
+    if let Some(old) = ctx.values.get_mut(&place).and_then(Vec::pop) {
+        record(ctx, Effect::Restore(place, old));
+        write_place(place, old, ctx)?;
+    }
+    Ok(())
+}
+
+// swap and xor are their own inverses, so they ignore the direction
+pub fn swap(left: Place, right: Place, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let (l, r) = (read_place(left, ctx)?, read_place(right, ctx)?);
+    write_place(left, r, ctx)?;
+    write_place(right, l, ctx)
+}
+
+pub fn xor(place: Place, srcval: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let destval = read_place(place, ctx)?;
+    write_place(place, destval ^ srcval, ctx)
+}
 
-pub fn execute_instruction(inst: &ast::Instruction, ctx: &mut Context,) -> (bool, bool, bool){
-    match inst{
+// multiply by k, or going backwards divide it back out
+pub fn mul(place: Place, k: usize, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let destval = read_place(place, ctx)?;
+    let newval = if ctx.forward {
+        ctx.word.mul(destval, k)
+    }
+    else {
+        ctx.word.div(destval, k)
+    };
+    let newval = newval.ok_or(RuntimeError::Overflow{pc: ctx.pc})?;
+    write_place(place, newval, ctx)
+}
+
+// rotate left by amount, or right when left is false
+pub fn rotate(place: Place, amount: usize, left: bool, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let destval = read_place(place, ctx)?;
+    let newval = if left {
+        ctx.word.rol(destval, amount)
+    }
+    else {
+        ctx.word.ror(destval, amount)
+    };
+    write_place(place, newval, ctx)
+}
+
+pub fn neg(place: Place, ctx: &mut Context) -> Result<(), RuntimeError> {
+    let destval = read_place(place, ctx)?;
+    write_place(place, ctx.word.neg(destval), ctx)
+}
+
+pub fn execute_instruction(inst: &ast::Instruction, ctx: &mut Context,) -> Result<(bool, bool, bool), RuntimeError>{
+    Ok(match inst{
         ast::Instruction::Inc(dest, src) =>{
-            let srcval = source_to_val(src, ctx);
-            let destval = match dest{
-                ast::Dest::Reg(reg) => get_reg_val(reg, ctx),
-                ast::Dest::Addr(loc) => ctx.mem[*loc],
-                ast::Dest::Deref(dst) => ctx.mem[deref_dest(dest, ctx)],//TODO deref_dest function
-            };
-            let mut newval = 0;
-            if(ctx.forward){
-                newval = destval + srcval;
-            }
-            else{
-                newval = destval - srcval;
+            let srcval = source_to_val(src, ctx)?;
+            let place = dest_place(dest, ctx)?;
+            inc(place, srcval, ctx)?;
+            (false, false, false)
+        },
+        ast::Instruction::Move(dest, src) | ast::Instruction::Unmove(dest, src) => {
+            let saving = ctx.forward == matches!(inst, ast::Instruction::Move(..));
+            if saving {
+                let srcval = source_to_val(src, ctx)?;
+                save(dest_place(dest, ctx)?, srcval, ctx)?;
             }
-            match dest{
-                ast::Dest::Reg(reg) => set_reg_val(reg, newval, ctx),
-                ast::Dest::Addr(loc) => ctx.mem[*loc] = newval,
-                ast::Dest::Deref(dst) => ctx.mem[deref_dest(dest, ctx)] = newval,//TODO deref_dest function
+            else {
+                restore(dest_place(dest, ctx)?, ctx)?;
             }
-
+            (false, false, false)
+        },
+        ast::Instruction::Swap(left, right) => {
+            let left = dest_place(left, ctx)?;
+            let right = dest_place(right, ctx)?;
+            swap(left, right, ctx)?;
+            (false, false, false)
+        },
+        ast::Instruction::Xor(dest, src) => {
+            let srcval = source_to_val(src, ctx)?;
+            let place = dest_place(dest, ctx)?;
+            xor(place, srcval, ctx)?;
+            (false, false, false)
+        },
+        ast::Instruction::Mul(dest, k) => {
+            let place = dest_place(dest, ctx)?;
+            mul(place, *k, ctx)?;
+            (false, false, false)
+        },
+        ast::Instruction::Rol(dest, src) | ast::Instruction::Ror(dest, src) => {
+            let amount = source_to_val(src, ctx)?;
+            let place = dest_place(dest, ctx)?;
+            let left = ctx.forward == matches!(inst, ast::Instruction::Rol(..));
+            rotate(place, amount, left, ctx)?;
+            (false, false, false)
+        },
+        ast::Instruction::Neg(dest) => {
+            let place = dest_place(dest, ctx)?;
+            neg(place, ctx)?;
             (false, false, false)
         },
         ast::Instruction::Jump(lbl) =>{
-            if(ctx.forward){
+            if ctx.forward {
                 match lbl{
-                    Some(label) => {jump_to_label(label,ctx); return (false, true, false)},
-                    None => {return (false, false, true)}, //Pop Stack and go
+                    Some(label) => {jump_to_label(label,ctx); return Ok((false, true, false))},
+                    None => {return Ok((false, false, true))}, //Pop Stack and go
                 }
             }
             (false, false, false)
         },
         ast::Instruction::From(lbl) =>{
-            if(!ctx.forward){
+            if !ctx.forward {
                 match lbl{
-                    Some(label) => {jump_to_label(label,ctx); return (false, true, false)},
-                    None => {return (false, false, true)}, //Pop Stack and go
+                    Some(label) => {jump_to_label(label,ctx); return Ok((false, true, false))},
+                    None => {return Ok((false, false, true))}, //Pop Stack and go
                 }
 
             }
             (false, false, false)
         },
         ast::Instruction::Forwards => {
-            ctx.forward = true;
+            set_forward(true, ctx);
             (false, false, false)
         },
         ast::Instruction::Backwards => {
-            ctx.forward = false;
+            set_forward(false, ctx);
             (false, false, false)
         },
         ast::Instruction::Reverse => {
-            ctx.forward = !ctx.forward;
+            set_forward(!ctx.forward, ctx);
             (false, false, false)
         },
         ast::Instruction::Io(src) => {
-            if(!ctx.forward){
-                let val = source_to_val(src, ctx);
-                let val = [val as u8];
-                let output = str::from_utf8(&val).expect("not UTF-8");
-                print!("{}", output);
+            if ctx.forward {
+                input(source_place(src, ctx)?, ctx)?;
             }
             else{
-                let mut inp = getc();
-                let inp = inp as usize;
-                //panic!("Cannot take input");
-                match src{
-                    ast::Source::Literal(_) => panic!("Cannot place input into a literal"),
-                    ast::Source::Reg(reg) => set_reg_val(reg, inp, ctx),
-                    ast::Source::Addr(loc) => ctx.mem[*loc] = inp,
-                    ast::Source::Deref(src) => ctx.mem[source_to_val(src, ctx)] = inp,
-                }
+                output(source_to_val(src, ctx)?, ctx)?;
             }
             (false, false, false)
         },
         ast::Instruction::Halt => {
-            println!("Program Halted");
             (true, false, false)
         },
-    }
+        // these need the line stacks, so step runs them
+        ast::Instruction::Call(_) | ast::Instruction::Return => (false, false, false)
+    })
 }
 
+pub fn push_call(line: usize, ctx: &mut Context) {
+    ctx.calls.push(line);
+    record(ctx, Effect::Call(line));
+}
 
-// evaluate a program
-pub fn eval(program: &mut Vec<self::ast::Line>, ctx: &mut Context) {
-    let mut program = program;
-    loop{
-        let currentPC = ctx.pc.clone();
-        let mut halted = false;
-        let mut jumped = false;
-        let mut tojump = false;
-        let end_of_program = (ctx.pc >= program.len()) || (currentPC == 0 && !ctx.forward);
-        if(halted || end_of_program){
-            break;
-        }
-        let mut current_line = &program[ctx.pc];
-
-        //Check for option here!!
-        match(&current_line.cond){
-            Some(cond) => {
-                if(eval_expr(cond, &ctx)){
-                    let (mhalted, mjumped, mtojump)  = execute_instruction(&current_line.inst, ctx,);
-                    halted = mhalted;
-                    jumped = mjumped;
-                    tojump = mtojump;
+pub fn pop_call(ctx: &mut Context) -> Option<usize> {
+    let line = ctx.calls.pop()?;
+    record(ctx, Effect::Return(line));
+    Some(line)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Halted,
+    Finished,
+    // stopped by a RuntimeError
+    Trapped
+}
+
+// execute the line at the program counter and advance it
+pub fn step(program: &mut [ast::Line], ctx: &mut Context) -> Result<Status, RuntimeError> {
+    let current_pc = ctx.pc;
+    let end_of_program = (ctx.pc >= program.len()) || (current_pc == 0 && !ctx.forward);
+    if end_of_program {
+        return Ok(Status::Finished);
+    }
+    let current_line = &program[ctx.pc];
+
+    let (halted, jumped, tojump) = match (&current_line.cond, &current_line.inst) {
+        (Some(cond), _) if !eval_expr(cond, ctx)? => (false, false, false),
+        (_, ast::Instruction::Call(_) | ast::Instruction::Return) => {
+            subroutine(program, ctx);
+            let pc = ctx.pc;
+            record(ctx, Effect::Pc(current_pc, pc));
+            return Ok(Status::Running);
+        },
+        (_, inst) => execute_instruction(inst, ctx)?
+    };
+    if halted {
+        return Ok(Status::Halted);
+    }
+    if jumped {
+        program[ctx.pc].stack.push(current_pc);
+        record(ctx, Effect::Push(ctx.pc, current_pc));
+        match &program[ctx.pc].inst{
+            ast::Instruction::Jump(None) | ast::Instruction::From(None) => {
+                if ctx.forward {
+                    ctx.pc+=1;
+                }
+                else{
+                    ctx.pc = ctx.pc.wrapping_sub(1);
                 }
             },
-            None => {
-                let (mhalted, mjumped, mtojump) = execute_instruction(&current_line.inst, ctx,);
-                halted = mhalted;
-                jumped = mjumped;
-                tojump = mtojump;
-
-            }, //DO Instruction
+            _=>()
         }
-        if(jumped){
-            program[ctx.pc].stack.push(currentPC);
-            match &program[ctx.pc].inst{
-                ast::Instruction::Jump(label) =>{
-                    match label{
-                        None => {
-                            if(ctx.forward){
-                                ctx.pc+=1;
-                            }
-                            else{
-                                ctx.pc-=1;
-                            }
-                        },
-                        _=>()
-                    }
-                },
-                ast::Instruction::From(label) => {
-                    match label{
-                        None => {
-                            if(ctx.forward){
-                                ctx.pc+=1;
-                            }
-                            else{
-                                ctx.pc-=1;
-                            }
-                        },
-                        _=>()
-                    }
-                },
-                _=>()
+    }
+    else{
+        if tojump {
+            let lineno = program[current_pc].stack.pop();
+            if let Some(from) = lineno {
+                record(ctx, Effect::Pop(current_pc, from));
             }
+            ctx.pc = lineno.unwrap_or(current_pc);
+        }
+        if ctx.forward {
+            ctx.pc+=1;
+        }
+        else if ctx.pc == current_pc {
+            step_back(program, ctx);
         }
         else{
-            if(tojump){
-                let lineno = program[currentPC].stack.pop();
-                ctx.pc = match lineno{
-                    None => currentPC,
-                    Some(newPC) => newPC
-                };
-            }
-            if(ctx.forward){
-                ctx.pc+=1;
-            }
-            else{
-                ctx.pc-=1;
-            }
+            ctx.pc = ctx.pc.wrapping_sub(1);
         }
+    }
+    let pc = ctx.pc;
+    record(ctx, Effect::Pc(current_pc, pc));
+    Ok(Status::Running)
+}
 
+// run a call or return, leaving pc on the next line to run
+fn subroutine(program: &mut [ast::Line], ctx: &mut Context) {
+    let here = ctx.pc;
+    match (&program[here].inst, ctx.forward) {
+        (ast::Instruction::Call(label), true) => {
+            push_call(here, ctx);
+            jump_to_label(label, ctx);
+        },
+        // the return line goes on the call's stack, so going backwards the
+        // call knows where to re-enter the callee
+        (ast::Instruction::Return, true) => match pop_call(ctx) {
+            Some(caller) => {
+                program[caller].stack.push(here);
+                record(ctx, Effect::Push(caller, here));
+                ctx.pc = caller + 1;
+            },
+            // nothing to return to, like a bare jump
+            None => ctx.pc += 1
+        },
+        (ast::Instruction::Call(_), false) => match program[here].stack.pop() {
+            Some(ret) => {
+                record(ctx, Effect::Pop(here, ret));
+                push_call(here, ctx);
+                ctx.pc = ret;
+            },
+            None => step_back(program, ctx)
+        },
+        _ => step_back(program, ctx)
     }
+}
 
+// the line before pc, unless pc is the first line of the newest callee,
+// when going backwards returns out to the line before its call
+fn step_back(program: &[ast::Line], ctx: &mut Context) {
+    let entry = ctx.calls.last().and_then(|&caller| match &program[caller].inst {
+        ast::Instruction::Call(label) => Some(ctx.labels[label]),
+        _ => None
+    });
+    ctx.pc = match entry {
+        Some(entry) if entry == ctx.pc => pop_call(ctx).unwrap_or(0).wrapping_sub(1),
+        // off the start of the program, the next step finishes
+        _ => ctx.pc.wrapping_sub(1)
+    };
+}
+
+// evaluate a program
+pub fn eval(program: &mut [ast::Line], ctx: &mut Context) -> Result<Status, RuntimeError> {
+    loop{
+        let result = step(program, ctx);
+        match result {
+            Ok(Status::Running) => continue,
+            _ => {
+                ctx.output.flush().map_err(|_| RuntimeError::OutputFailed{pc: ctx.pc})?;
+                return result;
+            }
+        }
+    }
 }
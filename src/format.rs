@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::ast;
+use crate::cst::{self, Cst, LineKind, TriviaKind};
+use crate::diagnostic::Diagnostics;
+use crate::macros;
+
+// prints a line's numbers in the base they were written in, taking one
+// from the front for each number in the order they appear in the source
+struct Printer {
+    hex: VecDeque<bool>
+}
+
+impl Printer {
+    fn num(&mut self, n: usize) -> String {
+        if self.hex.pop_front().unwrap_or(false) {
+            format!("{:#x}", n)
+        }
+        else {
+            n.to_string()
+        }
+    }
+
+    fn source(&mut self, src: &ast::Source) -> String {
+        match src {
+            ast::Source::Reg(reg) => reg.to_string(),
+            ast::Source::Addr(addr) => self.num(*addr),
+            ast::Source::Literal(val) => format!("${}", self.num(*val)),
+            ast::Source::Deref(src) => format!("*{}", self.source(src))
+        }
+    }
+
+    fn dest(&mut self, dest: &ast::Dest) -> String {
+        match dest {
+            ast::Dest::Reg(reg) => reg.to_string(),
+            ast::Dest::Addr(addr) => self.num(*addr),
+            ast::Dest::Deref(dest) => format!("*{}", self.dest(dest))
+        }
+    }
+
+    // and/or are left associative, so a right operand only needs
+    // parentheses when it binds as loosely as its parent
+    fn logic(&mut self, parent: &ast::Expr, left: &ast::Expr, op: &str, right: &ast::Expr) -> String {
+        let l = self.expr(left);
+        let r = self.expr(right);
+        let l = if left.precedence() < parent.precedence() { format!("({})", l) } else { l };
+        let r = if right.precedence() <= parent.precedence() { format!("({})", r) } else { r };
+        format!("{} {} {}", l, op, r)
+    }
+
+    fn cmp(&mut self, left: &ast::Source, op: &str, right: &ast::Source) -> String {
+        let left = self.source(left);
+        format!("{} {} {}", left, op, self.source(right))
+    }
+
+    fn expr(&mut self, expr: &ast::Expr) -> String {
+        match expr {
+            ast::Expr::Backwards => "backwards".to_string(),
+            ast::Expr::Forwards => "forwards".to_string(),
+            ast::Expr::Or(left, right) => self.logic(expr, left, "or", right),
+            ast::Expr::And(left, right) => self.logic(expr, left, "and", right),
+            ast::Expr::Gte(left, right) => self.cmp(left, ">=", right),
+            ast::Expr::Lte(left, right) => self.cmp(left, "<=", right),
+            ast::Expr::Gt(left, right) => self.cmp(left, ">", right),
+            ast::Expr::Lt(left, right) => self.cmp(left, "<", right),
+            ast::Expr::Eq(left, right) => self.cmp(left, "=", right)
+        }
+    }
+
+    fn inst(&mut self, inst: &ast::Instruction) -> String {
+        match inst {
+            ast::Instruction::Inc(dest, src) => {
+                let dest = self.dest(dest);
+                format!("inc {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Move(dest, src) => {
+                let dest = self.dest(dest);
+                format!("move {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Unmove(dest, src) => {
+                let dest = self.dest(dest);
+                format!("unmove {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Swap(left, right) => {
+                let left = self.dest(left);
+                format!("swap {} {}", left, self.dest(right))
+            },
+            ast::Instruction::Xor(dest, src) => {
+                let dest = self.dest(dest);
+                format!("xor {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Mul(dest, k) => {
+                let dest = self.dest(dest);
+                format!("mul {} ${}", dest, self.num(*k))
+            },
+            ast::Instruction::Rol(dest, src) => {
+                let dest = self.dest(dest);
+                format!("rol {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Ror(dest, src) => {
+                let dest = self.dest(dest);
+                format!("ror {} {}", dest, self.source(src))
+            },
+            ast::Instruction::Neg(dest) => format!("neg {}", self.dest(dest)),
+            ast::Instruction::Io(src) => format!("io {}", self.source(src)),
+            // nothing else has numbers in it
+            inst => inst.to_string()
+        }
+    }
+}
+
+// macro definitions and calls aren't in the grammar, so they're printed a
+// token at a time with the same spacing as everything else. Returns the
+// label and the rest of the line
+fn tokens(line: &cst::Line) -> (Option<String>, String) {
+    let code: Vec<_> = line.code().collect();
+    let (label, code) = match code.as_slice() {
+        [cst::Token{token: ast::Token::Identifier(label), ..}, cst::Token{token: ast::Token::Label, ..}, rest @ ..] => (Some(label.clone()), rest),
+        code => (None, code)
+    };
+    let mut text = String::new();
+    for (i, t) in code.iter().enumerate() {
+        let joined = i == 0 || matches!(t.token, ast::Token::Close)
+            || matches!(code[i - 1].token, ast::Token::Deref | ast::Token::Literal | ast::Token::Open);
+        if !joined {
+            text.push(' ');
+        }
+        match &t.token {
+            ast::Token::Reg(reg) => text += &reg.to_string(),
+            ast::Token::Num(n) if t.text.starts_with("0x") => text += &format!("{:#x}", n),
+            ast::Token::Num(n) => text += &n.to_string(),
+            _ => text += &t.text
+        }
+    }
+    (label, text)
+}
+
+// a single space after the semicolons that start a comment
+fn comment(text: &str) -> String {
+    let text = text.trim_end();
+    let body = text.trim_start_matches(';');
+    let semis = &text[..text.len() - body.len()];
+    if body.is_empty() {
+        semis.to_string()
+    }
+    else {
+        format!("{} {}", semis, body.trim_start())
+    }
+}
+
+// reprint a program with labels in their own column, registers in upper
+// case, numbers as lower case hex or plain decimal depending on how they
+// were written, single spaces between operands and only the parentheses
+// and/or need. Comments stay on their line and runs of blank lines
+// become one. Macro definitions and calls are spaced the same way
+pub fn format(source: &str) -> Result<String, Diagnostics> {
+    // a macro that's never closed leaves nothing to tell code lines apart
+    macros::expand(source)?;
+    let cst = Cst::parse(source)?;
+    let mut comments = BTreeMap::new();
+    for trivia in cst.tokens().flat_map(|t| &t.leading).chain(&cst.trailing) {
+        if trivia.kind == TriviaKind::Comment {
+            comments.insert(trivia.span.line, (trivia.span.column, comment(&trivia.text)));
+        }
+    }
+    let nums: Vec<(usize, bool)> = cst.tokens()
+        .filter(|t| matches!(t.token, ast::Token::Num(_)))
+        .map(|t| (t.span.start, t.text.starts_with("0x")))
+        .collect();
+    let lines = cst.to_ast()?;
+
+    let raw: Vec<(usize, Option<String>, String)> = cst.lines.iter().zip(cst.kinds())
+        .filter(|(_, kind)| *kind != LineKind::Code)
+        .filter_map(|(line, _)| {
+            let first = line.code().next()?.span.line;
+            let (label, text) = tokens(line);
+            Some((first, label, text))
+        })
+        .collect();
+
+    // every instruction lines up after the longest label
+    let width = lines.iter().filter_map(|line| line.label.as_ref())
+        .chain(raw.iter().filter_map(|(_, label, _)| label.as_ref()))
+        .map(|label| label.len() + 2)
+        .max()
+        .unwrap_or(0);
+    let mut code = BTreeMap::new();
+    for (n, label, text) in raw {
+        let label = label.map_or(String::new(), |label| format!("{}:", label));
+        code.insert(n, format!("{:width$}{}", label, text, width = width));
+    }
+    for line in &lines {
+        let hex = nums.iter()
+            .filter(|(start, _)| *start >= line.span.start && *start < line.span.end)
+            .map(|(_, hex)| *hex)
+            .collect();
+        let mut printer = Printer{hex};
+        let label = line.label.as_ref().map_or(String::new(), |label| format!("{}:", label));
+        let mut text = format!("{:width$}{}", label, printer.inst(&line.inst), width = width);
+        if let Some(cond) = &line.cond {
+            text += &format!(" if {}", printer.expr(cond));
+        }
+        code.insert(line.span.line, text);
+    }
+
+    let last = code.keys().chain(comments.keys()).max().copied().unwrap_or(0);
+    let mut out = String::new();
+    let mut blank = false;
+    for n in 1..=last {
+        let text = match (code.get(&n), comments.get(&n)) {
+            (Some(text), Some((_, comment))) => format!("{} {}", text, comment),
+            (Some(text), None) => text.clone(),
+            // comments on their own line either start it or line up with code
+            (None, Some((1, comment))) => comment.clone(),
+            (None, Some((_, comment))) => format!("{:width$}{}", "", comment, width = width),
+            (None, None) => {
+                blank = !out.is_empty();
+                continue;
+            }
+        };
+        if blank {
+            out.push('\n');
+            blank = false;
+        }
+        out += &text;
+        out.push('\n');
+    }
+    Ok(out)
+}
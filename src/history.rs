@@ -0,0 +1,145 @@
+use crate::ast;
+use crate::eval::{Context, Place, Status};
+
+// a single change a step made to the machine, old value first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Reg(ast::Register, usize, usize),
+    Mem(usize, usize, usize),
+    Direction(bool, bool),
+    Pc(usize, usize),
+    // line whose jump stack changed and the value pushed or popped
+    Push(usize, usize),
+    Pop(usize, usize),
+    // place whose value stack changed and the value saved or restored
+    Save(Place, usize),
+    Restore(Place, usize),
+    // the call line pushed onto or popped off the shared call stack
+    Call(usize),
+    Return(usize),
+    // io is logged so a replay neither reads nor writes again
+    Input(u8),
+    Output(u8)
+}
+
+// everything one step of the interpreter did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    pub effects: Vec<Effect>,
+    pub status: Status
+}
+
+// a log of steps with a cursor, stepping back undoes the delta before the
+// cursor and stepping forward again redoes it instead of re-executing
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    deltas: Vec<Delta>,
+    cursor: usize
+}
+
+fn set_reg(reg: &ast::Register, val: usize, ctx: &mut Context) {
+    match reg {
+        ast::Register::A => ctx.a = val,
+        ast::Register::B => ctx.b = val,
+        ast::Register::C => ctx.c = val,
+        ast::Register::D => ctx.d = val,
+    }
+}
+
+pub fn undo(delta: &Delta, lines: &mut [ast::Line], ctx: &mut Context) {
+    for effect in delta.effects.iter().rev() {
+        match *effect {
+            Effect::Reg(reg, old, _) => set_reg(&reg, old, ctx),
+            Effect::Mem(addr, old, _) => {
+                ctx.mem.set(addr, old);
+            },
+            Effect::Direction(old, _) => ctx.forward = old,
+            Effect::Pc(old, _) => ctx.pc = old,
+            Effect::Push(line, _) => {
+                lines[line].stack.pop();
+            },
+            Effect::Pop(line, val) => lines[line].stack.push(val),
+            Effect::Save(place, _) => {
+                ctx.values.entry(place).or_default().pop();
+            },
+            Effect::Restore(place, val) => ctx.values.entry(place).or_default().push(val),
+            Effect::Call(_) => {
+                ctx.calls.pop();
+            },
+            Effect::Return(line) => ctx.calls.push(line),
+            Effect::Input(_) | Effect::Output(_) => ()
+        }
+    }
+}
+
+pub fn redo(delta: &Delta, lines: &mut [ast::Line], ctx: &mut Context) {
+    for effect in delta.effects.iter() {
+        match *effect {
+            Effect::Reg(reg, _, new) => set_reg(&reg, new, ctx),
+            Effect::Mem(addr, _, new) => {
+                ctx.mem.set(addr, new);
+            },
+            Effect::Direction(_, new) => ctx.forward = new,
+            Effect::Pc(_, new) => ctx.pc = new,
+            Effect::Push(line, val) => lines[line].stack.push(val),
+            Effect::Pop(line, _) => {
+                lines[line].stack.pop();
+            },
+            Effect::Save(place, val) => ctx.values.entry(place).or_default().push(val),
+            Effect::Restore(place, _) => {
+                ctx.values.entry(place).or_default().pop();
+            },
+            Effect::Call(line) => ctx.calls.push(line),
+            Effect::Return(_) => {
+                ctx.calls.pop();
+            },
+            Effect::Input(_) | Effect::Output(_) => ()
+        }
+    }
+}
+
+impl History {
+    pub fn new() -> History {
+        History{deltas: Vec::new(), cursor: 0}
+    }
+
+    // number of steps between the start of the program and now
+    pub fn position(&self) -> usize {
+        self.cursor
+    }
+
+    // number of steps recorded, some may be ahead of the cursor
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    pub fn deltas(&self) -> &[Delta] {
+        &self.deltas
+    }
+
+    pub fn record(&mut self, delta: Delta) {
+        self.deltas.truncate(self.cursor);
+        self.deltas.push(delta);
+        self.cursor += 1;
+    }
+
+    // the delta to redo if we have stepped back, None when at the end of the log
+    pub fn advance(&mut self) -> Option<&Delta> {
+        let delta = self.deltas.get(self.cursor)?;
+        self.cursor += 1;
+        Some(delta)
+    }
+
+    // the delta to undo, None at the start of the program
+    pub fn retreat(&mut self) -> Option<&Delta> {
+        if self.cursor == 0 {
+            return None;
+        }
+        self.cursor -= 1;
+        Some(&self.deltas[self.cursor])
+    }
+}
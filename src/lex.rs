@@ -4,143 +4,212 @@ extern crate regex;
 use regex::Regex;
 
 use crate::ast;
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+
+// a token along with where it came from in the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpannedToken {
+    pub token: ast::Token,
+    pub span: Span
+}
 
 trait MatchHandler {
-    fn handle(&self, mat: String) -> ast::Token;
+    fn handle(&self, mat: &str) -> Result<ast::Token, &'static str>;
 }
 
-impl<F> MatchHandler for F where F: Fn(String) -> ast::Token {
-    fn handle(&self, mat: String) -> ast::Token {self(mat)}
+impl<F> MatchHandler for F where F: Fn(&str) -> Result<ast::Token, &'static str> {
+    fn handle(&self, mat: &str) -> Result<ast::Token, &'static str> {self(mat)}
 }
 
 struct TokenMatcher<'l> {
     regex: Regex,
-    on_match: &'l MatchHandler
+    on_match: &'l dyn MatchHandler
 }
 
 impl<'l> TokenMatcher<'l> {
-    pub fn try_match(&mut self, iter: &String, tokens: &mut Vec<ast::Token>) -> Option<String>{
-        match self.regex.captures(&iter) {
-            Some(captures) => {
-                if captures[1].to_string() == "".to_string() {
-                    return None
-                }
-                tokens.push(self.on_match.handle(captures[1].to_string()));
-                Some(captures[2].to_string())
+    // length of the match at the start of input and the token it produced
+    pub fn try_match(&self, input: &str) -> Option<(usize, Result<ast::Token, &'static str>)> {
+        match self.regex.find(input) {
+            Some(mat) if !mat.as_str().is_empty() => {
+                Some((mat.end(), self.on_match.handle(mat.as_str())))
             }
-            None => None
+            _ => None
         }
     }
 }
 
+// maps byte offsets to line and column numbers
+#[derive(Debug, Clone)]
+pub(crate) struct LineIndex {
+    starts: Vec<usize>
+}
+
+impl LineIndex {
+    pub(crate) fn new(input: &str) -> LineIndex {
+        let mut starts = vec![0];
+        starts.extend(input.match_indices('\n').map(|(i, _)| i + 1));
+        LineIndex{starts}
+    }
+
+    pub(crate) fn span(&self, input: &str, start: usize, end: usize) -> Span {
+        let line = match self.starts.binary_search(&start) {
+            Ok(i) => i,
+            Err(i) => i - 1
+        };
+        let column = input[self.starts[line]..start].chars().count() + 1;
+        Span{start, end, line: line + 1, column}
+    }
+}
+
 struct Tokenizer<'l> {
     matchers: Vec<TokenMatcher<'l>>
 }
 
 impl<'l> Tokenizer<'l> {
     pub fn new() -> Tokenizer<'l> {
-        return Tokenizer{matchers: Vec::new()};
+        Tokenizer{matchers: Vec::new()}
     }
     pub fn def_match<F>(&mut self, sregex: &str, on_match: &'l F) ->
         std::result::Result<(), regex::Error>
         where F: MatchHandler
     {
-        let formatted = &format!(r"^[ ]*({})(?s)(.*)$", sregex.to_string());
+        let formatted = &format!(r"^(?:{})", sregex);
         Regex::new(formatted).map(|regex| {
             self.matchers.push(TokenMatcher{regex, on_match})
         })
     }
 
-    pub fn tokenize(&mut self, mut input: String) -> Option<Vec<ast::Token>> {
-        let mut tokens: Vec<ast::Token> = vec![];
-        'start_matching: while input.len() > 0 {
-            let mut i = 0;
-            while i < self.matchers.len() {
-                match self.matchers[i].try_match(&input, &mut tokens) {
-                    Some(new_input) => {
-                        input = new_input;
-                        continue 'start_matching;
-                    },
-                    None => ()
-                };
-                i+=1;
+    pub fn tokenize(&self, input: &str) -> Result<Vec<SpannedToken>, Diagnostic> {
+        let index = LineIndex::new(input);
+        let mut tokens: Vec<SpannedToken> = vec![];
+        let mut pos = 0;
+        'start_matching: while pos < input.len() {
+            // plain spaces separate tokens
+            if input[pos..].starts_with(' ') {
+                pos += 1;
+                continue;
             }
-            // no match
-            return None
+            for matcher in self.matchers.iter() {
+                if let Some((len, token)) = matcher.try_match(&input[pos..]) {
+                    let span = index.span(input, pos, pos + len);
+                    match token {
+                        Ok(token) => tokens.push(SpannedToken{token, span}),
+                        Err(e) => return Err(Diagnostic::error(span, e))
+                    }
+                    pos += len;
+                    continue 'start_matching;
+                }
+            }
+            // no match, point at the offending character
+            let len = input[pos..].chars().next().map_or(1, char::len_utf8);
+            return Err(Diagnostic::error(index.span(input, pos, pos + len), "bad token"));
         }
-        return Some(tokens);
+        Ok(tokens)
     }
 }
 
-pub fn lex(input: String) -> Option<Vec<ast::Token>> {
+// keywords and registers are lexed as words so labels like `order` or
+// `iffy` don't get split into a keyword and a leftover identifier
+pub fn keyword(word: &str) -> Option<ast::Token> {
+    use crate::ast::Token::{*};
+    Some(match word {
+        "jump" => Jump,
+        "from" => From,
+        "call" => Call,
+        "return" => Return,
+        "inc" => Inc,
+        "move" => Move,
+        "unmove" => Unmove,
+        "swap" => Swap,
+        "xor" => Xor,
+        "mul" => Mul,
+        "rol" => Rol,
+        "ror" => Ror,
+        "neg" => Neg,
+        "halt" => Halt,
+        "io" => Io,
+        "backwards" => Backwards,
+        "forwards" => Forwards,
+        "reverse" => Reverse,
+        "macro" => Macro,
+        "endmacro" => EndMacro,
+        "if" => If,
+        "and" => And,
+        "or" => Or,
+        "A" | "a" => Reg(ast::Register::A),
+        "B" | "b" => Reg(ast::Register::B),
+        "C" | "c" => Reg(ast::Register::C),
+        "D" | "d" => Reg(ast::Register::D),
+        _ => return None
+    })
+}
+
+pub fn lex(input: &str) -> Result<Vec<SpannedToken>, Diagnostic> {
+    tokenize(input, false)
+}
+
+// lex keeping the text of each comment as a Comment token, for tools that
+// reprint the source
+pub fn lex_with_comments(input: &str) -> Result<Vec<SpannedToken>, Diagnostic> {
+    tokenize(input, true)
+}
+
+fn tokenize(input: &str, comments: bool) -> Result<Vec<SpannedToken>, Diagnostic> {
     let mut tokenizer = Tokenizer::new();
     use crate::ast::Token::{*};
 
-    tokenizer.def_match(r"0x[0-9a-fA-F]+", &|mat: String| {
-        Num(usize::from_str_radix(&mat[2..], 16).unwrap())
-    });
+    tokenizer.def_match(r"0x[0-9a-fA-F]+", &|mat: &str| {
+        usize::from_str_radix(&mat[2..], 16).map(Num).map_err(|_| "number too large")
+    }).expect("invalid token regex");
 
-    tokenizer.def_match(r"[0-9]+", &|mat: String| {
-        Num(usize::from_str(&mat).unwrap())
-    });
+    tokenizer.def_match(r"[0-9]+", &|mat: &str| {
+        usize::from_str(mat).map(Num).map_err(|_| "number too large")
+    }).expect("invalid token regex");
 
     tokenizer.def_match(
-        r"[\$:\(\)\*]|jump|from|inc|halt|io|backwards|forwards|reverse|if|<=|>=|>|=|<|and|or",
-        &|mat: String| {
-            match mat.as_ref() {
+        r"[\$:\(\)\*]|<=|>=|>|=|<",
+        &|mat: &str| {
+            Ok(match mat {
                 "$" => Literal,
                 ":" => Label,
                 "(" => Open,
                 ")" => Close,
                 "*" => Deref,
-                "jump" => Jump,
-                "from" => From,
-                "inc" => Inc,
-                "halt" => Halt,
-                "io" => Io,
-                "backwards" => Backwards,
-                "forwards" => Forwards,
-                "reverse" => Reverse,
-                "if" => If,
                 ">=" => Gte,
                 "<=" => Lte,
                 ">" => Gt,
                 "=" => Eq,
                 "<" => Lt,
-                "and" => And,
-                "or" => Or,
                 _ => Nop // wont happen, guarded by regex
-            }
-        }
-    );
-    // register letter followed by non identifier character
-    tokenizer.def_match(r"(?i)A[^a-zA-Z\-0-9]|B[^a-zA-Z\-0-9]|C[^a-zA-Z\-0-9]|D[^a-zA-Z\-0-9]", &|mat: String| {
-        match &mat.as_str()[..1] {
-            "A" | "a" => Reg(ast::Register::A),
-            "B" | "b" => Reg(ast::Register::B),
-            "C" | "c" => Reg(ast::Register::C),
-            "D" | "d" => Reg(ast::Register::D),
-            _ => Nop // imposible
+            })
         }
-    });
-    
-    tokenizer.def_match(r"[a-zA-Z\-0-9]+", &|mat: String| {
-        Identifier(mat)
-    });
+    ).expect("invalid token regex");
+
+    tokenizer.def_match(r"[a-zA-Z\-0-9]+", &|mat: &str| {
+        Ok(keyword(mat).unwrap_or_else(|| Identifier(mat.to_string())))
+    }).expect("invalid token regex");
 
     // End of line comment
-    tokenizer.def_match(r";.*", &|_: String| {
-        Nop
-    });
+    if comments {
+        tokenizer.def_match(r";[^\n]*", &|mat: &str| {
+            Ok(Comment(mat.to_string()))
+        }).expect("invalid token regex");
+    }
+    else {
+        tokenizer.def_match(r";[^\n]*", &|_: &str| {
+            Ok(Nop)
+        }).expect("invalid token regex");
+    }
 
-    tokenizer.def_match(r"\n+", &|mat: String| {
-        Newlines(mat.len())
-    });
+    tokenizer.def_match(r"\n+", &|mat: &str| {
+        Ok(Newlines(mat.len()))
+    }).expect("invalid token regex");
 
 
-    tokenizer.def_match(r"\s*", &|_: String| {
-        Nop
-    });
+    tokenizer.def_match(r"[^\S\n]+", &|_: &str| {
+        Ok(Nop)
+    }).expect("invalid token regex");
 
-    return tokenizer.tokenize(input);
+    tokenizer.tokenize(input)
 }
@@ -0,0 +1,33 @@
+//! Moonwalk, a language that does different things going forwards and backwards.
+//!
+//! The pipeline is lex -> parse -> link labels -> interpret. `Program` runs
+//! the first three stages and `Machine` interprets the result. Parsing goes
+//! through a lossless `cst::Cst` that keeps comments and whitespace, and the
+//! ast is derived from it.
+
+pub mod ast;
+pub mod backend;
+pub mod bf;
+pub mod bytecode;
+pub mod cfg;
+pub mod cst;
+pub mod debug;
+pub mod diagnostic;
+pub mod eval;
+pub mod format;
+pub mod history;
+pub mod lex;
+pub mod lsp;
+pub mod machine;
+pub mod macros;
+pub mod memory;
+pub mod parse;
+pub mod program;
+pub mod reversible;
+pub mod transpile;
+pub mod word;
+
+pub use diagnostic::{Diagnostic, Diagnostics, Severity};
+pub use eval::{RuntimeError, Status};
+pub use machine::Machine;
+pub use program::Program;
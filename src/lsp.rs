@@ -0,0 +1,370 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead, Read, Write};
+
+use serde_json::{json, Value};
+
+use crate::ast;
+use crate::cst::{self, Cst};
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::eval;
+use crate::program::Program;
+
+// the longest message body read, anything longer is skipped
+const MAX_MESSAGE: usize = 1 << 24;
+
+// a json-rpc error reply to a message that couldn't be read
+fn unreadable(code: i64, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": null, "error": {"code": code, "message": message}})
+}
+
+// read one message, None once the client hangs up. a body that's too long
+// or isn't json is read past and comes back as the error to reply with
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Result<Value, Value>>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "missing Content-Length"))?;
+    if length > MAX_MESSAGE {
+        io::copy(&mut Read::take(&mut *input, length as u64), &mut io::sink())?;
+        return Ok(Some(Err(unreadable(-32600, format!("message of {} bytes is too long, at most {}", length, MAX_MESSAGE)))));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body).map_err(|e| unreadable(-32700, format!("parse error: {}", e)))))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// lsp positions count lines from 0 and characters in utf-16 code units
+fn position(text: &str, offset: usize) -> Value {
+    let offset = offset.min(text.len());
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[..line_start].matches('\n').count();
+    let character: usize = text[line_start..offset].chars().map(char::len_utf16).sum();
+    json!({"line": line, "character": character})
+}
+
+fn offset(text: &str, pos: &Value) -> usize {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let character = pos["character"].as_u64().unwrap_or(0) as usize;
+    let line_start = match line {
+        0 => 0,
+        _ => text.match_indices('\n').nth(line - 1).map_or(text.len(), |(i, _)| i + 1)
+    };
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn range(text: &str, start: usize, end: usize) -> Value {
+    json!({"start": position(text, start), "end": position(text, end)})
+}
+
+// notes, like the macro calls a line came from, become related information
+fn to_lsp(uri: &str, text: &str, diagnostic: &Diagnostic) -> Value {
+    let (start, end) = diagnostic.span.map_or((0, 0), |span| (span.start, span.end));
+    let severity = match diagnostic.severity {
+        Severity::Error => 1,
+        Severity::Warning => 2
+    };
+    let related: Vec<Value> = diagnostic.notes.iter().map(|note| json!({
+        "location": {"uri": uri, "range": range(text, note.span.start, note.span.end)},
+        "message": note.message
+    })).collect();
+    let mut value = json!({"range": range(text, start, end), "severity": severity, "source": "moonwalk", "message": diagnostic.message});
+    if !related.is_empty() {
+        value["relatedInformation"] = Value::Array(related);
+    }
+    value
+}
+
+// what each instruction does in each direction, shown on hover
+fn describe(inst: &ast::Instruction) -> &'static str {
+    match inst {
+        ast::Instruction::Inc(..) => "forwards: adds the source to the destination\n\nbackwards: subtracts the source from the destination",
+        ast::Instruction::Move(..) => "forwards: saves the destination on its value stack and overwrites it with the source\n\nbackwards: restores the destination to the last value saved for it",
+        ast::Instruction::Unmove(..) => "forwards: restores the destination to the last value saved for it\n\nbackwards: saves the destination on its value stack and overwrites it with the source",
+        ast::Instruction::Swap(..) => "exchanges the two destinations in either direction",
+        ast::Instruction::Xor(..) => "xors the source into the destination in either direction",
+        ast::Instruction::Mul(..) => "forwards: multiplies the destination by the constant\n\nbackwards: divides the destination by the constant",
+        ast::Instruction::Rol(..) => "forwards: rotates the destination's bits left by the source\n\nbackwards: rotates them right by the source",
+        ast::Instruction::Ror(..) => "forwards: rotates the destination's bits right by the source\n\nbackwards: rotates them left by the source",
+        ast::Instruction::Neg(_) => "negates the destination as a two's complement number in either direction",
+        ast::Instruction::Jump(Some(_)) => "forwards: goes to the label, pushing this line onto its stack\n\nbackwards: does nothing",
+        ast::Instruction::Jump(None) => "forwards: returns to the line after the last jump or from that came here\n\nbackwards: does nothing",
+        ast::Instruction::From(Some(_)) => "forwards: does nothing\n\nbackwards: goes to the label, pushing this line onto its stack",
+        ast::Instruction::From(None) => "forwards: does nothing\n\nbackwards: returns to the line before the last jump or from that came here",
+        ast::Instruction::Call(_) => "forwards: goes to the label, pushing this line onto the call stack\n\nbackwards: goes back into the callee at the return it left by",
+        ast::Instruction::Return => "forwards: pops the call stack and goes to the line after that call\n\nbackwards: carries on back through the callee, which returns out once it steps back off its first line",
+        ast::Instruction::Forwards => "forwards: does nothing\n\nbackwards: starts running forwards",
+        ast::Instruction::Backwards => "forwards: starts running backwards\n\nbackwards: does nothing",
+        ast::Instruction::Reverse => "forwards: starts running backwards\n\nbackwards: starts running forwards",
+        ast::Instruction::Halt => "stops the program in either direction",
+        ast::Instruction::Io(_) => "forwards: reads a character of input into the source\n\nbackwards: writes the source as a character"
+    }
+}
+
+// a document broken down as far as it goes, a file with syntax errors
+// still has tokens to work with. Tokens leave out comments and whitespace,
+// and lines leave out macro definitions and calls
+struct Analysis {
+    tokens: Vec<cst::Token>,
+    lines: Vec<ast::Line>,
+    // labels on macro calls and inside macro bodies, with where they start
+    macro_labels: Vec<(String, usize)>
+}
+
+impl Analysis {
+    fn new(text: &str) -> Analysis {
+        let cst = Cst::parse(text).unwrap_or_default();
+        let lines = cst.to_ast().unwrap_or_default();
+        let macro_labels = cst.lines.iter().zip(cst.kinds())
+            .filter(|(_, kind)| matches!(kind, cst::LineKind::Call | cst::LineKind::Body))
+            .filter_map(|(line, _)| match line.tokens.as_slice() {
+                [cst::Token{token: ast::Token::Identifier(label), span, ..}, cst::Token{token: ast::Token::Label, ..}, ..] => {
+                    Some((label.clone(), span.start))
+                },
+                _ => None
+            })
+            .collect();
+        Analysis{tokens: cst.tokens().cloned().collect(), lines, macro_labels}
+    }
+
+    // the label name under the cursor, if there is one
+    fn label_at(&self, offset: usize) -> Option<&str> {
+        self.tokens.iter().find_map(|t| match &t.token {
+            ast::Token::Identifier(name) if t.span.start <= offset && offset <= t.span.end => Some(name.as_str()),
+            _ => None
+        })
+    }
+
+    // where a label is defined, as a byte range
+    fn definition(&self, label: &str) -> Option<(usize, usize)> {
+        let labels = match eval::scan_labels(&self.lines) {
+            eval::ScanResult::Ok(labels) | eval::ScanResult::Unused(_, labels) => labels,
+            _ => self.lines.iter().enumerate()
+                .filter_map(|(i, line)| line.label.clone().map(|label| (label, i)))
+                .collect()
+        };
+        let start = match labels.get(label) {
+            Some(&i) => self.lines[i].span.start,
+            None => self.macro_labels.iter().find(|(name, _)| name == label)?.1
+        };
+        Some((start, start + label.len()))
+    }
+
+    // every jump, from or call naming the label, as byte ranges
+    fn references(&self, label: &str) -> Vec<(usize, usize)> {
+        self.tokens.windows(2).filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+            (ast::Token::Jump | ast::Token::From | ast::Token::Call, ast::Token::Identifier(name)) if name == label => {
+                Some((pair[1].span.start, pair[1].span.end))
+            },
+            _ => None
+        }).collect()
+    }
+
+    fn labels(&self) -> Vec<&str> {
+        self.tokens.windows(2).filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+            (ast::Token::Identifier(name), ast::Token::Label) => Some(name.as_str()),
+            _ => None
+        }).collect()
+    }
+
+    fn line_at(&self, offset: usize) -> Option<&ast::Line> {
+        self.lines.iter().find(|line| line.span.start <= offset && offset <= line.span.end)
+    }
+}
+
+// the open documents by uri
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, String>,
+    shutdown: bool
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server::default()
+    }
+
+    fn capabilities() -> Value {
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "definitionProvider": true,
+                "referencesProvider": true,
+                "hoverProvider": true,
+                "completionProvider": {"triggerCharacters": [" "]}
+            },
+            "serverInfo": {"name": "moonwalk"}
+        })
+    }
+
+    fn diagnostics(&self, uri: &str) -> Value {
+        let text = &self.documents[uri];
+        let diagnostics: Vec<Value> = match Program::from_source(text) {
+            Ok(program) => program.warnings().iter().map(|d| to_lsp(uri, text, d)).collect(),
+            Err(errors) => errors.iter().map(|d| to_lsp(uri, text, d)).collect()
+        };
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": {"uri": uri, "diagnostics": diagnostics}
+        })
+    }
+
+    // the document and cursor offset a request is about
+    fn at<'a>(&'a self, params: &Value) -> Option<(&'a str, &'a str, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let (uri, text) = self.documents.get_key_value(uri)?;
+        Some((uri, text, offset(text, &params["position"])))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let found = self.at(params).and_then(|(uri, text, offset)| {
+            let analysis = Analysis::new(text);
+            let (start, end) = analysis.definition(analysis.label_at(offset)?)?;
+            Some(json!({"uri": uri, "range": range(text, start, end)}))
+        });
+        found.unwrap_or(Value::Null)
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let found = self.at(params).and_then(|(uri, text, offset)| {
+            let analysis = Analysis::new(text);
+            let label = analysis.label_at(offset)?;
+            let mut ranges = Vec::new();
+            if params["context"]["includeDeclaration"].as_bool().unwrap_or(false) {
+                ranges.extend(analysis.definition(label));
+            }
+            ranges.extend(analysis.references(label));
+            Some(ranges.into_iter().map(|(start, end)| json!({"uri": uri, "range": range(text, start, end)})).collect())
+        });
+        found.map_or(Value::Null, Value::Array)
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let found = self.at(params).and_then(|(_, text, offset)| {
+            let analysis = Analysis::new(text);
+            let line = analysis.line_at(offset)?;
+            let mut value = format!("```moonwalk\n{}\n```\n\n{}", line, describe(&line.inst));
+            if let Some(cond) = &line.cond {
+                value += &format!("\n\nonly when `{}` holds, in either direction", cond);
+            }
+            Some(json!({
+                "contents": {"kind": "markdown", "value": value},
+                "range": range(text, line.span.start, line.span.end)
+            }))
+        });
+        found.unwrap_or(Value::Null)
+    }
+
+    fn completion(&self, params: &Value) -> Value {
+        let found = self.at(params).map(|(_, text, _)| {
+            let analysis = Analysis::new(text);
+            analysis.labels().into_iter()
+                .map(|label| json!({"label": label, "kind": 18, "detail": "label"}))
+                .collect()
+        });
+        found.map_or(Value::Null, Value::Array)
+    }
+
+    // handle one message, returning what to send back
+    pub fn handle(&mut self, message: &Value) -> Vec<Value> {
+        let method = message["method"].as_str().unwrap_or("");
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            // notifications
+            None => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or("").to_string();
+                return match method {
+                    "textDocument/didOpen" => {
+                        let text = params["textDocument"]["text"].as_str().unwrap_or("");
+                        self.documents.insert(uri.clone(), text.to_string());
+                        vec![self.diagnostics(&uri)]
+                    },
+                    "textDocument/didChange" => {
+                        // full sync, the last change has the whole document
+                        let changes = params["contentChanges"].as_array();
+                        match changes.and_then(|c| c.last()).and_then(|c| c["text"].as_str()) {
+                            Some(text) => {
+                                self.documents.insert(uri.clone(), text.to_string());
+                                vec![self.diagnostics(&uri)]
+                            },
+                            None => Vec::new()
+                        }
+                    },
+                    "textDocument/didClose" => {
+                        self.documents.remove(&uri);
+                        vec![json!({
+                            "jsonrpc": "2.0",
+                            "method": "textDocument/publishDiagnostics",
+                            "params": {"uri": uri, "diagnostics": []}
+                        })]
+                    },
+                    _ => Vec::new()
+                };
+            }
+        };
+        let result = match method {
+            "initialize" => Server::capabilities(),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            },
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/completion" => self.completion(params),
+            _ => return vec![json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {"code": -32601, "message": format!("unsupported method {}", method)}
+            })]
+        };
+        vec![json!({"jsonrpc": "2.0", "id": id, "result": result})]
+    }
+}
+
+// speak lsp until the client sends exit or hangs up, returns whether it
+// shut down cleanly first
+pub fn serve(mut input: impl BufRead, mut output: impl Write) -> io::Result<bool> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(&mut input)? {
+        let message = match message {
+            Ok(message) => message,
+            Err(reply) => {
+                write_message(&mut output, &reply)?;
+                continue;
+            }
+        };
+        if message["method"] == "exit" {
+            break;
+        }
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+    }
+    Ok(server.shutdown)
+}
@@ -0,0 +1,207 @@
+use std::io::{self, Read, Write};
+
+use crate::ast;
+use crate::bytecode::{self, Engine, Vm};
+use crate::diagnostic::Diagnostic;
+use crate::eval::{self, Context, RuntimeError, Status};
+use crate::history::{self, Delta, History};
+use crate::memory::{Memory, MemoryModel};
+use crate::program::Program;
+use crate::word::Word;
+
+// a loaded program together with the state of the moonwalk vm running it
+pub struct Machine {
+    lines: Vec<ast::Line>,
+    ctx: Context,
+    status: Status,
+    error: Option<RuntimeError>,
+    history: Option<History>,
+    engine: Engine
+}
+
+impl Machine {
+    // io reads from stdin and writes to stdout
+    pub fn new(program: Program) -> Machine {
+        let ctx = Context::new(program.labels);
+        Machine{lines: program.lines, ctx, status: Status::Running, error: None, history: None, engine: Engine::Tree}
+    }
+
+    pub fn with_io(program: Program, input: Box<dyn Read>, output: Box<dyn Write>) -> Machine {
+        let ctx = Context::with_io(program.labels, input, output);
+        Machine{lines: program.lines, ctx, status: Status::Running, error: None, history: None, engine: Engine::Tree}
+    }
+
+    // set the word size and overflow policy, meant to be called before running
+    pub fn set_word(&mut self, word: Word) {
+        self.ctx.word = word;
+    }
+
+    // replace memory with empty memory of the given model, meant to be
+    // called before running
+    pub fn set_memory(&mut self, model: MemoryModel) {
+        self.ctx.mem = Memory::new(model);
+    }
+
+    // pick what run uses, stepping and history always walk the tree
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.engine = engine;
+    }
+
+    // start logging every step so they can be undone, see step_back
+    pub fn record_history(&mut self) {
+        if self.history.is_none() {
+            self.history = Some(History::new());
+        }
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    // execute a single line, does nothing once the program has stopped
+    pub fn step(&mut self) -> Status {
+        if self.status != Status::Running {
+            return self.status;
+        }
+        let result = match &mut self.history {
+            None => eval::step(&mut self.lines, &mut self.ctx),
+            Some(history) => match history.advance() {
+                // we've been here before, replay the log instead of executing
+                Some(delta) => {
+                    history::redo(delta, &mut self.lines, &mut self.ctx);
+                    Ok(delta.status)
+                },
+                None => {
+                    self.ctx.journal = Some(Vec::new());
+                    let result = eval::step(&mut self.lines, &mut self.ctx);
+                    let effects = self.ctx.journal.take().unwrap_or_default();
+                    // failed and finishing steps change nothing worth undoing
+                    if let Ok(status @ (Status::Running | Status::Halted)) = result {
+                        history.record(Delta{effects, status});
+                    }
+                    result
+                }
+            }
+        };
+        self.finish_step(result)
+    }
+
+    fn finish_step(&mut self, result: Result<Status, RuntimeError>) -> Status {
+        self.status = match result {
+            Ok(status) => status,
+            Err(e) => {
+                self.error = Some(e);
+                Status::Trapped
+            }
+        };
+        self.status
+    }
+
+    // the error that stopped the program, if it trapped
+    pub fn error(&self) -> Option<&RuntimeError> {
+        self.error.as_ref()
+    }
+
+    // the trap as a diagnostic pointing at the offending line
+    pub fn error_diagnostic(&self) -> Option<Diagnostic> {
+        self.error.as_ref().map(|e| {
+            let span = self.lines.get(e.pc()).map(|line| line.span);
+            Diagnostic::error(span, e.to_string())
+        })
+    }
+
+    // undo the last step, returns false if there is nothing to undo
+    // or history isn't being recorded
+    pub fn step_back(&mut self) -> bool {
+        // a trapped step made no changes, so backing out of it only clears the error
+        if self.status == Status::Trapped && self.history.is_some() {
+            self.status = Status::Running;
+            self.error = None;
+            return true;
+        }
+        let delta = match self.history.as_mut().and_then(History::retreat) {
+            Some(delta) => delta,
+            None => return false
+        };
+        history::undo(delta, &mut self.lines, &mut self.ctx);
+        self.status = Status::Running;
+        self.error = None;
+        true
+    }
+
+    // move backward or forward through time to the given step number,
+    // going past the end of the log executes new steps
+    pub fn rewind_to(&mut self, position: usize) -> Status {
+        while self.history.as_ref().is_some_and(|h| h.position() > position) {
+            self.step_back();
+        }
+        while self.history.as_ref().is_some_and(|h| h.position() < position)
+            && self.step() == Status::Running {}
+        self.status
+    }
+
+    // run until the program halts or runs off either end
+    pub fn run(&mut self) -> Status {
+        if self.history.is_some() {
+            while self.step() == Status::Running {}
+        }
+        else if self.status == Status::Running {
+            let result = match self.engine {
+                Engine::Tree => eval::eval(&mut self.lines, &mut self.ctx),
+                Engine::Bytecode => self.run_bytecode()
+            };
+            self.finish_step(result);
+        }
+        self.status
+    }
+
+    fn run_bytecode(&mut self) -> Result<Status, RuntimeError> {
+        let mut vm = Vm::new(bytecode::compile(&self.lines, &self.ctx.labels));
+        // carry the jump stacks over so stepping can pick up where it left off
+        for (stack, line) in vm.stacks_mut().iter_mut().zip(&mut self.lines) {
+            std::mem::swap(stack, &mut line.stack);
+        }
+        let result = vm.run(&mut self.ctx);
+        for (stack, line) in vm.stacks_mut().iter_mut().zip(&mut self.lines) {
+            std::mem::swap(stack, &mut line.stack);
+        }
+        result
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn lines(&self) -> &[ast::Line] {
+        &self.lines
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn context_mut(&mut self) -> &mut Context {
+        &mut self.ctx
+    }
+
+    pub fn register(&self, reg: &ast::Register) -> usize {
+        eval::get_reg_val(reg, &self.ctx)
+    }
+
+    pub fn pc(&self) -> usize {
+        self.ctx.pc
+    }
+
+    pub fn forward(&self) -> bool {
+        self.ctx.forward
+    }
+
+    pub fn mem(&self) -> &Memory {
+        &self.ctx.mem
+    }
+
+    // output may be buffered, so flush it once the program stops
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.ctx.output.flush()
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ast::{self, Span};
+use crate::diagnostic::{Diagnostic, Note};
+use crate::lex::{self, LineIndex, SpannedToken};
+
+// how many macros deep a call can be before we give up on it ever ending
+const MAX_DEPTH: usize = 32;
+
+// the code tokens of one line and where its text ends, newlines included
+struct Line {
+    tokens: Vec<SpannedToken>,
+    end: usize
+}
+
+struct Macro {
+    // where the name is written, which is where the definition is shown
+    name: Span,
+    params: Vec<String>,
+    body: Vec<Vec<SpannedToken>>,
+    // labels the body defines, renamed for every call
+    labels: HashSet<String>
+}
+
+// a token on its way into the expanded text, from a call if it was
+// expanded out of a macro
+#[derive(Debug, Clone)]
+struct Tok {
+    token: ast::Token,
+    text: String,
+    span: Span,
+    expansion: Option<usize>
+}
+
+// one call of a macro, and the call it was expanded inside of
+#[derive(Debug, Clone)]
+struct Expansion {
+    name: String,
+    call: Span,
+    parent: Option<usize>
+}
+
+// a run of expanded text and the source it was copied from. Text added
+// between tokens runs past len and maps to the end of what came before
+#[derive(Debug, Clone, Copy)]
+struct Piece {
+    at: usize,
+    from: usize,
+    len: usize,
+    expansion: Option<usize>
+}
+
+// source with every macro call replaced by the macro's body, and what it
+// takes to point anything found in the expanded text back at the source
+#[derive(Debug, Clone)]
+pub struct Expanded {
+    text: String,
+    source: String,
+    index: LineIndex,
+    pieces: Vec<Piece>,
+    expansions: Vec<Expansion>
+}
+
+impl Expanded {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    fn copy(&mut self, start: usize, end: usize) {
+        if start < end {
+            self.pieces.push(Piece{at: self.text.len(), from: start, len: end - start, expansion: None});
+            self.text += &self.source[start..end];
+        }
+    }
+
+    // a line of tokens spaced the way they'd be written
+    fn push(&mut self, line: &[Tok]) {
+        for (i, tok) in line.iter().enumerate() {
+            let joined = i == 0 || tok.token == ast::Token::Label
+                || matches!(line[i - 1].token, ast::Token::Deref | ast::Token::Literal);
+            if !joined {
+                self.text.push(' ');
+            }
+            let len = tok.span.end - tok.span.start;
+            self.pieces.push(Piece{at: self.text.len(), from: tok.span.start, len, expansion: tok.expansion});
+            self.text += &tok.text;
+        }
+    }
+
+    // the source offset an offset into the expanded text came from
+    fn origin(&self, offset: usize) -> Option<(usize, Piece)> {
+        let i = self.pieces.partition_point(|piece| piece.at <= offset).checked_sub(1)?;
+        let piece = self.pieces[i];
+        Some((piece.from + (offset - piece.at).min(piece.len), piece))
+    }
+
+    // a span of the expanded text as a span of the source. A span that
+    // starts in a macro's body and ends in its arguments keeps to its start
+    pub fn locate(&self, span: Span) -> Span {
+        let (start, first) = match self.origin(span.start) {
+            Some(origin) => origin,
+            None => return span
+        };
+        let end = match self.origin(span.end.max(span.start + 1) - 1) {
+            Some((_, last)) if last.expansion == first.expansion => last.from + (span.end - last.at).min(last.len),
+            _ => first.from + first.len
+        };
+        self.index.span(&self.source, start, end.max(start))
+    }
+
+    // every call a piece of the expanded text came through, innermost first
+    fn notes(&self, mut expansion: Option<usize>, mut diagnostic: Diagnostic) -> Diagnostic {
+        while let Some(e) = expansion {
+            let Expansion{name, call, parent} = &self.expansions[e];
+            diagnostic = diagnostic.with_note(*call, format!("in this expansion of {}", name));
+            expansion = *parent;
+        }
+        diagnostic
+    }
+
+    // a diagnostic about the expanded text, moved back to the source with a
+    // note at each macro call it came through
+    pub fn relocate(&self, diagnostic: &Diagnostic) -> Diagnostic {
+        let span = match diagnostic.span {
+            Some(span) => span,
+            None => return diagnostic.clone()
+        };
+        let expansion = self.origin(span.start).and_then(|(_, piece)| piece.expansion);
+        let located = Diagnostic{span: Some(self.locate(span)), notes: Vec::new(), ..diagnostic.clone()};
+        let mut located = self.notes(expansion, located);
+        located.notes.extend(diagnostic.notes.iter().map(|note| Note{span: self.locate(note.span), message: note.message.clone()}));
+        located
+    }
+}
+
+struct Expander<'s> {
+    source: &'s str,
+    macros: HashMap<String, Macro>,
+    // identifiers in the source, renamed labels can't be any of them
+    taken: HashSet<String>,
+    out: Expanded
+}
+
+impl<'s> Expander<'s> {
+    fn tok(&self, token: &SpannedToken, expansion: Option<usize>) -> Tok {
+        let text = self.source[token.span.start..token.span.end].to_string();
+        Tok{token: token.token.clone(), text, span: token.span, expansion}
+    }
+
+    fn error(&self, span: Span, expansion: Option<usize>, message: impl Into<String>) -> Diagnostic {
+        self.out.notes(expansion, Diagnostic::error(span, message))
+    }
+
+    // where the macro's name is if the line calls one, after any label
+    fn call_at(&self, line: &[Tok]) -> Option<usize> {
+        let at = match line {
+            [Tok{token: ast::Token::Identifier(_), ..}, Tok{token: ast::Token::Label, ..}, ..] => 2,
+            _ => 0
+        };
+        match line.get(at) {
+            Some(Tok{token: ast::Token::Identifier(name), ..}) if self.macros.contains_key(name) => Some(at),
+            _ => None
+        }
+    }
+
+    // split a call's arguments into operands like A, *B, $3 or a label
+    fn arguments(&self, toks: &[Tok]) -> Result<Vec<Vec<Tok>>, Diagnostic> {
+        let mut args = Vec::new();
+        let mut start = 0;
+        for (i, tok) in toks.iter().enumerate() {
+            match tok.token {
+                ast::Token::Deref | ast::Token::Literal => (),
+                ast::Token::Num(_) | ast::Token::Reg(_) | ast::Token::Identifier(_) => {
+                    args.push(toks[start..=i].to_vec());
+                    start = i + 1;
+                },
+                _ => return Err(self.error(tok.span, tok.expansion, "macro arguments are operands like A, *B, $3 or a label"))
+            }
+        }
+        match toks.get(start) {
+            Some(tok) => Err(self.error(tok.span, tok.expansion, "this argument is missing what it refers to")),
+            None => Ok(args)
+        }
+    }
+
+    // a label that can't clash with anything in the source or any other call
+    fn rename(&mut self, label: &str, expansion: usize) -> String {
+        let mut name = format!("{}-{}", label, expansion);
+        while self.taken.contains(&name) {
+            name.insert(0, '-');
+        }
+        self.taken.insert(name.clone());
+        name
+    }
+
+    // the lines a call expands to, without the label in front of the call
+    fn expand(&mut self, line: &[Tok], at: usize, depth: usize) -> Result<Vec<Vec<Tok>>, Diagnostic> {
+        let call = &line[at];
+        let name = match &call.token {
+            ast::Token::Identifier(name) => name.clone(),
+            _ => unreachable!()
+        };
+        if depth == MAX_DEPTH {
+            // a note for every level would be the same few lines over and
+            // over, so only point at where it started
+            let mut outermost = call.expansion.map(|e| &self.out.expansions[e]);
+            while let Some(Expansion{parent: Some(parent), ..}) = outermost {
+                outermost = Some(&self.out.expansions[*parent]);
+            }
+            let error = Diagnostic::error(call.span, format!("macro {} goes {} calls deep, does it call itself?", name, MAX_DEPTH));
+            return Err(match outermost {
+                Some(outermost) => error.with_note(outermost.call, format!("in this expansion of {}", outermost.name)),
+                None => error
+            });
+        }
+        let args = self.arguments(&line[at + 1..])?;
+        let mac = &self.macros[&name];
+        if args.len() != mac.params.len() {
+            let plural = if mac.params.len() == 1 { "" } else { "s" };
+            let message = format!("macro {} takes {} argument{}, found {}", name, mac.params.len(), plural, args.len());
+            let definition = mac.name;
+            return Err(self.error(call.span, call.expansion, message).with_note(definition, format!("{} is defined here", name)));
+        }
+        let params: HashMap<String, Vec<Tok>> = mac.params.iter().cloned().zip(args).collect();
+        let (body, labels) = (mac.body.clone(), mac.labels.clone());
+        let expansion = self.out.expansions.len();
+        self.out.expansions.push(Expansion{name, call: call.span, parent: call.expansion});
+        // a labelled call of a macro that starts with a label would name
+        // one line twice, so the call's label stands in for the macro's
+        let shared = match (&line[..at], body.first().map(Vec::as_slice)) {
+            ([Tok{token: ast::Token::Identifier(outer), ..}, _], Some([SpannedToken{token: ast::Token::Identifier(inner), ..}, SpannedToken{token: ast::Token::Label, ..}, ..])) => {
+                Some((inner.clone(), outer.clone()))
+            },
+            _ => None
+        };
+        let mut renamed = HashMap::new();
+        for label in labels {
+            let name = match &shared {
+                Some((inner, outer)) if *inner == label => outer.clone(),
+                _ => self.rename(&label, expansion)
+            };
+            renamed.insert(label, name);
+        }
+
+        let mut lines = Vec::new();
+        for (n, tokens) in body.iter().enumerate() {
+            let tokens = if n == 0 && shared.is_some() { &tokens[2..] } else { &tokens[..] };
+            let mut toks = Vec::new();
+            for token in tokens {
+                match &token.token {
+                    ast::Token::Identifier(ident) if params.contains_key(ident) => toks.extend(params[ident].iter().cloned()),
+                    ast::Token::Identifier(ident) if renamed.contains_key(ident) => {
+                        let name = renamed[ident].clone();
+                        toks.push(Tok{token: ast::Token::Identifier(name.clone()), text: name, span: token.span, expansion: Some(expansion)});
+                    },
+                    _ => toks.push(self.tok(token, Some(expansion)))
+                }
+            }
+            match self.call_at(&toks) {
+                Some(at) => {
+                    let mut inner = self.expand(&toks, at, depth + 1)?;
+                    // the label in front of the call goes on its first line
+                    inner[0].splice(0..0, toks[..at].iter().cloned());
+                    lines.extend(inner);
+                },
+                None => lines.push(toks)
+            }
+        }
+        Ok(lines)
+    }
+
+    // read a definition starting at lines[i], returning the line its
+    // endmacro is on
+    fn define(&mut self, lines: &[Line], i: usize) -> Result<usize, Diagnostic> {
+        let header = &lines[i].tokens;
+        let name = match header.get(1) {
+            Some(SpannedToken{token: ast::Token::Identifier(name), span}) => (name.clone(), *span),
+            other => {
+                let span = other.map_or(header[0].span, |t| t.span);
+                return Err(Diagnostic::error(span, "a macro needs a name, like macro zero r"));
+            }
+        };
+        let mut params = Vec::new();
+        for token in &header[2..] {
+            match &token.token {
+                ast::Token::Identifier(param) if params.contains(param) => {
+                    return Err(Diagnostic::error(token.span, format!("parameter {} is listed twice", param)));
+                },
+                ast::Token::Identifier(param) => params.push(param.clone()),
+                _ => return Err(Diagnostic::error(token.span, "macro parameters are names, like r"))
+            }
+        }
+        if let Some(mac) = self.macros.get(&name.0) {
+            return Err(Diagnostic::error(name.1, format!("macro {} is already defined", name.0)).with_note(mac.name, "first defined here"));
+        }
+
+        let mut body = Vec::new();
+        let mut labels = HashSet::new();
+        for (j, line) in lines.iter().enumerate().skip(i + 1) {
+            match line.tokens.as_slice() {
+                // a label on a call has to go on the line it expands to
+                [SpannedToken{token: ast::Token::EndMacro, ..}] if body.is_empty() => {
+                    return Err(Diagnostic::error(name.1, format!("macro {} has no lines, a macro needs at least one", name.0)));
+                },
+                [SpannedToken{token: ast::Token::EndMacro, ..}] => {
+                    self.macros.insert(name.0, Macro{name: name.1, params, body, labels});
+                    return Ok(j);
+                },
+                [SpannedToken{token: ast::Token::EndMacro, ..}, extra, ..] => {
+                    return Err(Diagnostic::error(extra.span, "endmacro goes on a line of its own"));
+                },
+                tokens => {
+                    if let Some(inner) = tokens.iter().find(|t| t.token == ast::Token::Macro) {
+                        return Err(Diagnostic::error(inner.span, "macros can't be defined inside another macro"));
+                    }
+                    if let [SpannedToken{token: ast::Token::Identifier(label), ..}, SpannedToken{token: ast::Token::Label, ..}, ..] = tokens {
+                        labels.insert(label.clone());
+                    }
+                    body.push(tokens.to_vec());
+                }
+            }
+        }
+        Err(Diagnostic::error(header[0].span.to(name.1), format!("macro {} is never closed with endmacro", name.0)))
+    }
+}
+
+fn split(source: &str, tokens: Vec<SpannedToken>) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for token in tokens {
+        match token.token {
+            ast::Token::Nop | ast::Token::Comment(_) => (),
+            ast::Token::Newlines(_) => lines.push(Line{tokens: std::mem::take(&mut line), end: token.span.end}),
+            _ => line.push(token)
+        }
+    }
+    lines.push(Line{tokens: line, end: source.len()});
+    lines.retain(|line| !line.tokens.is_empty());
+    lines
+}
+
+// replace `macro name params ... endmacro` definitions with nothing and
+// every call of one with its body, arguments put in for its parameters and
+// the labels it defines renamed so calling it twice doesn't repeat them
+pub fn expand(source: &str) -> Result<Expanded, Diagnostic> {
+    let lines = split(source, lex::lex(source)?);
+    let taken = lines.iter().flat_map(|line| &line.tokens).filter_map(|t| match &t.token {
+        ast::Token::Identifier(name) => Some(name.clone()),
+        _ => None
+    }).collect();
+    let out = Expanded{text: String::new(), source: source.to_string(), index: LineIndex::new(source), pieces: Vec::new(), expansions: Vec::new()};
+    let mut expander = Expander{source, macros: HashMap::new(), taken, out};
+
+    // macros can be called before they're defined
+    let mut definitions = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        match lines[i].tokens[0].token {
+            ast::Token::Macro => {
+                let end = expander.define(&lines, i)?;
+                definitions.push((i, end));
+                i = end;
+            },
+            ast::Token::EndMacro => return Err(Diagnostic::error(lines[i].tokens[0].span, "endmacro without a macro")),
+            _ => ()
+        }
+        i += 1;
+    }
+
+    let mut copied = 0;
+    let mut definitions = definitions.into_iter().peekable();
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some(&(start, end)) = definitions.peek() {
+            if start == i {
+                expander.out.copy(copied, lines[start].tokens[0].span.start);
+                copied = lines[end].end;
+                definitions.next();
+                i = end + 1;
+                continue;
+            }
+        }
+        let toks: Vec<Tok> = lines[i].tokens.iter().map(|t| expander.tok(t, None)).collect();
+        if let Some(at) = expander.call_at(&toks) {
+            expander.out.copy(copied, toks[at].span.start);
+            let expanded = expander.expand(&toks, at, 0)?;
+            for (n, line) in expanded.iter().enumerate() {
+                if n > 0 {
+                    expander.out.text.push('\n');
+                }
+                expander.out.push(line);
+            }
+            copied = toks[toks.len() - 1].span.end;
+        }
+        i += 1;
+    }
+    expander.out.copy(copied, source.len());
+    Ok(expander.out)
+}
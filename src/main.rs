@@ -1,56 +1,358 @@
 use std::env;
 use std::fs;
+use std::io::{self, Read, Write};
+use std::process;
 
-use std::collections::HashMap;
-
-//mod bf;
-mod ast;
-mod parse;
-mod lex;
-mod eval;
-
-fn moonwalk_main() {
-    let args: Vec<String> = env::args().collect();
-    let message = format!("Unable to read file {}", args[1]);
-    let content = fs::read_to_string(&args[1]).expect(&message);
-    let tokens = match self::lex::lex(content) {
-        Some(toks) => toks,
-        None => {
-            print!("ERROR: bad token");
-            return;
-        }
-    };
-    let mut program = match self::parse::parse(tokens) {
-        std::result::Result::Ok(prg) => prg,
-        std::result::Result::Err((lineno, e)) => {
-            println!("PARSE ERROR ON LINE {}: {}", lineno, e);
-            return;
+use moonwalk::backend::{self, Settings, Target};
+use moonwalk::bf::{self, Eof};
+use moonwalk::bytecode::Engine;
+use moonwalk::cfg;
+use moonwalk::debug::Debugger;
+use moonwalk::format;
+use moonwalk::lsp;
+use moonwalk::memory::MemoryModel;
+use moonwalk::transpile::{self, Language};
+use moonwalk::word::{Overflow, Word};
+use moonwalk::{Diagnostics, Machine, Program, Status};
+
+const USAGE: &str = "\
+usage: moonwalk [run] [options] [--engine <tree|bytecode>] <source>.mw
+       moonwalk debug [options] [--input <file>] <source>.mw
+       moonwalk compile [options] --target <target> [-o <file>] <source>.mw
+       moonwalk transpile --from <bf> [-o <file>] <source>
+       moonwalk cfg --dot <source>.mw
+       moonwalk fmt [--check | --write] <source>.mw
+       moonwalk lsp
+       moonwalk bf [--tape <cells>] [--eof <zero|max|unchanged>] <source>.bf
+
+options:
+  --word-size <8|16|32|64>        width of registers and memory cells (default 64)
+  --overflow <wrap|trap|saturate> what inc does when it overflows (default wrap)
+  --memory <cells|sparse>         number of memory cells (default 65536, at
+                                  most 268435456), or sparse memory where
+                                  every address is valid
+  --engine <tree|bytecode>        walk the syntax tree (default) or compile
+                                  to bytecode first, run only
+  --target <target>               what to compile to, one of c, x86_64-asm,
+                                  wat or wasm, compile only
+  --from <bf>                     language to transpile into moonwalk,
+                                  transpile only
+  -o <file>                       where to write compiled or transpiled code
+                                  (default stdout)
+  --dot                           print the control flow graph as graphviz
+                                  dot, required as the only format, cfg only
+  --check                         exit with status 1 if formatting would
+                                  change the file, fmt only
+  --write                         format the file in place, fmt only
+  --tape <cells>                  brainfuck tape length (default 30000, at
+                                  most 268435456)
+  --eof <zero|max|unchanged>      what , leaves in the cell at end of input
+                                  (default zero)";
+
+#[derive(PartialEq)]
+enum Mode {
+    Run,
+    Debug,
+    Compile,
+    Transpile,
+    Cfg,
+    Fmt,
+    Bf
+}
+
+// flags shared by the subcommands that load a program
+struct Options {
+    path: String,
+    word_size: u32,
+    overflow: Overflow,
+    memory: MemoryModel,
+    input: Option<String>,
+    engine: Engine,
+    target: Option<Target>,
+    from: Option<Language>,
+    output: Option<String>,
+    tape: usize,
+    eof: Eof,
+    dot: bool,
+    check: bool,
+    write: bool
+}
+
+fn parse_options(args: &[String], mode: Mode) -> Result<Options, String> {
+    let mut path = None;
+    let mut word_size = usize::BITS;
+    let mut overflow = Overflow::Wrapping;
+    let mut memory = MemoryModel::default();
+    let mut input = None;
+    let mut engine = Engine::default();
+    let mut target = None;
+    let mut from = None;
+    let mut output = None;
+    let mut tape = bf::DEFAULT_TAPE;
+    let mut eof = Eof::default();
+    let mut dot = false;
+    let mut check = false;
+    let mut write = false;
+    // only the subcommands that run or compile a program have words and memory
+    let machine = mode == Mode::Run || mode == Mode::Debug || mode == Mode::Compile;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} expects a value", arg));
+        match arg.as_str() {
+            "--word-size" if machine => {
+                let value = value()?;
+                word_size = value.parse().map_err(|_| format!("invalid word size {}", value))?;
+            },
+            "--overflow" if machine => overflow = value()?.parse()?,
+            "--memory" if machine => memory = value()?.parse()?,
+            "--input" if mode == Mode::Debug => input = Some(value()?.clone()),
+            "--engine" if mode == Mode::Run => engine = value()?.parse()?,
+            "--target" if mode == Mode::Compile => target = Some(value()?.parse()?),
+            "--from" if mode == Mode::Transpile => from = Some(value()?.parse()?),
+            "-o" if mode == Mode::Compile || mode == Mode::Transpile => output = Some(value()?.clone()),
+            "--tape" if mode == Mode::Bf => {
+                let value = value()?;
+                tape = match value.parse() {
+                    Ok(0) | Err(_) => return Err(format!("invalid tape length {}", value)),
+                    Ok(cells) if cells > bf::MAX_TAPE => return Err(format!("tape length {} is too long, at most {} cells", value, bf::MAX_TAPE)),
+                    Ok(cells) => cells
+                };
+            },
+            "--eof" if mode == Mode::Bf => eof = value()?.parse()?,
+            "--dot" if mode == Mode::Cfg => dot = true,
+            "--check" if mode == Mode::Fmt => check = true,
+            "--write" if mode == Mode::Fmt => write = true,
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument {}", arg))
         }
-    };
-    let labels = match self::eval::scan_labels(&program) {
-        eval::ScanResult::Missing(missing) => {
-            println!("ERROR: Missing the folowing labels");
-            println!("{:?}", missing);
-            return;
-        },
-        eval::ScanResult::Unused(unused, labels) => {
-            println!("warning: the folowing labels are unused");
-            println!("{:?}", unused);
-            labels
+    }
+    match path {
+        Some(path) => Ok(Options{path, word_size, overflow, memory, input, engine, target, from, output, tape, eof, dot, check, write}),
+        None => Err("missing source file".to_string())
+    }
+}
+
+fn report(diagnostics: &Diagnostics, path: &str, content: &str) {
+    let mut diagnostics = diagnostics.clone();
+    diagnostics.set_file(path);
+    eprint!("{}", diagnostics.render(content));
+}
+
+fn read_source(path: &str) -> Result<String, i32> {
+    fs::read_to_string(path).map_err(|e| {
+        eprintln!("Unable to read file {}: {}", path, e);
+        1
+    })
+}
+
+// read and build a program, printing diagnostics against the file
+fn load(path: &str) -> Result<(String, Program), i32> {
+    let content = read_source(path)?;
+    match Program::from_source(&content) {
+        Ok(program) => {
+            report(program.warnings(), path, &content);
+            Ok((content, program))
         },
-        eval::ScanResult::Duplicate(dup) => {
-            println!("ERROR: Duplicate Label Found:");
-            println!("{}", dup);
-            return;
+        Err(diagnostics) => {
+            report(&diagnostics, path, &content);
+            Err(1)
         }
-        eval::ScanResult::Ok(labels) => labels
+    }
+}
+
+fn build_word(options: &Options) -> Result<Word, i32> {
+    Word::new(options.word_size, options.overflow).map_err(|e| {
+        eprintln!("{}", e);
+        2
+    })
+}
+
+fn build_machine(options: &Options, program: Program, input: Box<dyn Read>) -> Result<Machine, i32> {
+    let word = build_word(options)?;
+    let mut machine = Machine::with_io(program, input, Box::new(io::stdout()));
+    machine.set_word(word);
+    machine.set_memory(options.memory);
+    machine.set_engine(options.engine);
+    Ok(machine)
+}
+
+fn run(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Run).map_err(usage)?;
+    let (content, program) = load(&options.path)?;
+    let mut machine = build_machine(&options, program, Box::new(io::stdin()))?;
+    let status = machine.run();
+    // a closed stdout, like piping into head, is an error rather than a panic
+    let written = machine.flush().and_then(|_| match status {
+        Status::Halted => writeln!(io::stdout(), "Program Halted"),
+        _ => Ok(())
+    });
+    if let Err(e) = written {
+        eprintln!("Unable to write output: {}", e);
+        return Err(1);
+    }
+    if status == Status::Trapped {
+        let diagnostic = machine.error_diagnostic().unwrap();
+        report(&diagnostic.into(), &options.path, &content);
+        return Err(1);
+    }
+    Ok(())
+}
+
+fn debug(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Debug).map_err(usage)?;
+    // the debugger owns stdin, so program input comes from a file
+    let input: Box<dyn Read> = match &options.input {
+        None => Box::new(io::empty()),
+        Some(file) => match fs::File::open(file) {
+            Ok(f) => Box::new(f),
+            Err(e) => {
+                eprintln!("Unable to read file {}: {}", file, e);
+                return Err(1);
+            }
+        }
+    };
+    let (content, program) = load(&options.path)?;
+    let machine = build_machine(&options, program, input)?;
+    let mut debugger = Debugger::new(machine, &content);
+    let stdin = io::stdin();
+    debugger.repl(stdin.lock(), io::stdout()).map_err(|e| {
+        eprintln!("{}", e);
+        1
+    })
+}
+
+fn compile(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Compile).map_err(usage)?;
+    let target = options.target.ok_or_else(|| usage("missing --target".to_string()))?;
+    let (_, program) = load(&options.path)?;
+    let settings = Settings{word: build_word(&options)?, memory: options.memory, file: options.path.clone()};
+    let code = backend::compile(&program, target, &settings).map_err(|e| {
+        eprintln!("{}", e);
+        1
+    })?;
+    write_output(&options, &code)
+}
+
+fn write_output(options: &Options, code: &[u8]) -> Result<(), i32> {
+    let written = match &options.output {
+        Some(file) => fs::write(file, code).map_err(|e| format!("Unable to write file {}: {}", file, e)),
+        None => io::stdout().write_all(code).map_err(|e| e.to_string())
     };
-    let mut init_ctx = self::eval::Context::new(labels);
-    self::eval::eval(&mut program, &mut init_ctx);
+    written.map_err(|e| {
+        eprintln!("{}", e);
+        1
+    })
+}
+
+fn transpile(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Transpile).map_err(usage)?;
+    let from = options.from.ok_or_else(|| usage("missing --from".to_string()))?;
+    let content = read_source(&options.path)?;
+    let code = transpile::transpile(&content, from).map_err(|diagnostics| {
+        report(&diagnostics, &options.path, &content);
+        1
+    })?;
+    write_output(&options, code.as_bytes())
+}
+
+fn control_flow(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Cfg).map_err(usage)?;
+    if !options.dot {
+        return Err(usage("missing --dot".to_string()));
+    }
+    let (_, program) = load(&options.path)?;
+    let graph = cfg::build(program.lines(), program.labels());
+    print!("{}", cfg::to_dot(&graph, program.lines()));
+    Ok(())
+}
+
+fn format_source(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Fmt).map_err(usage)?;
+    if options.check && options.write {
+        return Err(usage("--check and --write can't be used together".to_string()));
+    }
+    let content = read_source(&options.path)?;
+    let formatted = format::format(&content).map_err(|diagnostics| {
+        report(&diagnostics, &options.path, &content);
+        1
+    })?;
+    if options.check {
+        if formatted != content {
+            eprintln!("{} is not formatted", options.path);
+            return Err(1);
+        }
+        Ok(())
+    }
+    else if options.write {
+        fs::write(&options.path, formatted).map_err(|e| {
+            eprintln!("Unable to write file {}: {}", options.path, e);
+            1
+        })
+    }
+    else {
+        print!("{}", formatted);
+        Ok(())
+    }
+}
+
+// language server over stdin and stdout, exits with 1 if the client
+// didn't ask it to shut down first
+fn language_server(args: &[String]) -> Result<(), i32> {
+    if let Some(arg) = args.first() {
+        return Err(usage(format!("unexpected argument {}", arg)));
+    }
+    let stdin = io::stdin();
+    match lsp::serve(stdin.lock(), io::stdout()) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(1),
+        Err(e) => {
+            eprintln!("{}", e);
+            Err(1)
+        }
+    }
+}
+
+fn brainfuck(args: &[String]) -> Result<(), i32> {
+    let options = parse_options(args, Mode::Bf).map_err(usage)?;
+    let content = read_source(&options.path)?;
+    let program = bf::parse(&content).map_err(|diagnostics| {
+        report(&diagnostics, &options.path, &content);
+        1
+    })?;
+    let mut ctx = bf::Context::new(options.tape, options.eof, Box::new(io::stdin()), Box::new(io::stdout()));
+    bf::eval(&program, &mut ctx).map_err(|e| {
+        report(&program.error(e, &ctx).into(), &options.path, &content);
+        1
+    })
+}
+
+fn usage(message: String) -> i32 {
+    if !message.is_empty() {
+        eprintln!("{}", message);
+    }
+    eprintln!("{}", USAGE);
+    2
+}
+
+fn moonwalk_main() -> Result<(), i32> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None => Err(usage(String::new())),
+        Some("run") => run(&args[1..]),
+        Some("debug") => debug(&args[1..]),
+        Some("compile") => compile(&args[1..]),
+        Some("transpile") => transpile(&args[1..]),
+        Some("cfg") => control_flow(&args[1..]),
+        Some("fmt") => format_source(&args[1..]),
+        Some("lsp") => language_server(&args[1..]),
+        Some("bf") => brainfuck(&args[1..]),
+        Some(_) => run(&args)
+    }
 }
 
 fn main() {
-    moonwalk_main();
-    //infinite10();
-    //printInput();
+    if let Err(code) = moonwalk_main() {
+        process::exit(code);
+    }
 }
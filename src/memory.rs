@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+pub const DEFAULT_SIZE: usize = 65536;
+// the most cells fixed memory can have, 2GiB of 64 bit cells
+pub const MAX_SIZE: usize = 1 << 28;
+
+// how memory is laid out, chosen on the command line with --memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryModel {
+    // a fixed number of cells, any address past the end is an error
+    Fixed(usize),
+    // every address is valid, only cells that were written take up space
+    Sparse
+}
+
+impl Default for MemoryModel {
+    fn default() -> MemoryModel {
+        MemoryModel::Fixed(DEFAULT_SIZE)
+    }
+}
+
+impl FromStr for MemoryModel {
+    type Err = String;
+
+    // `sparse` or a cell count in decimal or hex
+    fn from_str(s: &str) -> Result<MemoryModel, String> {
+        if s == "sparse" {
+            return Ok(MemoryModel::Sparse);
+        }
+        let size = match s.strip_prefix("0x") {
+            Some(hex) => usize::from_str_radix(hex, 16),
+            None => s.parse()
+        };
+        match size {
+            Ok(size) if size > MAX_SIZE => Err(format!("memory size {} is too big, at most {} cells or sparse", s, MAX_SIZE)),
+            Ok(size) => Ok(MemoryModel::Fixed(size)),
+            Err(_) => Err(format!("invalid memory model {}, expected a size or sparse", s))
+        }
+    }
+}
+
+impl fmt::Display for MemoryModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryModel::Fixed(size) => write!(f, "{}", size),
+            MemoryModel::Sparse => write!(f, "sparse")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Memory {
+    Fixed(Vec<usize>),
+    Sparse(HashMap<usize, usize>)
+}
+
+impl Default for Memory {
+    fn default() -> Memory {
+        Memory::new(MemoryModel::default())
+    }
+}
+
+impl Memory {
+    pub fn new(model: MemoryModel) -> Memory {
+        match model {
+            MemoryModel::Fixed(size) => Memory::Fixed(vec![0; size]),
+            MemoryModel::Sparse => Memory::Sparse(HashMap::new())
+        }
+    }
+
+    pub fn model(&self) -> MemoryModel {
+        match self {
+            Memory::Fixed(cells) => MemoryModel::Fixed(cells.len()),
+            Memory::Sparse(_) => MemoryModel::Sparse
+        }
+    }
+
+    // None when the address is out of bounds
+    pub fn get(&self, addr: usize) -> Option<usize> {
+        match self {
+            Memory::Fixed(cells) => cells.get(addr).copied(),
+            Memory::Sparse(cells) => Some(cells.get(&addr).copied().unwrap_or(0))
+        }
+    }
+
+    // false when the address is out of bounds
+    pub fn set(&mut self, addr: usize, val: usize) -> bool {
+        match self {
+            Memory::Fixed(cells) => match cells.get_mut(addr) {
+                Some(cell) => {
+                    *cell = val;
+                    true
+                },
+                None => false
+            },
+            Memory::Sparse(cells) => {
+                // keep the map from filling up with zeroes
+                if val == 0 {
+                    cells.remove(&addr);
+                }
+                else {
+                    cells.insert(addr, val);
+                }
+                true
+            }
+        }
+    }
+
+    // number of addressable cells, None if unbounded
+    pub fn size(&self) -> Option<usize> {
+        match self {
+            Memory::Fixed(cells) => Some(cells.len()),
+            Memory::Sparse(_) => None
+        }
+    }
+}
@@ -2,38 +2,107 @@ use std::result::Result;
 use std::collections::VecDeque;
 
 use crate::ast;
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::lex::SpannedToken;
+use crate::reversible;
 
-pub fn parse_src(tokens: &mut VecDeque<ast::Token>) -> Result<ast::Source, &'static str> {
-    use crate::ast::Token::{*};
-    let mut derefs = 0;
-    while !tokens.is_empty() {
-        match tokens.front().unwrap() {
-            Deref => {
-                derefs += 1;
-                tokens.pop_front();
+// token queue the parse functions consume from, it remembers
+// where the input ends so errors at the end have a location
+pub struct Tokens {
+    q: VecDeque<SpannedToken>,
+    prev: Span,
+    eof: Span
+}
+
+impl Tokens {
+    pub fn new(tokens: Vec<SpannedToken>) -> Tokens {
+        let eof = match tokens.last() {
+            None => Span{start: 0, end: 0, line: 1, column: 1},
+            Some(SpannedToken{token: ast::Token::Newlines(n), span}) => {
+                Span{start: span.end, end: span.end, line: span.line + n, column: 1}
             },
-            _ => break // parens are done
+            Some(SpannedToken{span, ..}) => Span{
+                start: span.end,
+                end: span.end,
+                line: span.line,
+                column: span.column + (span.end - span.start)
+            }
+        };
+        Tokens{q: VecDeque::from(tokens), prev: eof, eof}
+    }
+
+    pub fn front(&self) -> Option<&ast::Token> {
+        self.q.front().map(|t| &t.token)
+    }
+
+    pub fn pop_front(&mut self) -> Option<ast::Token> {
+        self.pop_spanned().map(|t| t.token)
+    }
+
+    pub fn pop_spanned(&mut self) -> Option<SpannedToken> {
+        let tok = self.q.pop_front();
+        if let Some(t) = &tok {
+            self.prev = t.span;
         }
+        tok
     }
 
-    let mut src = match tokens.pop_front().unwrap() {
-        Reg(r) => ast::Source::Reg(r),
-        Num(n) => ast::Source::Addr(n),
-        Literal => match tokens.pop_front() {
-            None => return Err("unexpected end of program"),
-            Some(tok) => match tok {
-                Num(n) => ast::Source::Literal(n),
-                _ => return Err("expected number after $")
+    pub fn push_front(&mut self, tok: SpannedToken) {
+        self.q.push_front(tok);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.q.is_empty()
+    }
+
+    // span of the next token, or the end of input
+    pub fn span(&self) -> Span {
+        self.q.front().map_or(self.eof, |t| t.span)
+    }
+
+    // span of the last token taken off the queue
+    pub fn prev_span(&self) -> Span {
+        self.prev
+    }
+
+    // error pointing at the next token
+    pub fn error(&self, message: &str) -> Diagnostic {
+        Diagnostic::error(self.span(), message)
+    }
+}
+
+pub fn parse_src(tokens: &mut Tokens) -> Result<ast::Source, Diagnostic> {
+    use crate::ast::Token::{*};
+    let mut derefs = 0;
+    while let Some(Deref) = tokens.front() {
+        derefs += 1;
+        tokens.pop_front();
+    }
+
+    let mut src = match tokens.front() {
+        None => return Err(tokens.error("unexpected end of program")),
+        Some(Reg(_)) | Some(Num(_)) | Some(Literal) => match tokens.pop_front().unwrap() {
+            Reg(r) => ast::Source::Reg(r),
+            Num(n) => ast::Source::Addr(n),
+            _ => match tokens.front() {
+                Some(Num(n)) => {
+                    let n = *n;
+                    tokens.pop_front();
+                    ast::Source::Literal(n)
+                },
+                None => return Err(tokens.error("unexpected end of program")),
+                _ => return Err(tokens.error("expected number after $"))
             }
-        }
-        t => return Err("invalid source or destination")
+        },
+        _ => return Err(tokens.error("invalid source or destination"))
     };
 
     while derefs > 0 {
         src = ast::Source::Deref(Box::new(src));
         derefs -= 1;
     }
-    return Ok(src);
+    Ok(src)
 }
 
 pub fn src2dest(src: ast::Source) -> Result<ast::Dest, &'static str> {
@@ -45,46 +114,104 @@ pub fn src2dest(src: ast::Source) -> Result<ast::Dest, &'static str> {
     }
 }
 
-pub fn pop_if_ident(q: &mut VecDeque<ast::Token>) -> Option<String> {
+pub fn parse_dest(q: &mut Tokens) -> Result<ast::Dest, Diagnostic> {
+    let start = q.span();
+    let src = parse_src(q)?;
+    src2dest(src).map_err(|e| Diagnostic::error(start.to(q.prev_span()), e))
+}
+
+pub fn pop_if_ident(q: &mut Tokens) -> Option<String> {
     // if there is no label immediately after jump token, it
     // becomes bare jump
-    match q.front_mut() {
-        Some(tok) => match tok {
-            ast::Token::Identifier(ident) => {
-                let res = Some(ident.clone());
-                q.pop_front();
-                res
-            },
-            _ => None
+    match q.front() {
+        Some(ast::Token::Identifier(ident)) => {
+            let res = Some(ident.clone());
+            q.pop_front();
+            res
         },
-        None => None
+        _ => None
     }
 }
 
-pub fn parse_inst(mut q: &mut VecDeque<ast::Token>) -> Result<ast::Instruction, &'static str> {
+pub fn parse_inst(q: &mut Tokens) -> Result<ast::Instruction, Diagnostic> {
     use crate::ast::Token::{*};
+    let span = q.span();
     match q.pop_front() {
-        None => Err("unexpected end of program"),
+        None => Err(Diagnostic::error(span, "unexpected end of program")),
         Some(tok) => match tok {
             Halt => Ok(ast::Instruction::Halt),
             Backwards => Ok(ast::Instruction::Backwards),
             Forwards => Ok(ast::Instruction::Forwards),
             Reverse => Ok(ast::Instruction::Reverse),
-            Jump => Ok(ast::Instruction::Jump(pop_if_ident(&mut q))),
-            From => Ok(ast::Instruction::From(pop_if_ident(&mut q))),
+            Jump => Ok(ast::Instruction::Jump(pop_if_ident(q))),
+            From => Ok(ast::Instruction::From(pop_if_ident(q))),
+            // unlike jump there's no bare call, return is what pops
+            Call => pop_if_ident(q).map(ast::Instruction::Call)
+                .ok_or_else(|| Diagnostic::error(span.to(q.prev_span()), "call needs a label to go to")),
+            Return => Ok(ast::Instruction::Return),
             Inc => {
-                parse_src(&mut q)
-                .and_then(|src| src2dest(src))
+                parse_dest(q)
                 .and_then(|dest| {
-                    parse_src(&mut q).map(|src| {
+                    parse_src(q).map(|src| {
                         ast::Instruction::Inc(dest, src)
                     })
                 })
             },
-            Io => parse_src(&mut q).map(|src| {
+            Move | Unmove => {
+                parse_dest(q)
+                .and_then(|dest| {
+                    parse_src(q).map(|src| match tok {
+                        Move => ast::Instruction::Move(dest, src),
+                        _ => ast::Instruction::Unmove(dest, src)
+                    })
+                })
+            },
+            Swap => {
+                parse_dest(q)
+                .and_then(|left| {
+                    parse_dest(q).map(|right| {
+                        ast::Instruction::Swap(left, right)
+                    })
+                })
+            },
+            Xor => {
+                let dest = parse_dest(q)?;
+                let src = parse_src(q)?;
+                // xor A A zeroes A and xor A *A reads somewhere else the
+                // second time, neither can be undone
+                if reversible::aliases(&dest, &src) {
+                    return Err(Diagnostic::error(span.to(q.prev_span()), "xor can't read its source through its destination, it wouldn't undo itself"));
+                }
+                Ok(ast::Instruction::Xor(dest, src))
+            },
+            Mul => {
+                let dest = parse_dest(q)?;
+                let start = q.span();
+                match parse_src(q)? {
+                    // truncating to a smaller word keeps the low bit, so an
+                    // odd k can be undone whatever the word size
+                    ast::Source::Literal(k) if k % 2 == 1 => Ok(ast::Instruction::Mul(dest, k)),
+                    ast::Source::Literal(_) => Err(Diagnostic::error(start.to(q.prev_span()), "mul can only be undone by an odd number")),
+                    _ => Err(Diagnostic::error(start.to(q.prev_span()), "mul needs a literal like $3"))
+                }
+            },
+            Rol | Ror => {
+                let dest = parse_dest(q)?;
+                let src = parse_src(q)?;
+                // rotating A by A rotates back by a different amount
+                if reversible::aliases(&dest, &src) {
+                    return Err(Diagnostic::error(span.to(q.prev_span()), "a rotation can't read its amount through its destination, it couldn't be undone"));
+                }
+                Ok(match tok {
+                    Rol => ast::Instruction::Rol(dest, src),
+                    _ => ast::Instruction::Ror(dest, src)
+                })
+            },
+            Neg => parse_dest(q).map(ast::Instruction::Neg),
+            Io => parse_src(q).map(|src| {
                 ast::Instruction::Io(src)
             }),
-            _ => Err("Not an Instruction")
+            _ => Err(Diagnostic::error(span, "Not an Instruction"))
         }
     }
 }
@@ -122,183 +249,185 @@ pub fn precedence(a: &ast::Token) -> usize {
         _ => 5,
     }
 }
-pub fn op_map(tok: ast::Token, a: ast::Expr, b: ast::Expr) -> Option<ast::Expr> {
-    use crate::ast::Token::{*};
-    let ba = Box::new(a);
-    let bb = Box::new(b);
 
-    Some(match tok {
-        Or => ast::Expr::Or(ba, bb),
-        And => ast::Expr::And(ba, bb),
-        Gte => ast::Expr::Gte(ba, bb),
-        Gt => ast::Expr::Gt(ba, bb),
-        Eq => ast::Expr::Eq(ba, bb),
-        Lt => ast::Expr::Lt(ba, bb),
-        Lte => ast::Expr::Lte(ba, bb),
-        _ => return None
-    })
+// expressions are two sorted: sources are integer valued and
+// only comparisons, and, or, forwards and backwards are conditions
+pub enum Operand {
+    Value(ast::Source),
+    Cond(ast::Expr)
 }
 
-pub fn op_pop(operators: &mut Vec<ast::Token>, operands: &mut Vec<ast::Expr>) ->
-    Result<(), &'static str>
+pub fn op_map(tok: ast::Token, a: Operand, b: Operand) -> Result<ast::Expr, &'static str> {
+    use crate::ast::Token::{*};
+    match tok {
+        Or | And => match (a, b) {
+            (Operand::Cond(a), Operand::Cond(b)) => {
+                let ba = Box::new(a);
+                let bb = Box::new(b);
+                Ok(match tok {
+                    Or => ast::Expr::Or(ba, bb),
+                    _ => ast::Expr::And(ba, bb)
+                })
+            },
+            _ => Err("type error, and/or expect conditions on both sides")
+        },
+        Gte | Gt | Eq | Lt | Lte => match (a, b) {
+            (Operand::Value(a), Operand::Value(b)) => Ok(match tok {
+                Gte => ast::Expr::Gte(a, b),
+                Gt => ast::Expr::Gt(a, b),
+                Eq => ast::Expr::Eq(a, b),
+                Lt => ast::Expr::Lt(a, b),
+                _ => ast::Expr::Lte(a, b)
+            }),
+            _ => Err("type error, comparisons expect values on both sides")
+        },
+        Open => Err("mismatched Parentheses"),
+        _ => Err("malformed expression, Invalid operator")
+    }
+}
+
+pub fn op_pop(operators: &mut Vec<SpannedToken>, operands: &mut Vec<(Operand, Span)>) ->
+    Result<(), Diagnostic>
 {
     let top = match operators.pop() {
         Some(op) => op,
-        None => return Err("mismatched Parentheses")
+        None => return Err(Diagnostic::error(None, "mismatched Parentheses"))
     };
-    let b = match operands.pop() {
+    let (b, bspan) = match operands.pop() {
         Some(expr) => expr,
-        None => return Err("malformed expression, not enough operands")
+        None => return Err(Diagnostic::error(top.span, "malformed expression, not enough operands"))
     };
-    let a = match operands.pop() {
+    let (a, aspan) = match operands.pop() {
         Some(expr) => expr,
-        None => return Err("malformed expression, not enough operands")
+        None => return Err(Diagnostic::error(top.span, "malformed expression, not enough operands"))
+    };
+    let span = aspan.to(bspan);
+    match op_map(top.token, a, b) {
+        Ok(expr) => operands.push((Operand::Cond(expr), span)),
+        Err(e) => return Err(Diagnostic::error(top.span, e))
     };
-    return Ok(operands.push(match op_map(top, a, b) {
-        Some(res) => res,
-        None => return Err("malformed expression, Invalid operator")
-    }));
+    Ok(())
 }
 
-pub fn parse_expr(mut q: &mut VecDeque<ast::Token>) -> Result<ast::Expr, &'static str> {
+pub fn parse_expr(q: &mut Tokens) -> Result<ast::Expr, Diagnostic> {
     use crate::ast::Token::{*};
-    let mut operands: Vec<ast::Expr> = vec![];
-    let mut operators: Vec<ast::Token> = vec![];
+    let mut operands: Vec<(Operand, Span)> = vec![];
+    let mut operators: Vec<SpannedToken> = vec![];
+    let start = q.span();
 
     // and now for the tricky bit
-    while !q.is_empty() {
-        match expr_type(q.front().unwrap()) {
+    while let Some(front) = q.front() {
+        let span = q.span();
+        match expr_type(front) {
             ExprType::None => {break;},
             ExprType::Terminal => {
-                match q.front().unwrap() {
+                match front {
                     Backwards => {
                         q.pop_front();
-                        operands.push(ast::Expr::Backwards);
+                        operands.push((Operand::Cond(ast::Expr::Backwards), span));
                     },
                     Forwards => {
                         q.pop_front();
-                        operands.push(ast::Expr::Forwards);
+                        operands.push((Operand::Cond(ast::Expr::Forwards), span));
                     },
                     Close => {
                         q.pop_front();
                         loop {
-                            match operators[operators.len() - 1] {
-                                Open => {
+                            match operators.last() {
+                                None => return Err(Diagnostic::error(span, "mismatched Parentheses")),
+                                Some(SpannedToken{token: Open, ..}) => {
                                     operators.pop();
                                     break;
                                 },
-                                _ => match op_pop(&mut operators, &mut operands){
-                                    Ok(_) => continue,
-                                    Err(e) => return Err(e)
-                                }
+                                _ => op_pop(&mut operators, &mut operands)?
                             };
                         };
                     }
-                    _ => match parse_src(&mut q) {
-                        Ok(src) => operands.push(ast::Expr::Lit(src)),
-                        Err(e) => return Err(e)
+                    _ => {
+                        let src = parse_src(q)?;
+                        operands.push((Operand::Value(src), span.to(q.prev_span())));
                     }
                 };
             }
             ExprType::NonTerminal => {
-                match q.front().unwrap() {
-                    Open => operators.push(q.pop_front().unwrap()),
+                match front {
+                    Open => operators.push(q.pop_spanned().unwrap()),
                     _ => {
-                        while operators.len() > 0 {
-                            let top = &operators[operators.len() - 1];
-                            if precedence(top) < precedence(q.front().unwrap()) {
+                        while let Some(top) = operators.last() {
+                            if precedence(&top.token) < precedence(front) {
                                 break;
                             }
-                            match op_pop(&mut operators, &mut operands){
-                                Ok(res) => res,
-                                Err(e) => return Err(e)
-                            };
+                            op_pop(&mut operators, &mut operands)?;
                         }
-                        operators.push(q.pop_front().unwrap());
+                        operators.push(q.pop_spanned().unwrap());
                     }
                 }
             }
         }
     }
     // final eval
-    while operators.len() > 0 {
-        match op_pop(&mut operators, &mut operands){
-            Ok(res) => res,
-            Err(e) => return Err(e)
-        };
+    while !operators.is_empty() {
+        op_pop(&mut operators, &mut operands)?;
     }
     if operands.len() != 1 {
-        return Err("malformed expression")
+        return Err(Diagnostic::error(start.to(q.prev_span()), "malformed expression"))
+    }
+    match operands.pop().unwrap() {
+        (Operand::Cond(expr), _) => Ok(expr),
+        (Operand::Value(_), span) => Err(Diagnostic::error(span, "type error, expected a condition but found a value"))
     }
-    return Ok(operands.pop().unwrap());
 }
 
-pub fn parse_cond(mut q: &mut VecDeque<ast::Token>) -> Result<Option<ast::Expr>, &'static str> {
+pub fn parse_cond(q: &mut Tokens) -> Result<Option<ast::Expr>, Diagnostic> {
     use crate::ast::Token::{*};
     // clear writespace or comments
-    while !q.is_empty() {
-        match q.front_mut().unwrap() {
-            Nop => {q.pop_front();}
-            _ => {break;}
-        }
+    while let Some(Nop) | Some(Comment(_)) = q.front() {
+        q.pop_front();
     }
-    match q.front_mut() {
+    match q.front() {
         None => Ok(None),
         Some(tok) => match tok {
             If => {
                 q.pop_front();
-                parse_expr(&mut q).map(|expr| Some(expr))
+                parse_expr(q).map(Some)
             }
             Newlines(_) => Ok(None),
-            _ => Err("Expected if condition or newline")
+            _ => Err(q.error("Expected if condition or newline"))
         }
     }
 }
 
-pub fn parse(tokens: Vec<ast::Token>) -> Result<Vec<ast::Line>, (usize, &'static str)> {
+pub fn parse(tokens: Vec<SpannedToken>) -> Result<Vec<ast::Line>, Diagnostic> {
     use crate::ast::Token::{*};
-    let mut q = VecDeque::from(tokens);
+    let mut q = Tokens::new(tokens);
     let mut lines = vec![];
-    let mut lineno = 1;
 
-    'start: while !q.is_empty() {
+    while let Some(tok) = q.pop_spanned() {
+        let start = tok.span;
         let mut label: Option<String> = None;
         // read label
-        match q.pop_front().unwrap() {
-            Nop => continue,
-            Newlines(n) => {
-                lineno += n;
-                match label {
-                    Some(_) => return Err((lineno, "no newline after label")),
-                    None => continue
-                }
-            },
+        match tok.token {
+            Nop | Comment(_) | Newlines(_) => continue,
             Identifier(ident) => match q.pop_front() {
-                Some(Label) => {
-                    match label {
-                        Some(_) => return Err((lineno, "already labeled")),
-                        None => label = Some(ident.to_string())
-                    }
-                },
-                _ => return Err((lineno, "malformed label"))
+                Some(Label) => label = Some(ident),
+                _ => return Err(Diagnostic::error(start, "malformed label"))
             },
-            tok => q.push_front(tok) // successful label or no label
+            _ => q.push_front(tok) // no label
+        }
+        while let Some(Nop) | Some(Comment(_)) = q.front() {
+            q.pop_front();
         }
 
         // read instruction
+        let inst = parse_inst(&mut q)?;
+        let cond = parse_cond(&mut q)?;
         lines.push(ast::Line{
             label,
-            inst: match parse_inst(&mut q) {
-                Ok(inst) => inst,
-                Err(e) => return Err((lineno, e))
-            },
-            cond: match parse_cond(&mut q) {
-                Ok(cond) => cond,
-                Err(e) => return Err((lineno, e))
-            },
+            inst,
+            cond,
             stack: Vec::new(),
+            span: start.to(q.prev_span())
         });
-
     }
-    return Ok(lines);
+    Ok(lines)
 }
@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use crate::ast;
+use crate::cst::Cst;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::eval;
+use crate::macros;
+use crate::reversible;
+
+// a lexed, parsed and linked moonwalk program ready to be loaded into a Machine
+#[derive(Debug)]
+pub struct Program {
+    pub(crate) lines: Vec<ast::Line>,
+    pub(crate) labels: HashMap<String, usize>,
+    pub(crate) warnings: Diagnostics
+}
+
+impl Program {
+    pub fn from_source(source: &str) -> Result<Program, Diagnostics> {
+        // everything is found in the expanded text, then moved back to
+        // where it came from in the source
+        let expanded = macros::expand(source)?;
+        let relocate = |diagnostics: &Diagnostics| {
+            let mut relocated = Diagnostics::new();
+            for diagnostic in diagnostics {
+                relocated.push(expanded.relocate(diagnostic));
+            }
+            relocated
+        };
+        let mut program = Program::build(expanded.text()).map_err(|errors| relocate(&errors))?;
+        program.warnings = relocate(&program.warnings);
+        for line in program.lines.iter_mut() {
+            line.span = expanded.locate(line.span);
+        }
+        Ok(program)
+    }
+
+    fn build(source: &str) -> Result<Program, Diagnostics> {
+        let lines = Cst::parse(source)?.to_ast()?;
+        let mut warnings = Diagnostics::new();
+        let labels = match eval::scan_labels(&lines) {
+            eval::ScanResult::Missing(missing) => {
+                // point at every jump and from that goes nowhere
+                let mut errors = Diagnostics::new();
+                for label in missing {
+                    for line in lines.iter().filter(|line| eval::jump_target(&line.inst) == Some(&label)) {
+                        errors.push(Diagnostic::error(line.span, format!("missing label {}", label)));
+                    }
+                }
+                return Err(errors);
+            },
+            eval::ScanResult::Unused(unused, labels) => {
+                for label in unused {
+                    let span = lines.iter().find(|line| line.label.as_ref() == Some(&label)).map(|line| line.span);
+                    warnings.push(Diagnostic::warning(span, format!("unused label {}", label)));
+                }
+                labels
+            },
+            eval::ScanResult::Duplicate(dup) => {
+                // the second definition is the one in the wrong
+                let span = lines.iter()
+                    .filter(|line| line.label.as_ref() == Some(&dup))
+                    .nth(1)
+                    .map(|line| line.span);
+                return Err(Diagnostic::error(span, format!("duplicate label {}", dup)).into());
+            },
+            eval::ScanResult::Ok(labels) => labels
+        };
+        warnings.extend(reversible::check(&lines));
+        Ok(Program{lines, labels, warnings})
+    }
+
+    pub fn lines(&self) -> &[ast::Line] {
+        &self.lines
+    }
+
+    pub fn labels(&self) -> &HashMap<String, usize> {
+        &self.labels
+    }
+
+    // non fatal diagnostics produced while building the program
+    pub fn warnings(&self) -> &Diagnostics {
+        &self.warnings
+    }
+}
@@ -0,0 +1,132 @@
+use crate::ast;
+use crate::bytecode::Operand;
+use crate::diagnostic::{Diagnostic, Diagnostics};
+use crate::eval::Place;
+
+// a dereferenced literal is just a memory address, so *$5 and 5 are the
+// same place and compare equal
+fn normalize(op: Operand) -> Operand {
+    match op.base {
+        Place::Literal(addr) if op.depth > 0 => Operand{base: Place::Mem(addr), depth: op.depth - 1},
+        _ => op
+    }
+}
+
+// every place reading an operand touches, from its base out to the value
+fn chain(op: Operand) -> Vec<Operand> {
+    (0..=op.depth)
+        .map(|depth| normalize(Operand{depth, ..op}))
+        .filter(|op| !matches!(op.base, Place::Literal(_)))
+        .collect()
+}
+
+// whether reading src touches the place dest writes, which would make xor
+// zero it or read somewhere else running it again
+pub fn aliases(dest: &ast::Dest, src: &ast::Source) -> bool {
+    chain(Operand::source(src)).contains(&normalize(Operand::dest(dest)))
+}
+
+fn cond_reads(expr: &ast::Expr, out: &mut Vec<Operand>) {
+    match expr {
+        ast::Expr::Forwards | ast::Expr::Backwards => (),
+        ast::Expr::Or(left, right) | ast::Expr::And(left, right) => {
+            cond_reads(left, out);
+            cond_reads(right, out);
+        },
+        ast::Expr::Eq(left, right) | ast::Expr::Gt(left, right) | ast::Expr::Gte(left, right)
+        | ast::Expr::Lt(left, right) | ast::Expr::Lte(left, right) => {
+            out.extend(chain(Operand::source(left)));
+            out.extend(chain(Operand::source(right)));
+        }
+    }
+}
+
+// Some(true) if the condition only holds going forwards, Some(false) if
+// only going backwards
+fn direction(expr: &ast::Expr) -> Option<bool> {
+    match expr {
+        ast::Expr::Forwards => Some(true),
+        ast::Expr::Backwards => Some(false),
+        ast::Expr::And(left, right) => direction(left).or_else(|| direction(right)),
+        ast::Expr::Or(left, right) => match (direction(left), direction(right)) {
+            (Some(l), Some(r)) if l == r => Some(l),
+            _ => None
+        },
+        _ => None
+    }
+}
+
+// the places a line writes when run forwards
+fn writes(inst: &ast::Instruction) -> Vec<Operand> {
+    match inst {
+        ast::Instruction::Inc(dest, _) | ast::Instruction::Move(dest, _) | ast::Instruction::Unmove(dest, _)
+        | ast::Instruction::Xor(dest, _) | ast::Instruction::Mul(dest, _) | ast::Instruction::Rol(dest, _)
+        | ast::Instruction::Ror(dest, _) | ast::Instruction::Neg(dest) => vec![normalize(Operand::dest(dest))],
+        ast::Instruction::Swap(left, right) => vec![normalize(Operand::dest(left)), normalize(Operand::dest(right))],
+        ast::Instruction::Io(src) => Some(normalize(Operand::source(src))).into_iter().filter(|op| !matches!(op.base, Place::Literal(_))).collect(),
+        _ => Vec::new()
+    }
+}
+
+// warn about lines whose forward effect running them backwards can't undo,
+// lines that only run in one direction are left alone since they aren't
+// meant to be undone. Places are only compared by how they're written, so
+// derefs that happen to meet at runtime aren't caught
+pub fn check(lines: &[ast::Line]) -> Diagnostics {
+    let mut warnings = Diagnostics::new();
+    let mut conds = Vec::new();
+    // conditions that only hold going forwards don't steer a reversal
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(expr) = line.cond.as_ref().filter(|expr| direction(expr) != Some(true)) {
+            let mut places = Vec::new();
+            cond_reads(expr, &mut places);
+            conds.push((i, places));
+        }
+    }
+
+    for line in lines {
+        if line.cond.as_ref().and_then(direction).is_some() {
+            continue;
+        }
+        let written = writes(&line.inst);
+        if written.is_empty() {
+            continue;
+        }
+        match &line.inst {
+            ast::Instruction::Inc(_, src) => {
+                let read = chain(Operand::source(src));
+                if read.last() == Some(&written[0]) {
+                    warnings.push(Diagnostic::warning(line.span, "inc adds its destination to itself, going backwards always leaves 0"));
+                }
+                else if read.contains(&written[0]) {
+                    warnings.push(Diagnostic::warning(line.span, "inc finds its source through its destination, going backwards reads a different place"));
+                }
+            },
+            // swapping A with *A moves the address, so the second swap is
+            // between different places
+            ast::Instruction::Swap(left, right) => {
+                let (left, right) = (normalize(Operand::dest(left)), normalize(Operand::dest(right)));
+                let through = |outer: Operand, inner: Operand| chain(outer)[..outer.depth].contains(&inner);
+                if left != right && (through(left, right) || through(right, left)) {
+                    warnings.push(Diagnostic::warning(line.span, "swap finds one place through the other, going backwards swaps different places"));
+                }
+            },
+            _ => ()
+        }
+        let mut places = Vec::new();
+        if let Some(expr) = &line.cond {
+            cond_reads(expr, &mut places);
+        }
+        if written.iter().any(|w| places.contains(w)) {
+            warnings.push(Diagnostic::warning(line.span, "the condition reads a place this line changes, so it may not hold going backwards"));
+        }
+        else if let ast::Instruction::Io(_) = line.inst {
+            let used = conds.iter().find(|(_, places)| places.contains(&written[0]));
+            if let Some((cond_line, _)) = used {
+                warnings.push(Diagnostic::warning(line.span, "io reads into a place a condition depends on, going backwards doesn't restore it")
+                    .with_note(lines[*cond_line].span, "the condition is on this line"));
+            }
+        }
+    }
+    warnings
+}
@@ -0,0 +1,137 @@
+use std::fmt::{self, Write};
+use std::str::FromStr;
+
+use crate::bf;
+use crate::diagnostic::Diagnostics;
+
+// languages `moonwalk transpile` can read, chosen with --from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    Bf
+}
+
+impl FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Language, String> {
+        match s {
+            "bf" => Ok(Language::Bf),
+            _ => Err(format!("unknown language {}, expected bf", s))
+        }
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Language::Bf => write!(f, "bf")
+        }
+    }
+}
+
+// translate source in another language into moonwalk source
+pub fn transpile(source: &str, from: Language) -> Result<String, Diagnostics> {
+    match from {
+        Language::Bf => bf::parse(source).map(|program| from_bf(&program))
+    }
+}
+
+// `.` calls this with the pointer in A, the bare jump lets it return to
+// whichever line jumped here, and running backwards makes io write
+const BF_PRINT: &str = "\
+jump main
+
+forwards
+print: jump
+io *A if backwards
+backwards
+
+main: forwards
+";
+
+// which way a run of the same instruction moves the pointer
+fn step(inst: &bf::Instruction) -> Option<bool> {
+    match inst {
+        bf::Instruction::Right => Some(true),
+        bf::Instruction::Left => Some(false),
+        _ => None
+    }
+}
+
+// what a run of + and - adds to the cell, as a byte
+fn change(inst: &bf::Instruction) -> Option<u8> {
+    match inst {
+        bf::Instruction::Inc => Some(1),
+        bf::Instruction::Dec => Some(u8::MAX),
+        _ => None
+    }
+}
+
+// the pointer lives in A and the tape is memory from address 0
+pub fn from_bf(program: &bf::Program) -> String {
+    let mut out = String::from("; transpiled from brainfuck by moonwalk\n");
+    let program = &program.insts[..];
+    if program.contains(&bf::Instruction::Out) {
+        out.push_str(BF_PRINT);
+    }
+    // loops are numbered as they open, the ones still open are on the stack
+    let (mut loops, mut open) = (0, Vec::new());
+    let mut i = 0;
+    while i < program.len() {
+        if let Some(up) = step(&program[i]) {
+            let mut count = 0usize;
+            while i < program.len() && step(&program[i]) == Some(up) {
+                count += 1;
+                i += 1;
+            }
+            // going left is adding to the word's max minus A, xor with the
+            // max flips A between the two, so nothing relies on wrapping
+            if up {
+                writeln!(out, "inc A ${}", count).unwrap();
+            }
+            else {
+                writeln!(out, "xor A ${}\ninc A ${}\nxor A ${}", usize::MAX, count, usize::MAX).unwrap();
+            }
+            continue;
+        }
+        if change(&program[i]).is_some() {
+            let mut amount = 0u8;
+            while let Some(by) = program.get(i).and_then(change) {
+                amount = amount.wrapping_add(by);
+                i += 1;
+            }
+            // cells stay below 256 without ever overflowing a word, so it
+            // works under any --overflow. B is set while a cell wraps, which
+            // takes 256 - amount off by adding it to 255 minus the cell. a
+            // wrapped cell ends up below amount and any other at or above it,
+            // so B can be cleared the same way going either direction
+            if amount > 0 {
+                let rest = 256 - amount as usize;
+                writeln!(out, "xor B $1 if *A >= ${}", rest).unwrap();
+                writeln!(out, "inc *A ${} if B = $0", amount).unwrap();
+                writeln!(out, "xor *A $255 if B = $1\ninc *A ${} if B = $1\nxor *A $255 if B = $1", rest).unwrap();
+                writeln!(out, "xor B $1 if *A < ${}", amount).unwrap();
+            }
+            continue;
+        }
+        match program[i] {
+            bf::Instruction::In => out.push_str("io *A\n"),
+            bf::Instruction::Out => out.push_str("jump print\n"),
+            bf::Instruction::Open(_) => {
+                writeln!(out, "jump end-{} if *A = $0", loops).unwrap();
+                writeln!(out, "loop-{}: forwards", loops).unwrap();
+                open.push(loops);
+                loops += 1;
+            },
+            bf::Instruction::Close(_) => {
+                // parse matched every bracket
+                let n = open.pop().unwrap();
+                writeln!(out, "jump loop-{} if *A > $0", n).unwrap();
+                writeln!(out, "end-{}: forwards", n).unwrap();
+            },
+            _ => ()
+        }
+        i += 1;
+    }
+    out
+}
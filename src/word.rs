@@ -0,0 +1,152 @@
+use std::fmt;
+use std::str::FromStr;
+
+// what inc does when a result doesn't fit in a word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // modular arithmetic, the only policy where every inc can be undone exactly
+    Wrapping,
+    // stop the program with a runtime error
+    Trapping,
+    // clamp to 0 or the largest word, not reversible once it clamps
+    Saturating
+}
+
+impl FromStr for Overflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Overflow, String> {
+        match s {
+            "wrap" | "wrapping" => Ok(Overflow::Wrapping),
+            "trap" | "trapping" => Ok(Overflow::Trapping),
+            "saturate" | "saturating" => Ok(Overflow::Saturating),
+            _ => Err(format!("unknown overflow policy {}, expected wrap, trap or saturate", s))
+        }
+    }
+}
+
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Overflow::Wrapping => write!(f, "wrap"),
+            Overflow::Trapping => write!(f, "trap"),
+            Overflow::Saturating => write!(f, "saturate")
+        }
+    }
+}
+
+// width of registers and memory cells and how arithmetic on them overflows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Word {
+    bits: u32,
+    overflow: Overflow
+}
+
+impl Default for Word {
+    fn default() -> Word {
+        Word{bits: usize::BITS, overflow: Overflow::Wrapping}
+    }
+}
+
+impl Word {
+    pub fn new(bits: u32, overflow: Overflow) -> Result<Word, String> {
+        match bits {
+            8 | 16 | 32 | 64 if bits <= usize::BITS => Ok(Word{bits, overflow}),
+            _ => Err(format!("unsupported word size {}, expected 8, 16, 32 or 64", bits))
+        }
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    // largest value a word can hold
+    pub fn max(&self) -> usize {
+        usize::MAX >> (usize::BITS - self.bits)
+    }
+
+    // reduce a value read from a source, like a wide literal, to a word
+    pub fn truncate(&self, val: usize) -> usize {
+        val & self.max()
+    }
+
+    // None when the policy is to trap and the result overflowed
+    pub fn add(&self, a: usize, b: usize) -> Option<usize> {
+        let (a, b) = (self.truncate(a), self.truncate(b));
+        let sum = a.wrapping_add(b);
+        let overflowed = sum < a || sum > self.max();
+        match (overflowed, self.overflow) {
+            (false, _) => Some(sum),
+            (true, Overflow::Wrapping) => Some(self.truncate(sum)),
+            (true, Overflow::Trapping) => None,
+            (true, Overflow::Saturating) => Some(self.max())
+        }
+    }
+
+    pub fn sub(&self, a: usize, b: usize) -> Option<usize> {
+        let (a, b) = (self.truncate(a), self.truncate(b));
+        match (a.checked_sub(b), self.overflow) {
+            (Some(diff), _) => Some(diff),
+            (None, Overflow::Wrapping) => Some(self.truncate(a.wrapping_sub(b))),
+            (None, Overflow::Trapping) => None,
+            (None, Overflow::Saturating) => Some(0)
+        }
+    }
+
+    pub fn mul(&self, a: usize, k: usize) -> Option<usize> {
+        let (a, k) = (self.truncate(a), self.truncate(k));
+        match (a.checked_mul(k).filter(|&p| p <= self.max()), self.overflow) {
+            (Some(product), _) => Some(product),
+            (None, Overflow::Wrapping) => Some(self.truncate(a.wrapping_mul(k))),
+            (None, Overflow::Trapping) => None,
+            (None, Overflow::Saturating) => Some(self.max())
+        }
+    }
+
+    // undo mul. Wrapping multiplies by the inverse of k, trapping only
+    // divides values mul could have made and saturating rounds down
+    pub fn div(&self, a: usize, k: usize) -> Option<usize> {
+        let (a, k) = (self.truncate(a), self.truncate(k));
+        match self.overflow {
+            Overflow::Wrapping => self.inverse(k).map(|inv| self.truncate(a.wrapping_mul(inv))),
+            Overflow::Trapping => a.checked_rem(k).filter(|&r| r == 0).map(|_| a / k),
+            Overflow::Saturating => a.checked_div(k)
+        }
+    }
+
+    // the multiplicative inverse of k modulo 2^bits, which only odd numbers
+    // have. Each newton step doubles the bits that are right, 3 to start
+    pub fn inverse(&self, k: usize) -> Option<usize> {
+        if k.is_multiple_of(2) {
+            return None;
+        }
+        let mut inv = k;
+        for _ in 0..5 {
+            inv = inv.wrapping_mul(2usize.wrapping_sub(k.wrapping_mul(inv)));
+        }
+        Some(self.truncate(inv))
+    }
+
+    // rotations move bits around the word and never overflow
+    pub fn rol(&self, a: usize, n: usize) -> usize {
+        let (a, n) = (self.truncate(a), (n % self.bits as usize) as u32);
+        match n {
+            0 => a,
+            _ => self.truncate(a << n | a >> (self.bits - n))
+        }
+    }
+
+    pub fn ror(&self, a: usize, n: usize) -> usize {
+        let n = n % self.bits as usize;
+        self.rol(a, (self.bits as usize - n) % self.bits as usize)
+    }
+
+    // two's complement, it's its own inverse so it always wraps
+    pub fn neg(&self, a: usize) -> usize {
+        self.truncate(a.wrapping_neg())
+    }
+}
@@ -0,0 +1,70 @@
+use moonwalk::ast::{self, Register};
+use moonwalk::bytecode::Engine;
+use moonwalk::word::{Overflow, Word};
+use moonwalk::{Machine, Program, RuntimeError, Status};
+
+fn run(source: &str, word: Word, engine: Engine) -> Machine {
+    let program = Program::from_source(source).expect("program should build");
+    let mut machine = Machine::with_io(program, Box::new(std::io::empty()), Box::new(std::io::sink()));
+    machine.set_word(word);
+    machine.set_engine(engine);
+    machine.run();
+    machine
+}
+
+fn byte(overflow: Overflow) -> Word {
+    Word::new(8, overflow).unwrap()
+}
+
+#[test]
+fn parses_and_prints() {
+    let program = Program::from_source("mul A $0x3\nrol *B C\nror 4 $1\nneg D\n").unwrap();
+    assert_eq!(program.lines()[0].inst, ast::Instruction::Mul(ast::Dest::Reg(Register::A), 3));
+    let printed: Vec<_> = program.lines().iter().map(|l| l.to_string()).collect();
+    assert_eq!(printed, vec!["mul A $3", "rol *B C", "ror 4 $1", "neg D"]);
+}
+
+#[test]
+fn rejects_what_cant_be_undone() {
+    let error = |source: &str| Program::from_source(source).unwrap_err().iter().next().unwrap().message.clone();
+    assert!(error("mul A $0\n").contains("odd number"));
+    assert!(error("mul A $6\n").contains("odd number"));
+    assert!(error("mul A B\n").contains("needs a literal"));
+    assert!(error("rol A A\n").contains("through its destination"));
+    assert!(error("ror B *B\n").contains("through its destination"));
+    assert!(Program::from_source("mul A $0x101\nrol *B A\n").is_ok());
+}
+
+#[test]
+fn each_direction() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("inc A $100\nmul A $3\ninc B $0x81\nrol B $1\ninc C $3\nror C B\nneg D\n", byte(Overflow::Wrapping), engine);
+        assert_eq!(m.register(&Register::A), 44);
+        assert_eq!(m.register(&Register::B), 3);
+        assert_eq!(m.register(&Register::C), 0x60);
+        assert_eq!(m.register(&Register::D), 0);
+        // going back through the same lines undoes them, except line 0
+        let source = "inc D $1\ninc A $50\nmul A $3\ninc B $0x81\nrol B A\nror B $5\nneg A\ninc 2 $9\nmul 2 $0xb\nbackwards\n";
+        for overflow in [Overflow::Wrapping, Overflow::Saturating] {
+            let m = run(source, byte(overflow), engine);
+            assert_eq!(m.status(), Status::Finished);
+            assert_eq!((m.register(&Register::A), m.register(&Register::B)), (0, 0));
+            assert_eq!(m.mem().get(2), Some(0));
+        }
+    }
+}
+
+#[test]
+fn mul_follows_the_overflow_policy() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("inc A $100\nmul A $3\n", byte(Overflow::Trapping), engine);
+        assert_eq!(m.error(), Some(&RuntimeError::Overflow{pc: 1}));
+        let m = run("inc A $100\nmul A $3\n", byte(Overflow::Saturating), engine);
+        assert_eq!(m.register(&Register::A), 255);
+        // 44 isn't a multiple of 3, so no mul could have made it
+        let m = run("inc A $44\nmul A $3 if backwards\nbackwards\n", byte(Overflow::Trapping), engine);
+        assert_eq!(m.error(), Some(&RuntimeError::Overflow{pc: 1}));
+        let m = run("inc A $44\nmul A $3 if backwards\nbackwards\n", Word::default(), engine);
+        assert_eq!(m.register(&Register::A), 44usize.wrapping_mul(0xaaaaaaaaaaaaaaab));
+    }
+}
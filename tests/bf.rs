@@ -0,0 +1,95 @@
+use std::io;
+
+use moonwalk::bf::{self, Context, Eof, Error, Instruction};
+
+mod common;
+use common::SharedBuf;
+
+fn run(source: &str, input: &str, cells: usize, eof: Eof) -> (Result<(), Error>, Vec<u8>, Context) {
+    let program = bf::parse(source).expect("program should parse");
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut ctx = Context::new(cells, eof, Box::new(input), Box::new(out.clone()));
+    let result = bf::eval(&program, &mut ctx);
+    let bytes = out.0.borrow().clone();
+    (result, bytes, ctx)
+}
+
+const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+#[test]
+fn hello_world() {
+    let (result, out, _) = run(HELLO, "", bf::DEFAULT_TAPE, Eof::Zero);
+    assert_eq!(result, Ok(()));
+    assert_eq!(out, b"Hello World!\n");
+}
+
+#[test]
+fn cells_wrap() {
+    let (result, out, ctx) = run("-.>+[+]", "", 4, Eof::Zero);
+    assert_eq!(result, Ok(()));
+    assert_eq!(out, [255]);
+    assert_eq!(ctx.tape(), [255, 0, 0, 0]);
+}
+
+#[test]
+fn pointer_stays_on_the_tape() {
+    assert_eq!(run("<", "", 4, Eof::Zero).0, Err(Error::LeftOfTape));
+    let (result, _, ctx) = run("+[>+]", "", 4, Eof::Zero);
+    assert_eq!(result, Err(Error::RightOfTape{cells: 4}));
+    assert_eq!(ctx.ptr(), 3);
+    assert_eq!(run(">>>", "", 4, Eof::Zero).0, Ok(()));
+    // a tape too long to allocate is cut down to the longest one
+    let (_, _, ctx) = run("", "", usize::MAX, Eof::Zero);
+    assert_eq!(ctx.tape().len(), bf::MAX_TAPE);
+}
+
+#[test]
+fn eof_behavior() {
+    let cell = |eof| run("+++,,", "a", 1, eof).2.tape()[0];
+    assert_eq!(cell(Eof::Zero), 0);
+    assert_eq!(cell(Eof::Max), 255);
+    assert_eq!(cell(Eof::Unchanged), b'a');
+    assert_eq!(run(",[.,]", "echo", 1, Eof::Zero).1, b"echo");
+}
+
+#[test]
+fn comments_are_ignored() {
+    let program = bf::parse("add + then [loop - done]").unwrap();
+    assert_eq!(program.insts, vec![Instruction::Inc, Instruction::Open(3), Instruction::Dec, Instruction::Close(1)]);
+    assert_eq!(program.spans.iter().map(|span| span.start).collect::<Vec<_>>(), vec![4, 11, 17, 23]);
+}
+
+#[test]
+fn unmatched_brackets() {
+    let diagnostics = bf::parse("+]\n[[-]").unwrap_err();
+    let found: Vec<_> = diagnostics.iter().map(|d| (d.message.as_str(), d.line(), d.column())).collect();
+    assert_eq!(found, vec![("unmatched ]", Some(1), Some(2)), ("unmatched [", Some(2), Some(1))]);
+}
+
+#[test]
+fn parses_long_programs() {
+    // quadratic parsing took minutes on this
+    let source = format!("{}{}{}", "[".repeat(5000), "+-><".repeat(200_000), "]".repeat(5000));
+    let program = bf::parse(&source).unwrap();
+    assert_eq!(program.insts.len(), 810_000);
+}
+
+#[test]
+fn runs_deeply_nested_loops() {
+    // one native stack frame per loop used to overflow on this
+    let source = format!("+{}-{}.", "[".repeat(100_000), "]".repeat(100_000));
+    let (result, out, _) = run(&source, "", 1, Eof::Zero);
+    assert_eq!(result, Ok(()));
+    assert_eq!(out, [0]);
+}
+
+#[test]
+fn errors_point_at_the_instruction() {
+    let source = "+[>\n+>>]";
+    let program = bf::parse(source).unwrap();
+    let (result, _, ctx) = run(source, "", 3, Eof::Zero);
+    assert_eq!(result, Err(Error::RightOfTape{cells: 3}));
+    let diagnostic = program.error(result.unwrap_err(), &ctx);
+    assert_eq!((diagnostic.line(), diagnostic.column()), (Some(2), Some(3)));
+}
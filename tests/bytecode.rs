@@ -0,0 +1,84 @@
+use std::io;
+
+use moonwalk::bytecode::{self, CondOp, Engine};
+use moonwalk::{Machine, Program, Status};
+
+mod common;
+use common::SharedBuf;
+
+fn run(source: &str, input: &str, engine: Engine) -> (Machine, String) {
+    let program = Program::from_source(source).expect("program should build");
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut machine = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    machine.set_engine(engine);
+    machine.run();
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (machine, text)
+}
+
+// both engines should leave the machine in exactly the same state
+fn same(source: &str, input: &str) -> Status {
+    let (tree, tree_out) = run(source, input, Engine::Tree);
+    let (code, code_out) = run(source, input, Engine::Bytecode);
+    assert_eq!(tree.status(), code.status(), "status of {:?}", source);
+    assert_eq!(tree.error(), code.error(), "error of {:?}", source);
+    assert_eq!(tree_out, code_out, "output of {:?}", source);
+    assert_eq!(tree.pc(), code.pc(), "pc of {:?}", source);
+    assert_eq!(tree.forward(), code.forward(), "direction of {:?}", source);
+    let regs = |m: &Machine| (m.context().a, m.context().b, m.context().c, m.context().d);
+    assert_eq!(regs(&tree), regs(&code), "registers of {:?}", source);
+    assert_eq!(tree.mem(), code.mem(), "memory of {:?}", source);
+    let stacks = |m: &Machine| m.lines().iter().map(|l| l.stack.clone()).collect::<Vec<_>>();
+    assert_eq!(stacks(&tree), stacks(&code), "stacks of {:?}", source);
+    tree.status()
+}
+
+#[test]
+fn examples_match() {
+    assert_eq!(same(include_str!("../hello.mw"), ""), Status::Finished);
+    assert_eq!(same(include_str!("../testJmp.mw"), ""), Status::Finished);
+    assert_eq!(same(include_str!("../testIO.mw"), "q"), Status::Finished);
+    assert_eq!(same(include_str!("../test1.mw"), ""), Status::Trapped);
+}
+
+#[test]
+fn conditions_match() {
+    same("inc A $3\ninc B $1 if A = $3 or *A > $1\ninc C $1 if A < $3 and forwards\n", "");
+    // the right side would be out of bounds, so these only pass if both short circuit
+    same("inc A $1 if forwards or 0xdeadbeefabcdef = $0\n", "");
+    same("inc A $1 if backwards and 0xdeadbeefabcdef = $0\n", "");
+    same("inc A $1 if (backwards or A >= $0) and (forwards and A <= $0)\n", "");
+    same("inc A $1 if backwards or 0xdeadbeefabcdef = $0\n", "");
+}
+
+#[test]
+fn jumps_match() {
+    same("jump f\ninc A $1\nf: jump\ninc B $1\n", "");
+    same("jump f\ninc A $1\nf: inc B $1\n", "");
+    same("inc A $1\nf: from\nbackwards\nfrom f\ninc B $1\n", "");
+    same("backwards\n", "");
+    same("forwards\nhalt if A = $2\ninc A $1\nreverse\n", "");
+}
+
+#[test]
+fn errors_match() {
+    same("io $3\n", "");
+    same("inc *A $1\ninc A 0xdeadbeefabcdef\n", "");
+    same("io A\nio B\nbackwards\n", "\u{80}");
+}
+
+#[test]
+fn flattens_conditions() {
+    let program = Program::from_source("inc A $1 if forwards or backwards and A = $0\n").unwrap();
+    let code = bytecode::compile(program.lines(), program.labels());
+    assert_eq!(code.insns[0].cond, Some((0, 5)));
+    assert!(matches!(code.conds[..], [CondOp::Forwards, CondOp::Or(3), CondOp::Backwards, CondOp::And(1), CondOp::Cmp(..)]));
+}
+
+#[test]
+fn parses_engines() {
+    assert_eq!("tree".parse(), Ok(Engine::Tree));
+    assert_eq!("bytecode".parse(), Ok(Engine::Bytecode));
+    assert!("jit".parse::<Engine>().is_err());
+}
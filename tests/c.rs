@@ -0,0 +1,127 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use moonwalk::backend::{self, Settings, Target};
+use moonwalk::word::{Overflow, Word};
+use moonwalk::Program;
+
+mod common;
+use common::same;
+
+// compile with the system C compiler and run, None if there isn't one
+fn native(name: &str, source: &str, input: &str, word: Word) -> Option<(String, i32)> {
+    let program = Program::from_source(source).unwrap();
+    let settings = Settings{word, file: format!("{}.mw", name), ..Settings::default()};
+    let code = backend::compile(&program, Target::C, &settings).unwrap();
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let c_file = dir.join(format!("{}.c", name));
+    let exe = dir.join(name);
+    fs::write(&c_file, code).unwrap();
+    let built = Command::new("cc").arg("-std=c99").arg("-O1").arg("-o").arg(&exe).arg(&c_file).status().ok()?;
+    assert!(built.success(), "generated C for {} did not compile", name);
+    let mut child = Command::new(&exe).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::null()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    let output = child.wait_with_output().unwrap();
+    Some((String::from_utf8(output.stdout).unwrap(), output.status.code().unwrap()))
+}
+
+
+#[test]
+fn examples_match() {
+    same(native, "hello", include_str!("../hello.mw"), "", Word::default());
+    same(native, "jmp", include_str!("../testJmp.mw"), "", Word::default());
+    same(native, "io", include_str!("../testIO.mw"), "q", Word::default());
+    same(native, "halt", include_str!("../testc.mw"), "", Word::default());
+    same(native, "trap", include_str!("../test1.mw"), "", Word::default());
+}
+
+#[test]
+fn control_flow_matches() {
+    // print a register three times through a bare jump "function"
+    let source = "\
+jump main
+print: jump
+io A if backwards
+inc B $1 if backwards
+backwards
+main: inc A $0x41
+jump print
+halt if B = $3
+jump print
+";
+    same(native, "call", source, "", Word::default());
+    same(native, "from", "inc A $1\nf: from\nbackwards\nfrom f\ninc B $1\n", "", Word::default());
+    same(native, "reverse", "forwards\nhalt if A = $2\ninc A $1\nreverse\n", "", Word::default());
+    same(native, "off_start", "backwards\n", "", Word::default());
+    same(native, "conds", "inc A $3\nio A if A = $3 or 0xdeadbeefabcdef = $0\nhalt if (backwards or A >= $1) and *A = $0\n", "", Word::default());
+}
+
+#[test]
+fn memory_and_io_match() {
+    same(native, "deref", "inc A $0x10\ninc *A $0x20\ninc **A $0x42\nio **A if backwards\nreverse\n", "", Word::default());
+    same(native, "echo", "io A\nio *B\nio B if backwards\nreverse\n", "xyz", Word::default());
+    same(native, "out_of_bounds", "inc A $0xffffff\ninc *A $1\n", "", Word::default());
+    same(native, "literal_input", "io $1\n", "", Word::default());
+    same(native, "not_ascii", "inc A $200\nio A if backwards\nreverse\n", "", Word::default());
+}
+
+#[test]
+fn swap_and_xor_match() {
+    same(native, "swap_xor", "inc A $72\ninc 5 $105\ninc B $5\nswap A *B\nxor C $33\nswap C 6\nxor 6 A\nio 6 if backwards\nio A if backwards\nbackwards\n", "", Word::default());
+    same(native, "swap_oob", "inc A $1\nswap A 0xffffff\n", "", Word::default());
+}
+
+#[test]
+fn moves_match() {
+    same(native, "moves", "inc A $2\nmove A $5\nmove A B\ninc B $3\nmove 3 B\nmove *B $8\n", "", Word::default());
+    same(native, "move_back", "inc A $72\nio A if backwards\nmove A $105\nio A if backwards\nmove 3 A\nio 3 if backwards\nmove *B $33\nunmove A $33\nbackwards\n", "", Word::default());
+    same(native, "unmove_empty", "inc A $49\nunmove A $9\nio A if backwards\nbackwards\n", "", Word::default());
+    same(native, "move_oob", "move 0xffffff $1\n", "", Word::default());
+}
+
+#[test]
+fn calls_match() {
+    same(native, "call_twice", "inc B $48\ncall add\ncall add\njump end\nadd: inc B $1\nio B if backwards\nreturn\nend: from\nbackwards\n", "", Word::default());
+    same(native, "call_sum", "inc A $1\ninc D $48\ncall f\njump end\nf: inc C $1\ninc D C\nio D if backwards\ncall f if C < $3\nneg C\ninc C $1\nneg C\nreturn\nend: from\nbackwards\n", "", Word::default());
+    same(native, "call_turn", "inc A $1\ncall f\nhalt\nf: backwards if A = $1\nreturn\n", "", Word::default());
+    same(native, "call_return", "return\ninc A $49\nio A if backwards\nbackwards\n", "", Word::default());
+    same(native, "call_zero", "f: backwards if A = $49\ninc A $48\nio A if backwards\nreturn\ninc A $1\ncall f\n", "", Word::default());
+    same(native, "call_from", "f: from\ninc A $49\nio A if backwards\ncall g\nbackwards\ng: from\nreturn\n", "", Word::default());
+}
+
+// each halts when the result is right for its word, so agreeing on the
+// status and output means agreeing on the value
+const ARITH: &[&str] = &[
+    "inc A $100\nmul A $3\nhalt if A = $44\n",
+    "inc A $44\nhalt if backwards and A = $100\nmul A $3 if backwards\nbackwards\n",
+    "inc A $0x81\nrol A $1\nhalt if A = $3\n",
+    "inc B $68\ninc A $0x10\nror A B\nhalt if A = $1\n",
+    "inc A $1\nneg A\nhalt if A = $0xff\n",
+    "inc B $3\ninc 3 $5\nmul *B $7\nneg 3\nrol *B $2\nhalt if 3 = $119\n",
+    "inc A $0x8000000000000001\nrol A $4\nhalt if A = $0x18\n",
+    "inc A $0x5555555555555556\nmul A $3\nhalt if A = $2\n",
+];
+
+#[test]
+fn arithmetic_matches() {
+    for bits in [8, 64] {
+        for overflow in [Overflow::Wrapping, Overflow::Trapping, Overflow::Saturating] {
+            for (i, source) in ARITH.iter().enumerate() {
+                same(native, &format!("arith_{}_{}_{}", i, bits, overflow), source, "", Word::new(bits, overflow).unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn overflow_matches() {
+    let source = "inc A $250\ninc A $10\nhalt if A = $4\n";
+    same(native, "wrap", source, "", Word::new(8, Overflow::Wrapping).unwrap());
+    same(native, "trap_overflow", source, "", Word::new(8, Overflow::Trapping).unwrap());
+    same(native, "saturate", source, "", Word::new(8, Overflow::Saturating).unwrap());
+    let source = "forwards\nhalt if A = $0xffff\ninc A $1 if backwards\nbackwards\n";
+    same(native, "underflow", source, "", Word::new(16, Overflow::Wrapping).unwrap());
+    same(native, "trap_underflow", source, "", Word::new(16, Overflow::Trapping).unwrap());
+}
@@ -0,0 +1,100 @@
+use moonwalk::ast::{self, Register};
+use moonwalk::bytecode::Engine;
+use moonwalk::cfg::{self, EdgeKind, Exit, Node};
+use moonwalk::{Machine, Program, Status};
+
+fn run(source: &str, engine: Engine) -> Machine {
+    let program = Program::from_source(source).expect("program should build");
+    let mut machine = Machine::new(program);
+    machine.set_engine(engine);
+    machine.run();
+    machine
+}
+
+// adds A to B twice, bumping A after each
+const TWICE: &str = "inc A $1\ncall add\ncall add\njump end\nadd: inc B A\ninc A $1\nreturn\nend: from\n";
+
+// sums 1 to 3 into B, calling itself until C, the depth, reaches 3. C goes
+// back down before each return so the condition holds the same both ways
+const SUM: &str = "inc A $1\ncall f\njump end\nf: inc C $1\ninc B C\ncall f if C < $3\nneg C\ninc C $1\nneg C\nreturn\nend: from\n";
+
+#[test]
+fn parses_and_prints() {
+    let program = Program::from_source("f: call f\nreturn if A = $0\n").unwrap();
+    assert_eq!(program.lines()[0].inst, ast::Instruction::Call("f".to_string()));
+    assert_eq!(program.lines()[1].to_string(), "return if A = $0");
+    // there's no bare call, and calling nowhere is as wrong as jumping there
+    assert!(Program::from_source("call\n").unwrap_err().iter().next().unwrap().message.contains("needs a label"));
+    assert!(Program::from_source("call nowhere\n").is_err());
+}
+
+#[test]
+fn calls_share_one_stack() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run(TWICE, engine);
+        assert_eq!(m.status(), Status::Finished);
+        assert_eq!((m.register(&Register::A), m.register(&Register::B)), (3, 3));
+        assert!(m.context().calls.is_empty());
+        // each call remembers the return it came back by
+        assert_eq!(m.lines()[1].stack, vec![6]);
+        assert_eq!(m.lines()[2].stack, vec![6]);
+        // recursion works because the callee doesn't own the stack
+        let m = run(SUM, engine);
+        assert_eq!((m.register(&Register::B), m.register(&Register::C)), (6, 0));
+        assert!(m.context().calls.is_empty());
+    }
+}
+
+#[test]
+fn backwards_re_enters_and_returns_out() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        // line 0 is never run backwards, so A keeps the 1 from it
+        let m = run(&format!("{}backwards\n", TWICE), engine);
+        assert_eq!(m.status(), Status::Finished);
+        assert_eq!((m.register(&Register::A), m.register(&Register::B)), (1, 0));
+        assert!(m.lines().iter().all(|line| line.stack.is_empty()));
+        let m = run(&format!("{}backwards\n", SUM), engine);
+        assert_eq!((m.register(&Register::B), m.register(&Register::C)), (0, 0));
+        assert!(m.context().calls.is_empty());
+    }
+}
+
+#[test]
+fn turning_inside_a_callee_returns_out() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        // the callee turns on its first line, which undoes the call
+        let m = run("inc A $1\ncall f\nhalt\nf: backwards if A = $1\nreturn\n", engine);
+        assert_eq!(m.status(), Status::Finished);
+        assert!(m.context().calls.is_empty());
+        // a return with nothing to go back to carries on, like a bare jump
+        let m = run("return\ninc A $1\n", engine);
+        assert_eq!(m.register(&Register::A), 1);
+    }
+}
+
+#[test]
+fn step_back_undoes_calls() {
+    let program = Program::from_source(SUM).unwrap();
+    let mut m = Machine::new(program);
+    m.record_history();
+    while m.step() == Status::Running && m.context().calls.len() < 3 {}
+    assert_eq!(m.context().calls, vec![1, 5, 5]);
+    assert!(m.step_back());
+    assert_eq!(m.context().calls, vec![1, 5]);
+    assert_eq!(m.run(), Status::Finished);
+    m.rewind_to(0);
+    assert!(m.context().calls.is_empty());
+    assert!(m.lines().iter().all(|line| line.stack.is_empty()));
+}
+
+#[test]
+fn graph_follows_calls() {
+    let program = Program::from_source(TWICE).unwrap();
+    let graph = cfg::build(program.lines(), program.labels());
+    let has = |from: usize, forward: bool, to: usize, kind: EdgeKind| graph.edges.iter().any(|edge| {
+        edge.from == Node{line: from, forward} && edge.to == Exit::Node(Node{line: to, forward}) && edge.kind == kind
+    });
+    assert!(has(1, true, 4, EdgeKind::Jump));
+    assert!(has(6, true, 2, EdgeKind::Return));
+    assert!(has(6, true, 3, EdgeKind::Return));
+}
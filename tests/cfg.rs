@@ -0,0 +1,84 @@
+use moonwalk::cfg::{self, Edge, EdgeKind, Exit, Node};
+use moonwalk::Program;
+
+fn build(source: &str) -> (Program, cfg::Cfg) {
+    let program = Program::from_source(source).expect("program should build");
+    let graph = cfg::build(program.lines(), program.labels());
+    (program, graph)
+}
+
+fn fwd(line: usize) -> Exit {
+    Exit::Node(Node{line, forward: true})
+}
+
+fn back(line: usize) -> Exit {
+    Exit::Node(Node{line, forward: false})
+}
+
+fn edges(graph: &cfg::Cfg) -> Vec<(Exit, Exit, EdgeKind)> {
+    graph.edges.iter().map(|e: &Edge| (Exit::Node(e.from), e.to, e.kind)).collect()
+}
+
+#[test]
+fn jump_and_return_through_a_direction_change() {
+    let (_, graph) = build(include_str!("../testJmp.mw"));
+    assert_eq!(edges(&graph), vec![
+        (fwd(0), fwd(5), EdgeKind::Jump),
+        (fwd(5), fwd(6), EdgeKind::Next),
+        // jumping to a bare jump carries on past it
+        (fwd(6), fwd(3), EdgeKind::Jump),
+        (fwd(3), fwd(4), EdgeKind::Next),
+        (fwd(4), back(3), EdgeKind::Turn),
+        (back(3), back(2), EdgeKind::Next),
+        (back(2), back(1), EdgeKind::Next),
+        (back(1), fwd(2), EdgeKind::Turn),
+        (fwd(2), Exit::Finish, EdgeKind::Return),
+        (fwd(2), fwd(3), EdgeKind::Next)
+    ]);
+    // line 0 is never entered backwards
+    assert!(!graph.nodes.contains(&Node{line: 0, forward: false}));
+}
+
+#[test]
+fn conditions_annotate_both_edges() {
+    let (_, graph) = build("inc A $1\nhalt if A = $1 or backwards\ninc B $1\n");
+    let conditional: Vec<_> = graph.edges.iter().filter(|e| e.from.line == 1).collect();
+    assert_eq!(conditional.len(), 2);
+    assert_eq!((conditional[0].to, conditional[0].kind), (Exit::Halt, EdgeKind::Next));
+    assert_eq!((conditional[1].to, conditional[1].kind), (fwd(2), EdgeKind::Skip));
+    assert!(conditional.iter().all(|e| e.cond.as_deref() == Some("A = $1 or backwards")));
+}
+
+#[test]
+fn direction_settles_some_conditions() {
+    let (_, graph) = build("io A if backwards\ninc A $1 if forwards\n");
+    assert_eq!(edges(&graph), vec![
+        (fwd(0), fwd(1), EdgeKind::Next),
+        (fwd(1), Exit::Finish, EdgeKind::Next)
+    ]);
+    assert!(graph.edges.iter().all(|e| e.cond.is_none()));
+}
+
+#[test]
+fn from_is_followed_backwards() {
+    let (_, graph) = build("inc A $1\ntop: inc B $1\nreverse\nfrom top\n");
+    assert!(edges(&graph).contains(&(fwd(2), back(1), EdgeKind::Turn)));
+    assert!(edges(&graph).contains(&(back(1), Exit::Finish, EdgeKind::Next)));
+    // from does nothing going forwards, and nothing reaches line 3 anyway
+    assert!(!graph.nodes.iter().any(|n| n.line == 3));
+}
+
+#[test]
+fn dot_output() {
+    let (program, graph) = build("start: inc A $1 if A < $3\nreverse\nhalt\n");
+    let dot = cfg::to_dot(&graph, program.lines());
+    assert!(dot.starts_with("digraph moonwalk {\n"));
+    assert!(dot.contains("subgraph cluster_forwards"));
+    assert!(dot.contains("f0 [label=\"1: start: inc A $1 if A < $3\"];"));
+    assert!(dot.contains("f1 [label=\"2: reverse\", shape=diamond];"));
+    assert!(dot.contains("f0 -> f1 [label=\"if A < $3\"];"));
+    assert!(dot.contains("f0 -> f1 [label=\"unless A < $3\", style=dashed];"));
+    // reversing on line 1 backs onto line 0, which finishes
+    assert!(dot.contains("f1 -> finish [color=red];"));
+    assert!(dot.ends_with("}\n"));
+}
@@ -0,0 +1,51 @@
+#![allow(dead_code)]
+// not every test file uses everything here
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use moonwalk::word::Word;
+use moonwalk::{Machine, Program, Status};
+
+// output the test keeps a handle on after giving a clone to the program
+#[derive(Clone, Default)]
+pub struct SharedBuf(pub Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// what `moonwalk run` would print and exit with
+pub fn interpret(source: &str, input: &str, word: Word) -> (String, i32) {
+    let program = Program::from_source(source).unwrap();
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut machine = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    machine.set_word(word);
+    let status = machine.run();
+    let mut text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    match status {
+        Status::Halted => text.push_str("Program Halted\n"),
+        Status::Trapped => return (text, 1),
+        _ => ()
+    }
+    (text, 0)
+}
+
+// builds a named program for one target and runs it on some input, None
+// when the tools it needs aren't installed
+pub type Native = fn(&str, &str, &str, Word) -> Option<(String, i32)>;
+
+// a compiled program prints and exits the same as the interpreter
+pub fn same(native: Native, name: &str, source: &str, input: &str, word: Word) {
+    if let Some(native) = native(name, source, input, word) {
+        assert_eq!(native, interpret(source, input, word), "{}", name);
+    }
+}
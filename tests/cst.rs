@@ -0,0 +1,84 @@
+use moonwalk::ast::Token;
+use moonwalk::cst::{Cst, LineKind, TriviaKind};
+use moonwalk::{lex, parse};
+
+fn round_trip(source: &str) {
+    let cst = Cst::parse(source).expect("source should lex");
+    assert_eq!(cst.to_source(), source);
+}
+
+#[test]
+fn round_trips_exactly() {
+    for source in [
+        include_str!("../hello.mw"),
+        include_str!("../testJmp.mw"),
+        include_str!("../testc.mw"),
+        include_str!("../test1.mw"),
+        include_str!("../testp.mw"),
+        include_str!("../test.mw"),
+        "",
+        "   ",
+        "\n\n",
+        "; only a comment",
+        "inc A $1   ",
+        "\tinc\tA  $1 ;tabs\t\r\n\r\nhalt\r\n  \n"
+    ] {
+        round_trip(source);
+    }
+}
+
+#[test]
+fn trivia_is_attached_to_the_next_token() {
+    let cst = Cst::parse("  inc A $1 ; bump\n; note\nhalt").unwrap();
+    assert_eq!(cst.lines.len(), 3);
+    let first: Vec<&str> = cst.lines[0].tokens.iter().map(|t| t.text.as_str()).collect();
+    assert_eq!(first, vec!["inc", "A", "$", "1", "\n"]);
+
+    let inc = &cst.lines[0].tokens[0];
+    assert_eq!(inc.leading.len(), 1);
+    assert_eq!((inc.leading[0].kind, inc.leading[0].text.as_str()), (TriviaKind::Whitespace, "  "));
+    assert_eq!((inc.span.line, inc.span.column), (1, 3));
+
+    let comment = cst.lines[0].comment().unwrap();
+    assert_eq!((comment.text.as_str(), comment.span.line, comment.span.column), ("; bump", 1, 12));
+    // a line of only a comment is its newline with the comment in front
+    assert_eq!(cst.lines[1].code().count(), 0);
+    assert_eq!(cst.lines[1].comment().unwrap().text, "; note");
+    assert_eq!(cst.lines[2].tokens[0].token, Token::Halt);
+    assert!(cst.trailing.is_empty());
+}
+
+#[test]
+fn trailing_trivia() {
+    let cst = Cst::parse("halt\n  ; bye").unwrap();
+    let trailing: Vec<(TriviaKind, &str)> = cst.trailing.iter().map(|t| (t.kind, t.text.as_str())).collect();
+    assert_eq!(trailing, vec![(TriviaKind::Whitespace, "  "), (TriviaKind::Comment, "; bye")]);
+    assert_eq!((cst.trailing[1].span.line, cst.trailing[1].span.column), (2, 3));
+}
+
+#[test]
+fn ast_matches_the_parser() {
+    for source in [include_str!("../testJmp.mw"), include_str!("../testp.mw"), "x: inc A $1 if A < 3 ; c\n\tjump x\n"] {
+        let direct = parse::parse(lex::lex(source).unwrap()).unwrap();
+        let derived = Cst::parse(source).unwrap().to_ast().unwrap();
+        let strip = |lines: Vec<moonwalk::ast::Line>| lines.into_iter().map(|l| (l.label, l.inst, l.cond, l.span.start, l.span.line)).collect::<Vec<_>>();
+        assert_eq!(strip(derived), strip(direct));
+    }
+}
+
+#[test]
+fn macros_are_set_apart() {
+    let cst = Cst::parse("twice A\nmacro twice x\ninc x $1\n\ninc x $1\nendmacro\nx: twice B ; again\ninc A $1\nendmacro\n").unwrap();
+    use LineKind::*;
+    assert_eq!(cst.kinds(), vec![Call, Macro, Body, Body, EndMacro, Call, Code, Code]);
+    // only code lines have an ast, so a stray endmacro is still a parse error
+    let lines = Cst::parse("macro m x\ninc x x\nendmacro\nm A\nhalt\n").unwrap().to_ast().unwrap();
+    assert_eq!(lines.iter().map(|line| line.span.line).collect::<Vec<_>>(), vec![5]);
+    assert!(Cst::parse("endmacro\n").unwrap().to_ast().is_err());
+}
+
+#[test]
+fn lex_errors_come_through() {
+    let err = Cst::parse("inc A @\n").unwrap_err();
+    assert_eq!((err.line(), err.column()), (Some(1), Some(7)));
+}
@@ -0,0 +1,74 @@
+use moonwalk::debug::{parse_command, Command, Debugger, Location, Stop, MEM_LIMIT};
+use moonwalk::{Machine, Program, Status};
+
+const SOURCE: &str = "\
+inc A $1
+loop: inc B $1
+reverse if B = $3
+backwards
+";
+
+fn debugger() -> Debugger {
+    let program = Program::from_source(SOURCE).unwrap();
+    let machine = Machine::with_io(program, Box::new(std::io::empty()), Box::new(std::io::sink()));
+    Debugger::new(machine, SOURCE)
+}
+
+#[test]
+fn parses_commands() {
+    assert_eq!(parse_command("b loop"), Ok(Command::Break(Location::Label("loop".to_string()))));
+    assert_eq!(parse_command("break 3"), Ok(Command::Break(Location::Line(3))));
+    assert_eq!(parse_command("step 4"), Ok(Command::Step(4)));
+    assert_eq!(parse_command("mem 0x10 0x12"), Ok(Command::Mem(16, 18)));
+    assert_eq!(parse_command("mem 7"), Ok(Command::Mem(7, 8)));
+    assert!(parse_command(&format!("mem {}", usize::MAX)).is_err());
+    assert!(parse_command("mem 5 4").is_err());
+    assert!(parse_command("mem 5 5").is_err());
+    // sparse memory never runs out, so a huge range would print forever
+    assert_eq!(parse_command("mem 0 0x1000"), Ok(Command::Mem(0, MEM_LIMIT)));
+    assert!(parse_command("mem 0 0x1001").unwrap_err().contains("at most 4096"));
+    assert!(parse_command("mem 0 0xffffffffffff").is_err());
+    assert!(parse_command("frobnicate").is_err());
+}
+
+#[test]
+fn breakpoints_on_labels_and_lines() {
+    let mut dbg = debugger();
+    dbg.add_breakpoint(&Location::Label("loop".to_string())).unwrap();
+    assert_eq!(dbg.continue_(), Stop::Breakpoint(1));
+    assert_eq!(dbg.machine().context().a, 1);
+    dbg.add_breakpoint(&Location::Line(4)).unwrap();
+    assert_eq!(dbg.continue_(), Stop::Breakpoint(3));
+    assert!(dbg.add_breakpoint(&Location::Label("nope".to_string())).is_err());
+}
+
+#[test]
+fn turn_stops_when_direction_changes() {
+    let mut dbg = debugger();
+    assert_eq!(dbg.turn(), Stop::Turned);
+    assert!(!dbg.machine().forward());
+    assert_eq!(dbg.continue_(), Stop::Exited(Status::Finished));
+}
+
+#[test]
+fn repl_prints_registers() {
+    let mut dbg = debugger();
+    let mut out = Vec::new();
+    dbg.repl("step 2\nregs\nquit\n".as_bytes(), &mut out).unwrap();
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains("A = 1\nB = 1\n"), "{}", out);
+    assert!(out.contains("pc = 2"), "{}", out);
+}
+
+#[test]
+fn back_undoes_steps() {
+    let mut dbg = debugger();
+    dbg.step(3);
+    assert_eq!(dbg.machine().context().b, 1);
+    assert_eq!(dbg.back(2), Stop::Stepped);
+    assert_eq!(dbg.machine().pc(), 1);
+    assert_eq!(dbg.machine().context().b, 0);
+    assert_eq!(dbg.back(5), Stop::Start);
+    assert_eq!(dbg.goto(3), Stop::Stepped);
+    assert_eq!(dbg.machine().context().b, 1);
+}
@@ -0,0 +1,113 @@
+use moonwalk::ast::{Instruction, Register};
+use moonwalk::eval::{self, Place};
+use moonwalk::{Machine, Program, RuntimeError, Status};
+
+// memory is laid out as a chain 0x10 -> 0x20 -> 0x30 -> 7 with A = 0x10
+fn machine(source: &str) -> Machine {
+    let program = Program::from_source(source).unwrap();
+    let mut machine = Machine::with_io(program, Box::new(&b"x"[..]), Box::new(std::io::sink()));
+    let ctx = machine.context_mut();
+    ctx.a = 0x10;
+    ctx.mem.set(0x10, 0x20);
+    ctx.mem.set(0x20, 0x30);
+    ctx.mem.set(0x30, 7);
+    machine
+}
+
+fn run(source: &str) -> Machine {
+    let mut m = machine(source);
+    assert_eq!(m.run(), Status::Finished);
+    m
+}
+
+fn mem(m: &Machine, addr: usize) -> usize {
+    m.mem().get(addr).unwrap()
+}
+
+#[test]
+fn resolves_each_depth_as_a_source() {
+    let m = machine("halt\n");
+    let lines = |src: &str| Program::from_source(&format!("inc B {}\n", src)).unwrap();
+    let place = |src: &str| match &lines(src).lines()[0].inst {
+        Instruction::Inc(_, src) => eval::source_place(src, m.context()).unwrap(),
+        _ => unreachable!()
+    };
+    assert_eq!(place("A"), Place::Reg(Register::A));
+    assert_eq!(place("*A"), Place::Mem(0x10));
+    assert_eq!(place("**A"), Place::Mem(0x20));
+    assert_eq!(place("***A"), Place::Mem(0x30));
+    assert_eq!(place("0x10"), Place::Mem(0x10));
+    assert_eq!(place("*0x10"), Place::Mem(0x20));
+    assert_eq!(place("**0x10"), Place::Mem(0x30));
+    assert_eq!(place("$0x10"), Place::Literal(0x10));
+    assert_eq!(place("*$0x10"), Place::Mem(0x10));
+    assert_eq!(place("**$0x10"), Place::Mem(0x20));
+}
+
+#[test]
+fn reads_through_each_depth() {
+    assert_eq!(run("inc B A\n").context().b, 0x10);
+    assert_eq!(run("inc B *A\n").context().b, 0x20);
+    assert_eq!(run("inc B **A\n").context().b, 0x30);
+    assert_eq!(run("inc B ***A\n").context().b, 7);
+    assert_eq!(run("inc B 0x30\n").context().b, 7);
+    assert_eq!(run("inc B *0x20\n").context().b, 7);
+    assert_eq!(run("inc B *$0x30\n").context().b, 7);
+}
+
+#[test]
+fn writes_through_each_depth() {
+    let m = run("inc A $1\n");
+    assert_eq!(m.context().a, 0x11);
+
+    let m = run("inc *A $1\n");
+    assert_eq!((m.context().a, mem(&m, 0x10)), (0x10, 0x21));
+
+    let m = run("inc **A $1\n");
+    assert_eq!((mem(&m, 0x10), mem(&m, 0x20)), (0x20, 0x31));
+
+    let m = run("inc ***A $1\n");
+    assert_eq!((mem(&m, 0x20), mem(&m, 0x30)), (0x30, 8));
+
+    let m = run("inc *0x20 $1\n");
+    assert_eq!((mem(&m, 0x20), mem(&m, 0x30)), (0x30, 8));
+}
+
+#[test]
+fn deref_as_both_operands() {
+    // 0x30 += 7, reading and writing the same cell through different paths
+    let m = run("inc ***A *0x20\n");
+    assert_eq!(mem(&m, 0x30), 14);
+}
+
+#[test]
+fn backwards_undoes_a_deref_inc() {
+    let mut m = machine("inc **A $5\n");
+    assert_eq!(m.run(), Status::Finished);
+    assert_eq!(mem(&m, 0x20), 0x35);
+    // the program ends before running line 0 backwards, so run it by hand
+    m.context_mut().forward = false;
+    let line = m.lines()[0].inst.clone();
+    eval::execute_instruction(&line, m.context_mut()).unwrap();
+    assert_eq!(mem(&m, 0x20), 0x30);
+}
+
+#[test]
+fn io_reads_into_a_deref() {
+    let m = run("io **A\n");
+    assert_eq!(mem(&m, 0x20), 'x' as usize);
+}
+
+#[test]
+fn deref_out_of_bounds() {
+    let mut m = machine("inc B *B\n");
+    m.context_mut().b = 0xdeadbeefabcdef;
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error(), Some(&RuntimeError::OutOfBounds{pc: 0, addr: 0xdeadbeefabcdef}));
+}
+
+#[test]
+fn literal_is_not_a_destination() {
+    assert!(Program::from_source("inc *$5 $1\n").is_err());
+    assert!(Program::from_source("inc $5 $1\n").is_err());
+}
@@ -0,0 +1,60 @@
+use moonwalk::{lex, Program, Severity};
+
+#[test]
+fn bad_token_has_location() {
+    let err = lex::lex("inc A $3\ninc A @\n").unwrap_err();
+    assert_eq!(err.severity, Severity::Error);
+    assert_eq!(err.line(), Some(2));
+    assert_eq!(err.column(), Some(7));
+}
+
+#[test]
+fn tokens_carry_spans() {
+    let tokens = lex::lex("start: inc B $0x10").unwrap();
+    let inc = tokens.iter().find(|t| t.token == moonwalk::ast::Token::Inc).unwrap();
+    assert_eq!((inc.span.start, inc.span.end), (7, 10));
+    assert_eq!((inc.span.line, inc.span.column), (1, 8));
+}
+
+#[test]
+fn keywords_are_whole_words() {
+    // `order` used to lex as `or` followed by `der`
+    assert!(Program::from_source("order: inc A $1\njump order\n").is_ok());
+}
+
+#[test]
+fn parse_error_points_at_token() {
+    let mut err = Program::from_source("inc A $1\ninc $3 A\n").unwrap_err();
+    err.set_file("bad.mw");
+    let rendered = err.render("inc A $1\ninc $3 A\n");
+    assert_eq!(
+        rendered,
+        "error: Destination Can't Be Literal\n --> bad.mw:2:5\n  |\n2 | inc $3 A\n  |     ^^\n"
+    );
+}
+
+#[test]
+fn duplicate_label_points_at_second_definition() {
+    let err = Program::from_source("x: halt\nx: halt\n").unwrap_err();
+    assert_eq!(err.iter().next().unwrap().line(), Some(2));
+}
+
+#[test]
+fn missing_labels_are_errors_at_each_use() {
+    let err = Program::from_source("jump typo\nhalt\nfrom typo\njump other\n").unwrap_err();
+    let found: Vec<_> = err.iter().map(|d| (d.severity, d.line(), d.message.as_str())).collect();
+    assert_eq!(found, vec![
+        (Severity::Error, Some(1), "missing label typo"),
+        (Severity::Error, Some(3), "missing label typo"),
+        (Severity::Error, Some(4), "missing label other")
+    ]);
+}
+
+#[test]
+fn unused_labels_are_warnings() {
+    let program = Program::from_source("top: inc A $1\nmid: jump top\nend: halt\n").unwrap();
+    let found: Vec<_> = program.warnings().iter().map(|d| (d.severity, d.line(), d.message.as_str())).collect();
+    assert_eq!(found, vec![(Severity::Warning, Some(2), "unused label mid"), (Severity::Warning, Some(3), "unused label end")]);
+    // a from counts as a use too
+    assert!(Program::from_source("x: halt\nfrom x\n").unwrap().warnings().is_empty());
+}
@@ -0,0 +1,53 @@
+use moonwalk::ast::{Expr, Register, Source};
+use moonwalk::{lex, parse, Diagnostic, Machine, Program};
+
+fn expr(src: &str) -> Result<Expr, Diagnostic> {
+    let mut q = parse::Tokens::new(lex::lex(src).expect("should lex"));
+    parse::parse_expr(&mut q)
+}
+
+#[test]
+fn comparisons_take_values() {
+    assert_eq!(
+        expr("A = $2\n"),
+        Ok(Expr::Eq(Source::Reg(Register::A), Source::Literal(2)))
+    );
+}
+
+#[test]
+fn precedence_and_parens() {
+    let parsed = expr("(*B >= 5 or A = 3) and forwards").unwrap();
+    match parsed {
+        Expr::And(left, right) => {
+            assert!(matches!(*left, Expr::Or(_, _)));
+            assert_eq!(*right, Expr::Forwards);
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn mixed_sorts_are_rejected() {
+    assert!(expr("forwards > A\n").is_err());
+    assert!(expr("A and forwards").is_err());
+    assert!(expr("(A = B ) = C\n").is_err());
+    assert!(expr("A\n").is_err());
+}
+
+#[test]
+fn comparisons_use_values_not_truthiness() {
+    let source = "inc A $2\ninc B $3\ninc C $1 if A = B \ninc D $1 if A < B \nhalt\n";
+    let mut machine = Machine::new(Program::from_source(source).unwrap());
+    machine.run();
+    assert_eq!(machine.register(&Register::C), 0);
+    assert_eq!(machine.register(&Register::D), 1);
+}
+
+#[test]
+fn display_parses_back_the_same() {
+    for src in ["(*B >= 5 or A = 3) and forwards", "A = $1 or B < 2 and backwards", "forwards and (A = $1 and **B <= C)", "A = $1 or (B = $2 or C > $0)"] {
+        let parsed = expr(src).unwrap();
+        assert_eq!(parsed.to_string(), src);
+        assert_eq!(expr(&parsed.to_string()), Ok(parsed));
+    }
+}
@@ -0,0 +1,98 @@
+use moonwalk::format::format;
+use moonwalk::Program;
+
+fn fmt(source: &str) -> String {
+    format(source).expect("source should format")
+}
+
+// the program a source builds, with spans left out
+fn meaning(source: &str) -> Vec<String> {
+    Program::from_source(source).unwrap().lines().iter().map(|line| line.to_string()).collect()
+}
+
+const EXAMPLES: [&str; 6] = [
+    include_str!("../hello.mw"),
+    include_str!("../testJmp.mw"),
+    include_str!("../testIO.mw"),
+    include_str!("../testc.mw"),
+    include_str!("../test1.mw"),
+    include_str!("../testp.mw")
+];
+
+#[test]
+fn labels_line_up() {
+    let expected = [
+        "        jump start",
+        "",
+        "        forwards",
+        "printA: jump",
+        "        io A if backwards",
+        "        backwards",
+        "",
+        "start:  inc A $65 if forwards",
+        "        jump printA\n"
+    ];
+    assert_eq!(fmt(include_str!("../testJmp.mw")), expected.join("\n"));
+}
+
+#[test]
+fn registers_and_numbers() {
+    assert_eq!(fmt("inc a $0xFF\n"), "inc A $0xff\n");
+    assert_eq!(fmt("inc   d   0x00AB\ninc b $007\n"), "inc D 0xab\ninc B $7\n");
+    assert_eq!(fmt("io  *  *c\n"), "io **C\n");
+}
+
+#[test]
+fn conditions() {
+    assert_eq!(fmt("halt if((A=$1)and(B=$2))or C>=$3\n"), "halt if A = $1 and B = $2 or C >= $3\n");
+    assert_eq!(fmt("halt if A = $1 and (B = $2 or backwards)\n"), "halt if A = $1 and (B = $2 or backwards)\n");
+    assert_eq!(fmt("halt if forwards or (backwards or A < 3)\n"), "halt if forwards or (backwards or A < 3)\n");
+}
+
+#[test]
+fn comments_and_blank_lines() {
+    let source = "\n\n;header\n\n\n  ;;   indented   \ninc A $1   ;bump\n\n\nhalt\n\n";
+    assert_eq!(fmt(source), "; header\n\n;; indented\ninc A $1 ; bump\n\nhalt\n");
+    let labelled = "x: halt\n  ; note\n";
+    assert_eq!(fmt(labelled), "x: halt\n   ; note\n");
+}
+
+#[test]
+fn formatting_is_stable() {
+    for source in EXAMPLES {
+        let once = fmt(source);
+        assert_eq!(fmt(&once), once);
+        assert_eq!(meaning(&once), meaning(source));
+    }
+}
+
+#[test]
+fn errors_are_reported() {
+    let err = format("inc $3 A\n").unwrap_err();
+    assert_eq!(err.iter().next().unwrap().line(), Some(1));
+}
+
+#[test]
+fn macros() {
+    let source = "macro   clear r ;zero it\ntop: jump out if r=$0\nneg  r\ninc r $0x01\nneg r\njump top\nout:   from\n  endmacro\n\n\ninc a $3\nagain:  clear  a\njump again if *  b = $0xA\n";
+    let expected = [
+        "       macro clear r ; zero it",
+        "top:   jump out if r = $0",
+        "       neg r",
+        "       inc r $0x1",
+        "       neg r",
+        "       jump top",
+        "out:   from",
+        "       endmacro",
+        "",
+        "       inc A $3",
+        "again: clear A",
+        "       jump again if *B = $0xa\n"
+    ];
+    let once = fmt(source);
+    assert_eq!(once, expected.join("\n"));
+    assert_eq!(fmt(&once), once);
+    assert_eq!(meaning(&once), meaning(source));
+    // a definition that never ends can't be told apart from code
+    assert!(format("macro m\ninc A $1\n").is_err());
+}
@@ -0,0 +1,70 @@
+use std::io;
+
+use moonwalk::history::Effect;
+use moonwalk::{Machine, Program, Status};
+
+mod common;
+use common::SharedBuf;
+
+type Snapshot = (usize, usize, usize, usize, bool, usize, usize, Vec<Vec<usize>>);
+
+fn snapshot(m: &Machine) -> Snapshot {
+    let ctx = m.context();
+    let stacks = m.lines().iter().map(|l| l.stack.clone()).collect();
+    (ctx.a, ctx.b, ctx.c, ctx.d, ctx.forward, ctx.pc, ctx.mem.get(7).unwrap(), stacks)
+}
+
+fn machine(source: &str, input: &str) -> (Machine, SharedBuf) {
+    let program = Program::from_source(source).unwrap();
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut m = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    m.record_history();
+    (m, out)
+}
+
+const SOURCE: &str = include_str!("../testJmp.mw");
+
+#[test]
+fn undo_restores_every_earlier_state() {
+    let (mut m, _) = machine(SOURCE, "");
+    let mut states = vec![snapshot(&m)];
+    while m.step() == Status::Running {
+        states.push(snapshot(&m));
+    }
+    // the final step that ran off the end isn't recorded
+    assert_eq!(m.history().unwrap().len(), states.len() - 1);
+    while let Some(expected) = states.pop() {
+        assert_eq!(snapshot(&m), expected);
+        if !m.step_back() {
+            break;
+        }
+    }
+    assert!(states.is_empty());
+    assert_eq!(m.history().unwrap().position(), 0);
+}
+
+#[test]
+fn replay_is_deterministic_and_quiet() {
+    let (mut m, out) = machine("io 7\nio C\nbackwards\nio 7\n", "xy");
+    m.run();
+    let first = snapshot(&m);
+    let printed = out.0.borrow().clone();
+    assert_eq!(m.context().c, 'y' as usize);
+
+    m.rewind_to(0);
+    assert_eq!(m.context().mem.get(7).unwrap(), 0);
+    assert_eq!(m.run(), Status::Finished);
+    assert_eq!(snapshot(&m), first);
+    // replaying didn't read more input or print again
+    assert_eq!(*out.0.borrow(), printed);
+}
+
+#[test]
+fn log_records_consumed_input() {
+    let (mut m, _) = machine("io A\n", "q");
+    m.step();
+    let effects = &m.history().unwrap().deltas()[0].effects;
+    assert!(effects.contains(&Effect::Input(b'q')));
+    assert!(effects.contains(&Effect::Reg(moonwalk::ast::Register::A, 0, 'q' as usize)));
+}
@@ -0,0 +1,163 @@
+use std::io::Cursor;
+
+use moonwalk::lsp::{self, Server};
+use serde_json::{json, Value};
+
+const URI: &str = "file:///prog.mw";
+const SOURCE: &str = "jump start\nforwards\nprintA: jump\nio A if backwards\nbackwards\nstart: inc A $65\njump printA\njump printA\n";
+
+fn open(server: &mut Server, text: &str) -> Vec<Value> {
+    server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didOpen",
+        "params": {"textDocument": {"uri": URI, "languageId": "moonwalk", "version": 1, "text": text}}
+    }))
+}
+
+fn request(server: &mut Server, method: &str, line: u64, character: u64) -> Value {
+    let replies = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "id": 7,
+        "method": method,
+        "params": {
+            "textDocument": {"uri": URI},
+            "position": {"line": line, "character": character},
+            "context": {"includeDeclaration": true}
+        }
+    }));
+    assert_eq!(replies.len(), 1);
+    assert_eq!(replies[0]["id"], 7);
+    replies[0]["result"].clone()
+}
+
+fn range(start: (u64, u64), end: (u64, u64)) -> Value {
+    json!({"start": {"line": start.0, "character": start.1}, "end": {"line": end.0, "character": end.1}})
+}
+
+#[test]
+fn diagnostics_on_open_and_change() {
+    let mut server = Server::new();
+    let published = open(&mut server, "jump nowhere\nlonely: halt\n");
+    assert_eq!(published[0]["method"], "textDocument/publishDiagnostics");
+    let diagnostics = &published[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["message"], "missing label nowhere");
+    assert_eq!(diagnostics[0]["severity"], 1);
+    assert_eq!(diagnostics[0]["range"], range((0, 0), (0, 12)));
+
+    let changed = server.handle(&json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/didChange",
+        "params": {"textDocument": {"uri": URI, "version": 2}, "contentChanges": [{"text": "lonely: halt\n"}]}
+    }));
+    let diagnostics = &changed[0]["params"]["diagnostics"];
+    assert_eq!(diagnostics[0]["message"], "unused label lonely");
+    assert_eq!(diagnostics[0]["severity"], 2);
+}
+
+#[test]
+fn go_to_definition() {
+    let mut server = Server::new();
+    open(&mut server, SOURCE);
+    // on `printA` in the last jump
+    let found = request(&mut server, "textDocument/definition", 6, 8);
+    assert_eq!(found, json!({"uri": URI, "range": range((2, 0), (2, 6))}));
+    // nothing on an instruction
+    assert_eq!(request(&mut server, "textDocument/definition", 1, 2), Value::Null);
+}
+
+#[test]
+fn find_references() {
+    let mut server = Server::new();
+    open(&mut server, SOURCE);
+    let found = request(&mut server, "textDocument/references", 2, 1);
+    let ranges: Vec<&Value> = found.as_array().unwrap().iter().map(|r| &r["range"]).collect();
+    assert_eq!(ranges, vec![&range((2, 0), (2, 6)), &range((6, 5), (6, 11)), &range((7, 5), (7, 11))]);
+}
+
+#[test]
+fn hover_explains_both_directions() {
+    let mut server = Server::new();
+    open(&mut server, SOURCE);
+    let hover = request(&mut server, "textDocument/hover", 3, 1);
+    let text = hover["contents"]["value"].as_str().unwrap();
+    assert!(text.contains("io A if backwards"));
+    assert!(text.contains("forwards: reads a character of input"));
+    assert!(text.contains("backwards: writes the source as a character"));
+    assert!(text.contains("only when `backwards` holds"));
+    assert_eq!(hover["range"], range((3, 0), (3, 17)));
+}
+
+#[test]
+fn completes_labels() {
+    let mut server = Server::new();
+    // still completes while the document doesn't parse
+    open(&mut server, "top: inc A $1\nend: halt\njump \n");
+    let items = request(&mut server, "textDocument/completion", 2, 5);
+    let labels: Vec<&str> = items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect();
+    assert_eq!(labels, vec!["top", "end"]);
+}
+
+#[test]
+fn works_around_macros() {
+    let mut server = Server::new();
+    open(&mut server, "macro two r\nfirst: inc r $1\ninc r $1\nendmacro\nagain: two A\nio A\njump again if A < $4\n");
+    let hover = request(&mut server, "textDocument/hover", 5, 1);
+    assert!(hover["contents"]["value"].as_str().unwrap().contains("io A"));
+    // a label on a call names the line it expands to
+    assert_eq!(request(&mut server, "textDocument/definition", 6, 6), json!({"uri": URI, "range": range((4, 0), (4, 5))}));
+    let items = request(&mut server, "textDocument/completion", 6, 0);
+    let labels: Vec<&str> = items.as_array().unwrap().iter().map(|i| i["label"].as_str().unwrap()).collect();
+    assert_eq!(labels, vec!["first", "again"]);
+}
+
+#[test]
+fn unknown_requests_are_errors() {
+    let mut server = Server::new();
+    let replies = server.handle(&json!({"jsonrpc": "2.0", "id": 1, "method": "textDocument/rename", "params": {}}));
+    assert_eq!(replies[0]["error"]["code"], -32601);
+}
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+}
+
+#[test]
+fn speaks_over_a_stream() {
+    let input = [
+        frame(json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {}})),
+        frame(json!({"jsonrpc": "2.0", "method": "initialized", "params": {}})),
+        frame(json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"})),
+        frame(json!({"jsonrpc": "2.0", "method": "exit"}))
+    ].concat();
+    let mut output = Vec::new();
+    assert!(lsp::serve(Cursor::new(input), &mut output).unwrap());
+    let output = String::from_utf8(output).unwrap();
+    let bodies: Vec<Value> = output.split("Content-Length: ").skip(1)
+        .map(|chunk| serde_json::from_str(chunk.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect();
+    assert_eq!(bodies.len(), 2);
+    assert_eq!(bodies[0]["result"]["capabilities"]["definitionProvider"], true);
+    assert_eq!(bodies[1], json!({"jsonrpc": "2.0", "id": 2, "result": null}));
+}
+
+#[test]
+fn keeps_serving_after_bad_messages() {
+    let input = [
+        "Content-Length: 9\r\n\r\n{not json".to_string(),
+        // too long to read, so it's skipped without being allocated
+        format!("Content-Length: {}\r\n\r\n{}", 1 << 25, " ".repeat(1 << 25)),
+        frame(json!({"jsonrpc": "2.0", "id": 2, "method": "shutdown"})),
+        frame(json!({"jsonrpc": "2.0", "method": "exit"}))
+    ].concat();
+    let mut output = Vec::new();
+    assert!(lsp::serve(Cursor::new(input), &mut output).unwrap());
+    let output = String::from_utf8(output).unwrap();
+    let bodies: Vec<Value> = output.split("Content-Length: ").skip(1)
+        .map(|chunk| serde_json::from_str(chunk.split_once("\r\n\r\n").unwrap().1).unwrap())
+        .collect();
+    assert_eq!(bodies.len(), 3);
+    assert_eq!((&bodies[0]["id"], &bodies[0]["error"]["code"]), (&Value::Null, &json!(-32700)));
+    assert_eq!(bodies[1]["error"]["code"], -32600);
+    assert_eq!(bodies[2], json!({"jsonrpc": "2.0", "id": 2, "result": null}));
+}
@@ -0,0 +1,74 @@
+use std::io::{self, Write};
+
+use moonwalk::ast::Register;
+use moonwalk::{Machine, Program, Status};
+
+mod common;
+use common::SharedBuf;
+
+fn run(source: &str, input: &str) -> (Machine, String) {
+    let program = Program::from_source(source).expect("program should build");
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut machine = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    machine.run();
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (machine, text)
+}
+
+#[test]
+fn hello_prints_backwards() {
+    let (machine, out) = run(include_str!("../hello.mw"), "");
+    assert_eq!(out, "hi ");
+    assert_eq!(machine.status(), Status::Finished);
+}
+
+#[test]
+fn jump_and_from_return() {
+    let (_, out) = run(include_str!("../testJmp.mw"), "");
+    assert_eq!(out, "A");
+}
+
+// output that's gone, like a pipe whose reader has exited
+struct Closed;
+
+impl Write for Closed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn flush_errors_are_returned() {
+    let program = Program::from_source("inc A $1\n").unwrap();
+    let mut machine = Machine::with_io(program, Box::new(io::empty()), Box::new(Closed));
+    // stepping leaves flushing to whoever is stepping
+    while machine.step() == Status::Running {}
+    assert_eq!(machine.status(), Status::Finished);
+    assert_eq!(machine.flush().unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+}
+
+#[test]
+fn step_and_inspect() {
+    let program = Program::from_source("inc A $3\ninc B $4\nhalt\n").unwrap();
+    let mut machine = Machine::new(program);
+    assert_eq!(machine.step(), Status::Running);
+    assert_eq!(machine.register(&Register::A), 3);
+    assert_eq!(machine.pc(), 1);
+    assert_eq!(machine.run(), Status::Halted);
+    assert_eq!(machine.register(&Register::B), 4);
+    assert!(machine.forward());
+    // stepping a stopped machine is a no-op
+    assert_eq!(machine.step(), Status::Halted);
+}
+
+#[test]
+fn bad_source_reports_diagnostics() {
+    let err = Program::from_source("inc A $3\nfrom x y\n").unwrap_err();
+    assert!(err.has_errors());
+    assert_eq!(err.iter().next().unwrap().line(), Some(2));
+}
@@ -0,0 +1,116 @@
+use moonwalk::ast::Register;
+use moonwalk::diagnostic::Diagnostic;
+use moonwalk::{macros, reversible, Machine, Program, Status};
+
+// counts a register down to zero, with labels of its own
+const CLEAR: &str = "macro clear r\ntop: jump out if r = $0\nneg r\ninc r $1\nneg r\njump top\nout: from\nendmacro\n";
+
+fn run(source: &str) -> Machine {
+    let program = Program::from_source(source).expect("program should build");
+    let mut machine = Machine::new(program);
+    machine.run();
+    machine
+}
+
+fn error(source: &str) -> Diagnostic {
+    Program::from_source(source).unwrap_err().iter().next().unwrap().clone()
+}
+
+#[test]
+fn calls_are_replaced_by_the_body() {
+    let m = run("macro add2 x y\ninc x y\ninc x y\nendmacro\ninc B $3\nadd2 A B\nadd2 5 $1\n");
+    assert_eq!(m.register(&Register::A), 6);
+    assert_eq!(m.mem().get(5), Some(2));
+    // macros can be called before they're defined, and comments stay put
+    let expanded = macros::expand("add2 A $1 ; twice\nmacro add2 x y\ninc x y\ninc x y\nendmacro\n").unwrap();
+    assert_eq!(expanded.text(), "inc A $1\ninc A $1 ; twice\n");
+    // a source without macros comes out the same
+    let source = "top: inc A $1 ; one\n\njump top\n";
+    assert_eq!(macros::expand(source).unwrap().text(), source);
+}
+
+#[test]
+fn labels_are_renamed_for_each_call() {
+    let m = run(&format!("{}inc A $3\ninc B $2\nclear A\nlast: clear B\n", CLEAR));
+    assert_eq!(m.status(), Status::Finished);
+    assert_eq!((m.register(&Register::A), m.register(&Register::B)), (0, 0));
+    let program = Program::from_source(&format!("{}clear A\nclear B\n", CLEAR)).unwrap();
+    assert_eq!(program.labels().len(), 4);
+    assert!(!program.labels().contains_key("top") && !program.labels().contains_key("out"));
+    // so a label outside can't be reached from, or clash with, the inside
+    assert!(Program::from_source(&format!("{}top: clear A\njump top\n", CLEAR)).is_ok());
+    // a label on the call names the same line as the macro's first label
+    let program = Program::from_source(&format!("{}again: clear A\njump again\n", CLEAR)).unwrap();
+    assert_eq!(program.labels()["again"], 0);
+    assert_eq!(program.lines()[4].to_string(), "jump again");
+}
+
+#[test]
+fn macros_call_macros() {
+    let m = run("macro four x\ntwo x\ntwo x\nendmacro\nmacro two x\ninc x $1\ninc x $1\nendmacro\nfour C\n");
+    assert_eq!(m.register(&Register::C), 4);
+    let err = error("macro f x\nf x\nendmacro\nf A\n");
+    assert!(err.message.contains("does it call itself"));
+    // one note at the call that started it rather than one per level
+    assert_eq!(err.notes.len(), 1);
+    assert_eq!(err.notes[0].span.line, 4);
+}
+
+#[test]
+fn errors_point_at_definition_and_call() {
+    let source = "macro twice x\nbad x\nbad x\nendmacro\nmacro bad x\nmul x $2\nendmacro\ninc A $1\ntop: twice A\n";
+    let err = error(source);
+    assert!(err.message.contains("odd number"));
+    assert_eq!((err.line(), err.column()), (Some(6), Some(7)));
+    let calls: Vec<_> = err.notes.iter().map(|note| (note.span.line, note.span.column, note.message.clone())).collect();
+    assert_eq!(calls, vec![
+        (2, 1, "in this expansion of bad".to_string()),
+        (9, 6, "in this expansion of twice".to_string())
+    ]);
+    let rendered = err.render(source);
+    assert!(rendered.contains(" --> <source>:6:7\n"));
+    assert!(rendered.contains("note: in this expansion of twice\n --> <source>:9:6\n"));
+    // an argument that's wrong is wrong at the call
+    let err = error("macro m x\ninc x $1\nendmacro\nm $4\n");
+    assert_eq!(err.line(), Some(4));
+    assert!(err.notes.is_empty());
+}
+
+#[test]
+fn warnings_and_lines_point_back_too() {
+    let source = "macro w x\ninc x x\nendmacro\ninc A $1\nw A\nw B\n";
+    let program = Program::from_source(source).unwrap();
+    let warnings: Vec<_> = reversible::check(program.lines()).iter().map(|d| d.line()).collect();
+    assert_eq!(warnings, vec![Some(2), Some(2)]);
+    let notes: Vec<_> = program.warnings().iter().map(|d| d.notes[0].span.line).collect();
+    assert_eq!(notes, vec![5, 6]);
+    // as do the places a warning points to
+    let program = Program::from_source("macro m r\nl: inc r $1\njump l if r < $3\nendmacro\nm A\nio A\n").unwrap();
+    let warning = program.warnings().iter().next().unwrap();
+    assert_eq!((warning.line(), warning.notes[0].span.line), (Some(6), 3));
+    // a runtime error in an expanded line is shown at the definition
+    let program = Program::from_source("macro far x\ninc *x $1\nendmacro\ninc A $0xffffff\nfar A\n").unwrap();
+    let mut m = Machine::new(program);
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error_diagnostic().unwrap().line(), Some(2));
+}
+
+#[test]
+fn malformed_definitions() {
+    assert!(error("macro\nendmacro\n").message.contains("needs a name"));
+    assert!(error("macro m $1\nendmacro\n").message.contains("parameters are names"));
+    assert!(error("macro m x x\nendmacro\n").message.contains("listed twice"));
+    assert!(error("macro m\ninc A $1\n").message.contains("never closed"));
+    assert!(error("inc A $1\nendmacro\n").message.contains("without a macro"));
+    assert!(error("macro m\nmacro n\nendmacro\nendmacro\n").message.contains("inside another macro"));
+    let err = error("macro m\nhalt\nendmacro\nmacro m\nhalt\nendmacro\n");
+    assert_eq!((err.line(), err.notes[0].span.line), (Some(4), 1));
+    // so a label on a call always has a line to go on
+    let err = error("macro e\nendmacro\nstart: e\njump start\n");
+    assert!(err.message.contains("macro e has no lines"));
+    assert_eq!((err.line(), err.column()), (Some(1), Some(7)));
+    let err = error("macro m x\ninc x $1\nendmacro\nm A B\n");
+    assert!(err.message.contains("takes 1 argument, found 2"));
+    assert_eq!(err.notes[0].message, "m is defined here");
+    assert!(error("macro m x\ninc x $1\nendmacro\nm *\n").message.contains("missing what it refers to"));
+}
@@ -0,0 +1,62 @@
+use moonwalk::memory::{self, Memory, MemoryModel};
+use moonwalk::{Machine, Program, RuntimeError, Status};
+
+fn machine(source: &str, model: MemoryModel) -> Machine {
+    let program = Program::from_source(source).unwrap();
+    let mut machine = Machine::with_io(program, Box::new(std::io::empty()), Box::new(std::io::sink()));
+    machine.set_memory(model);
+    machine
+}
+
+#[test]
+fn parses_models() {
+    assert_eq!("sparse".parse(), Ok(MemoryModel::Sparse));
+    assert_eq!("0x100".parse(), Ok(MemoryModel::Fixed(256)));
+    assert_eq!("1024".parse(), Ok(MemoryModel::Fixed(1024)));
+    assert!("lots".parse::<MemoryModel>().is_err());
+    // sizes that couldn't be allocated are turned away instead of aborting
+    assert_eq!("0x10000000".parse(), Ok(MemoryModel::Fixed(memory::MAX_SIZE)));
+    assert!("0x10000001".parse::<MemoryModel>().unwrap_err().contains("too big"));
+    assert!("0xffffffffffffffff".parse::<MemoryModel>().is_err());
+}
+
+#[test]
+fn sparse_memory_only_stores_nonzero_cells() {
+    let mut mem = Memory::new(MemoryModel::Sparse);
+    assert!(mem.set(0xdeadbeef, 4));
+    assert_eq!(mem.get(0xdeadbeef), Some(4));
+    assert!(mem.set(0xdeadbeef, 0));
+    assert_eq!(mem, Memory::new(MemoryModel::Sparse));
+}
+
+#[test]
+fn out_of_bounds_is_a_runtime_error() {
+    let mut m = machine("inc B 0xdeadbeefabcdef\n", MemoryModel::default());
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error(), Some(&RuntimeError::OutOfBounds{pc: 0, addr: 0xdeadbeefabcdef}));
+
+    let mut m = machine("inc 16 $1\n", MemoryModel::Fixed(16));
+    assert_eq!(m.run(), Status::Trapped);
+}
+
+#[test]
+fn sparse_memory_reaches_any_address() {
+    let mut m = machine("inc 0xdeadbeefabcdef $3\ninc A 0xdeadbeefabcdef\n", MemoryModel::Sparse);
+    assert_eq!(m.run(), Status::Finished);
+    assert_eq!(m.context().a, 3);
+}
+
+#[test]
+fn io_errors() {
+    let mut m = machine("io $3\n", MemoryModel::default());
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error(), Some(&RuntimeError::WriteToLiteral{pc: 0}));
+
+    // running backwards from the end, io writes A which isn't ascii
+    let mut m = machine("halt\nio A\n", MemoryModel::default());
+    m.context_mut().a = 200;
+    m.context_mut().pc = 1;
+    m.context_mut().forward = false;
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error(), Some(&RuntimeError::InvalidUtf8{pc: 1, value: 200}));
+}
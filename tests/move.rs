@@ -0,0 +1,93 @@
+use moonwalk::ast::{self, Register};
+use moonwalk::bytecode::Engine;
+use moonwalk::eval::Place;
+use moonwalk::{Machine, Program, Status};
+
+fn run(source: &str, engine: Engine) -> Machine {
+    let program = Program::from_source(source).expect("program should build");
+    let mut machine = Machine::new(program);
+    machine.set_engine(engine);
+    machine.run();
+    machine
+}
+
+fn values(machine: &Machine, place: Place) -> Vec<usize> {
+    machine.context().values.get(&place).cloned().unwrap_or_default()
+}
+
+#[test]
+fn parses_and_prints() {
+    let program = Program::from_source("move A $5\nunmove *B 0x3\n").unwrap();
+    let insts: Vec<_> = program.lines().iter().map(|l| l.inst.clone()).collect();
+    assert_eq!(insts, vec![
+        ast::Instruction::Move(ast::Dest::Reg(Register::A), ast::Source::Literal(5)),
+        ast::Instruction::Unmove(ast::Dest::Deref(Box::new(ast::Dest::Reg(Register::B))), ast::Source::Addr(3))
+    ]);
+    assert_eq!(program.lines()[1].to_string(), "unmove *B 3");
+    // a move can't write to a literal any more than inc can
+    assert!(Program::from_source("move $1 A\n").is_err());
+}
+
+#[test]
+fn forwards_saves_and_overwrites() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("inc A $2\nmove A $5\nmove A B\ninc B $3\nmove 3 B\nmove *B $8\n", engine);
+        assert_eq!(m.register(&Register::A), 0);
+        assert_eq!(values(&m, Place::Reg(Register::A)), vec![2, 5]);
+        assert_eq!(m.mem().get(3), Some(8));
+        assert_eq!(values(&m, Place::Mem(3)), vec![0, 3]);
+    }
+}
+
+#[test]
+fn backwards_restores() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("inc A $1\nmove A $5\nmove A $7\nbackwards if A = $7\n", engine);
+        // line 0 is where going backwards finishes, so the inc stays
+        assert_eq!(m.status(), Status::Finished);
+        assert_eq!(m.register(&Register::A), 1);
+        assert!(values(&m, Place::Reg(Register::A)).is_empty());
+    }
+}
+
+#[test]
+fn unmove_is_the_mirror_image() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        // going forwards unmove restores what move saved
+        let m = run("inc A $1\nmove A $4\nunmove A $0\n", engine);
+        assert_eq!(m.register(&Register::A), 1);
+        assert!(values(&m, Place::Reg(Register::A)).is_empty());
+        // and with nothing saved it does nothing, like a bare jump
+        let m = run("inc A $1\nunmove A $9\n", engine);
+        assert_eq!(m.register(&Register::A), 1);
+        // going backwards it saves and overwrites
+        let m = run("forwards\nhalt if A = $6\nunmove A $6\nbackwards\n", engine);
+        assert_eq!(m.status(), Status::Halted);
+        assert_eq!(values(&m, Place::Reg(Register::A)), vec![0]);
+    }
+}
+
+#[test]
+fn out_of_bounds_is_an_error() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("move 0xffffff $1\n", engine);
+        assert_eq!(m.status(), Status::Trapped);
+        assert!(m.context().values.is_empty());
+    }
+}
+
+#[test]
+fn step_back_undoes_the_value_stack() {
+    let program = Program::from_source("move A $5\nmove A $7\n").unwrap();
+    let mut m = Machine::new(program);
+    m.record_history();
+    m.run();
+    assert_eq!(values(&m, Place::Reg(Register::A)), vec![0, 5]);
+    assert!(m.step_back());
+    assert_eq!(m.register(&Register::A), 5);
+    assert_eq!(values(&m, Place::Reg(Register::A)), vec![0]);
+    m.rewind_to(0);
+    assert!(values(&m, Place::Reg(Register::A)).is_empty());
+    assert_eq!(m.run(), Status::Finished);
+    assert_eq!(values(&m, Place::Reg(Register::A)), vec![0, 5]);
+}
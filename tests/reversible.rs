@@ -0,0 +1,70 @@
+use moonwalk::reversible;
+use moonwalk::Program;
+
+// (line, message) for every warning the checker gives
+fn check(source: &str) -> Vec<(usize, String)> {
+    let program = Program::from_source(source).expect("program should build");
+    reversible::check(program.lines()).iter()
+        .map(|d| (d.line().unwrap(), d.message.clone()))
+        .collect()
+}
+
+fn lines(source: &str) -> Vec<usize> {
+    check(source).into_iter().map(|(line, _)| line).collect()
+}
+
+#[test]
+fn examples_are_reversible() {
+    assert!(check(include_str!("../hello.mw")).is_empty());
+    assert!(check(include_str!("../testJmp.mw")).is_empty());
+    assert!(check(include_str!("../testIO.mw")).is_empty());
+}
+
+#[test]
+fn source_aliases_destination() {
+    let warnings = check("inc A $1\ninc A A\ninc 5 *$5\ninc *B *B\n");
+    assert_eq!(warnings.iter().map(|w| w.0).collect::<Vec<_>>(), vec![2, 3, 4]);
+    assert!(warnings[0].1.contains("adds its destination to itself"));
+    // different places, or ones that could only meet at runtime
+    assert!(lines("inc A B\ninc *A A\ninc *A *B\n").is_empty());
+}
+
+#[test]
+fn source_found_through_destination() {
+    let warnings = check("inc A *A\ninc 3 **3\n");
+    assert_eq!(warnings.iter().map(|w| w.0).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(warnings[0].1.contains("finds its source through its destination"));
+}
+
+#[test]
+fn condition_changed_by_its_line() {
+    let warnings = check("inc A $1 if A < $5\ninc B $1 if A < $5\nio *C if *C = $0\n");
+    assert_eq!(warnings.iter().map(|w| w.0).collect::<Vec<_>>(), vec![1, 3]);
+    assert!(warnings[0].1.contains("condition reads a place this line changes"));
+    // the address the condition reads through changes too
+    assert_eq!(lines("inc A $1 if *A = $0\n"), vec![1]);
+}
+
+#[test]
+fn io_into_a_condition() {
+    let warnings = check("io B\ninc A $1\njump end if B = $0\nend: halt\n");
+    assert_eq!(warnings, vec![(1, "io reads into a place a condition depends on, going backwards doesn't restore it".to_string())]);
+    let program = Program::from_source("io B\ninc A $1\njump end if B = $0\nend: halt\n").unwrap();
+    let warnings = reversible::check(program.lines());
+    let note = &warnings.iter().next().unwrap().notes[0];
+    assert_eq!((note.span.line, note.message.as_str()), (3, "the condition is on this line"));
+    // a condition that only holds going forwards doesn't matter backwards
+    assert!(lines("io B\nhalt if B = $0 and forwards\n").is_empty());
+}
+
+#[test]
+fn one_way_lines_are_skipped() {
+    assert!(lines("inc A A if forwards\ninc A $1 if A < $5 and backwards\nio A if backwards or backwards\n").is_empty());
+    assert_eq!(lines("inc A A if forwards or A = $0\n"), vec![1, 1]);
+}
+
+#[test]
+fn warnings_reach_the_program() {
+    let program = Program::from_source("inc A A\n").unwrap();
+    assert_eq!(program.warnings().len(), 1);
+}
@@ -0,0 +1,72 @@
+use moonwalk::ast::{self, Register};
+use moonwalk::bytecode::Engine;
+use moonwalk::{reversible, Machine, Program, Status};
+
+fn run(source: &str, engine: Engine) -> Machine {
+    let program = Program::from_source(source).expect("program should build");
+    let mut machine = Machine::new(program);
+    machine.set_engine(engine);
+    machine.run();
+    machine
+}
+
+#[test]
+fn parses_and_prints() {
+    let program = Program::from_source("swap A *B\nxor 3 $0x5\n").unwrap();
+    assert_eq!(program.lines()[0].inst, ast::Instruction::Swap(
+        ast::Dest::Reg(Register::A),
+        ast::Dest::Deref(Box::new(ast::Dest::Reg(Register::B)))
+    ));
+    assert_eq!(program.lines()[1].to_string(), "xor 3 $5");
+    // swap only takes destinations
+    assert!(Program::from_source("swap A $1\n").is_err());
+}
+
+#[test]
+fn swaps_and_xors() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        let m = run("inc A $7\ninc B $3\ninc 3 $9\nswap A *B\nxor B $6\nxor C A\n", engine);
+        assert_eq!(m.register(&Register::A), 9);
+        assert_eq!(m.mem().get(3), Some(7));
+        assert_eq!(m.register(&Register::B), 5);
+        assert_eq!(m.register(&Register::C), 9);
+    }
+}
+
+#[test]
+fn same_in_both_directions() {
+    for engine in [Engine::Tree, Engine::Bytecode] {
+        // running the lines again backwards undoes them
+        let m = run("inc D $1\ninc A $7\ninc 3 $9\nswap A 3\nxor 3 $12\nswap B 3\nbackwards\n", engine);
+        assert_eq!(m.status(), Status::Finished);
+        assert_eq!((m.register(&Register::A), m.register(&Register::B)), (0, 0));
+        assert_eq!(m.mem().get(3), Some(0));
+        // a place swapped with itself is left alone
+        let m = run("inc A $4\nswap A A\n", engine);
+        assert_eq!(m.register(&Register::A), 4);
+    }
+}
+
+#[test]
+fn xor_rejects_aliases() {
+    for source in ["xor A A\n", "xor A *A\n", "xor 5 *$5\n", "xor *B **B\n"] {
+        let errors = Program::from_source(source).unwrap_err();
+        assert_eq!(errors.len(), 1, "{}", source);
+        let error = errors.iter().next().unwrap();
+        assert!(error.message.contains("xor can't read its source through its destination"));
+        assert_eq!((error.line(), error.column()), (Some(1), Some(1)));
+    }
+    // the same value in a different place is fine
+    assert!(Program::from_source("xor A B\nxor *A A\nxor *A *B\n").is_ok());
+}
+
+#[test]
+fn swap_through_the_other_warns() {
+    let program = Program::from_source("swap A *A\nswap **B *B\nswap A B\nswap *A *A\n").unwrap();
+    let warnings: Vec<_> = reversible::check(program.lines()).iter().map(|d| (d.line().unwrap(), d.message.clone())).collect();
+    assert_eq!(warnings.iter().map(|w| w.0).collect::<Vec<_>>(), vec![1, 2]);
+    assert!(warnings[0].1.contains("swap finds one place through the other"));
+    // conditions reading either side are caught too
+    let program = Program::from_source("swap A B if B = $0\n").unwrap();
+    assert_eq!(reversible::check(program.lines()).len(), 1);
+}
@@ -0,0 +1,78 @@
+use std::io;
+
+use moonwalk::bf;
+use moonwalk::transpile::{self, Language};
+use moonwalk::word::{Overflow, Word};
+use moonwalk::{Machine, Program, Status};
+
+mod common;
+use common::SharedBuf;
+
+fn run(bf: &str, input: &str, word: Word) -> (Status, String) {
+    let source = transpile::transpile(bf, Language::Bf).expect("brainfuck should parse");
+    let program = Program::from_source(&source).expect("transpiled program should build");
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut machine = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    machine.set_word(word);
+    let status = machine.run();
+    let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+    (status, text)
+}
+
+const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+#[test]
+fn hello_world() {
+    assert_eq!(run(HELLO, "", Word::default()), (Status::Finished, "Hello World!\n".to_string()));
+}
+
+#[test]
+fn echo() {
+    assert_eq!(run(",[.,]", "moonwalk", Word::default()), (Status::Finished, "moonwalk".to_string()));
+}
+
+#[test]
+fn byte_cells_wrap() {
+    // 0 - 1 is 255 with byte cells, so this counts down 255 times
+    let word = Word::new(8, Overflow::Wrapping).unwrap();
+    let bf = format!("->-[<->-]<{}.", "+".repeat(49));
+    assert_eq!(run(&bf, "", word), (Status::Finished, "1".to_string()));
+}
+
+#[test]
+fn wraps_like_bf_at_every_word_size() {
+    // the first cell goes round to 254 then down past 0, the second to 0
+    let source = format!("+[+]-->-[<->-]<{}.>+[+]{}.", "+".repeat(49), "+".repeat(65));
+    let out = SharedBuf::default();
+    let mut ctx = bf::Context::new(4, bf::Eof::Zero, Box::new(io::empty()), Box::new(out.clone()));
+    bf::eval(&bf::parse(&source).unwrap(), &mut ctx).unwrap();
+    let expected = String::from_utf8(out.0.borrow().clone()).unwrap();
+    assert_eq!(expected, "0A");
+    // nothing overflows, so it doesn't matter what overflowing would do
+    for bits in [8, 16, 32, 64] {
+        for overflow in [Overflow::Wrapping, Overflow::Trapping, Overflow::Saturating] {
+            let word = Word::new(bits, overflow).unwrap();
+            assert_eq!(run(&source, "", word), (Status::Finished, expected.clone()), "{} bits {}", bits, overflow);
+        }
+    }
+}
+
+#[test]
+fn runs_are_merged() {
+    let source = transpile::from_bf(&bf::parse("+++>>--<").unwrap());
+    assert!(source.contains("inc *A $3 if B = $0\n"));
+    assert!(source.contains("inc A $2\n"));
+    assert!(source.contains("inc *A $254 if B = $0\n"));
+    assert!(source.contains(&format!("xor A ${}\ninc A $1\n", usize::MAX)));
+    // nothing prints, so there is no print routine
+    assert!(!source.contains("print"));
+}
+
+#[test]
+fn transpiles_deeply_nested_loops() {
+    let source = transpile::from_bf(&bf::parse(&format!("{}{}", "[".repeat(100_000), "]".repeat(100_000))).unwrap());
+    assert!(source.contains("jump end-0 if *A = $0\n"));
+    // the innermost loop closes first
+    assert!(source.contains("jump loop-99999 if *A > $0\nend-99999: forwards\njump loop-99998"));
+}
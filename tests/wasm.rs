@@ -0,0 +1,223 @@
+use std::io;
+
+use moonwalk::backend::{self, wasm, Settings, Target};
+use moonwalk::word::{Overflow, Word};
+use moonwalk::{Machine, Program, RuntimeError, Status};
+
+mod common;
+use common::SharedBuf;
+
+// everything a run leaves behind that both sides can be compared on
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    status: Status,
+    error: Option<RuntimeError>,
+    output: String,
+    registers: [usize; 4],
+    forward: bool,
+    pc: usize,
+    mem: Vec<usize>
+}
+
+const CELLS: usize = 64;
+
+fn interpret(source: &str, input: &str, word: Word) -> Outcome {
+    let program = Program::from_source(source).unwrap();
+    let out = SharedBuf::default();
+    let input = io::Cursor::new(input.as_bytes().to_vec());
+    let mut machine = Machine::with_io(program, Box::new(input), Box::new(out.clone()));
+    machine.set_word(word);
+    machine.set_memory(moonwalk::memory::MemoryModel::Fixed(CELLS));
+    let status = machine.run();
+    let ctx = machine.context();
+    let output = String::from_utf8(out.0.borrow().clone()).unwrap();
+    Outcome {
+        status,
+        error: machine.error().cloned(),
+        output,
+        registers: [ctx.a, ctx.b, ctx.c, ctx.d],
+        forward: ctx.forward,
+        pc: ctx.pc,
+        mem: (0..CELLS).map(|addr| machine.mem().get(addr).unwrap()).collect()
+    }
+}
+
+struct Host {
+    input: Vec<u8>,
+    output: Vec<u8>
+}
+
+// what a browser playground would do, run the module with getc and putc
+// wired up and turn the error globals back into a RuntimeError
+fn wasm(source: &str, input: &str, word: Word) -> Outcome {
+    let program = Program::from_source(source).unwrap();
+    let settings = Settings{word, memory: moonwalk::memory::MemoryModel::Fixed(CELLS), ..Settings::default()};
+    let bytes = backend::compile(&program, Target::Wasm, &settings).unwrap();
+
+    let engine = wasmi::Engine::default();
+    let module = wasmi::Module::new(&engine, &bytes[..]).unwrap();
+    let mut store = wasmi::Store::new(&engine, Host{input: input.bytes().rev().collect(), output: Vec::new()});
+    let mut linker = wasmi::Linker::new(&engine);
+    linker.func_wrap("moonwalk", "getc", |mut caller: wasmi::Caller<'_, Host>| -> i32 {
+        caller.data_mut().input.pop().unwrap_or(0) as i32
+    }).unwrap();
+    linker.func_wrap("moonwalk", "putc", |mut caller: wasmi::Caller<'_, Host>, c: i32| {
+        caller.data_mut().output.push(c as u8);
+    }).unwrap();
+    let instance = linker.instantiate(&mut store, &module).unwrap().start(&mut store).unwrap();
+    let run = instance.get_typed_func::<(), i32>(&store, "run").unwrap();
+    let result = run.call(&mut store, ());
+
+    let global = |name: &str| instance.get_global(&store, name).unwrap().get(&store);
+    let int = |name: &str| match global(name) {
+        // pc is -1 after running off the start, same as the wrapped usize
+        wasmi::Val::I32(v) => v as isize as usize,
+        wasmi::Val::I64(v) => v as u64 as usize,
+        _ => unreachable!()
+    };
+    let pc = int("pc");
+    let (status, error) = match result {
+        Ok(0) => (Status::Finished, None),
+        Ok(1) => (Status::Halted, None),
+        Ok(n) => panic!("run returned {}", n),
+        Err(_) => {
+            let value = int("error_value");
+            let error = match int("error") as i32 {
+                wasm::ERROR_OVERFLOW => RuntimeError::Overflow{pc},
+                wasm::ERROR_OUT_OF_BOUNDS => RuntimeError::OutOfBounds{pc, addr: value},
+                wasm::ERROR_WRITE_TO_LITERAL => RuntimeError::WriteToLiteral{pc},
+                wasm::ERROR_INVALID_UTF8 => RuntimeError::InvalidUtf8{pc, value},
+                n => panic!("unknown error {}", n)
+            };
+            (Status::Trapped, Some(error))
+        }
+    };
+    let memory = instance.get_memory(&store, "memory").unwrap();
+    let data = memory.data(&store);
+    let mem = (0..CELLS).map(|addr| {
+        let mut cell = [0; 8];
+        cell.copy_from_slice(&data[addr * 8..addr * 8 + 8]);
+        u64::from_le_bytes(cell) as usize
+    }).collect();
+    Outcome {
+        status,
+        error,
+        output: String::from_utf8(store.data().output.clone()).unwrap(),
+        registers: [int("a"), int("b"), int("c"), int("d")],
+        forward: int("forward") != 0,
+        pc,
+        mem
+    }
+}
+
+fn same(source: &str, input: &str, word: Word) {
+    assert_eq!(wasm(source, input, word), interpret(source, input, word), "{:?}", source);
+}
+
+#[test]
+fn examples_match() {
+    same(include_str!("../hello.mw"), "", Word::default());
+    same(include_str!("../testJmp.mw"), "", Word::default());
+    same(include_str!("../testIO.mw"), "q", Word::default());
+    same(include_str!("../testc.mw"), "", Word::default());
+}
+
+#[test]
+fn control_flow_matches() {
+    let source = "\
+jump main
+print: jump
+io A if backwards
+inc B $1 if backwards
+backwards
+main: inc A $0x41
+jump print
+halt if B = $3
+jump print
+";
+    same(source, "", Word::default());
+    same("inc A $1\nf: from\nbackwards\nfrom f\ninc B $1\n", "", Word::default());
+    same("forwards\nhalt if A = $2\ninc A $1\nreverse\n", "", Word::default());
+    same("backwards\n", "", Word::default());
+    same("", "", Word::default());
+    same("top: inc A $1\njump top if A < $2000\nhalt if A = $2000\n", "", Word::default());
+    same("inc A $3\nio A if A = $3 or 0xdeadbeefabcdef = $0\nhalt if (backwards or A >= $1) and *A = $0\n", "", Word::default());
+}
+
+#[test]
+fn memory_and_io_match() {
+    same("inc A $0x10\ninc *A $0x20\ninc **A $0x2a\nio **A if backwards\nreverse\n", "", Word::default());
+    same("io A\nio *B\nio B if backwards\nreverse\n", "xyz", Word::default());
+    same("inc A $0xffffff\ninc *A $1\n", "", Word::default());
+    same("inc 64 $1\n", "", Word::default());
+    same("io $1\n", "", Word::default());
+    same("inc A $200\nio A if backwards\nreverse\n", "", Word::default());
+}
+
+#[test]
+fn swap_and_xor_match() {
+    same("inc A $72\ninc 5 $105\ninc B $5\nswap A *B\nxor C $33\nswap C 6\nxor 6 A\nio 6 if backwards\nio A if backwards\nbackwards\n", "", Word::default());
+    same("inc A $1\nswap A 0xffffff\n", "", Word::default());
+}
+
+#[test]
+fn moves_match() {
+    same("inc A $2\nmove A $5\nmove A B\ninc B $3\nmove 3 B\nmove *B $8\n", "", Word::default());
+    same("inc A $72\nio A if backwards\nmove A $105\nio A if backwards\nmove 3 A\nio 3 if backwards\nmove *B $33\nunmove A $33\nbackwards\n", "", Word::default());
+    same("inc A $49\nunmove A $9\nio A if backwards\nbackwards\n", "", Word::default());
+    same("move 0xffffff $1\n", "", Word::default());
+    same("move 64 $1\n", "", Word::default());
+}
+
+#[test]
+fn calls_match() {
+    same("inc B $48\ncall add\ncall add\njump end\nadd: inc B $1\nio B if backwards\nreturn\nend: from\nbackwards\n", "", Word::default());
+    same("inc A $1\ninc D $48\ncall f\njump end\nf: inc C $1\ninc D C\nio D if backwards\ncall f if C < $3\nneg C\ninc C $1\nneg C\nreturn\nend: from\nbackwards\n", "", Word::default());
+    same("inc A $1\ncall f\nhalt\nf: backwards if A = $1\nreturn\n", "", Word::default());
+    same("return\ninc A $49\nio A if backwards\nbackwards\n", "", Word::default());
+    same("f: backwards if A = $49\ninc A $48\nio A if backwards\nreturn\ninc A $1\ncall f\n", "", Word::default());
+    same("f: from\ninc A $49\nio A if backwards\ncall g\nbackwards\ng: from\nreturn\n", "", Word::default());
+}
+
+// each halts when the result is right for its word, so agreeing on the
+// status and output means agreeing on the value
+const ARITH: &[&str] = &[
+    "inc A $100\nmul A $3\nhalt if A = $44\n",
+    "inc A $44\nhalt if backwards and A = $100\nmul A $3 if backwards\nbackwards\n",
+    "inc A $0x81\nrol A $1\nhalt if A = $3\n",
+    "inc B $68\ninc A $0x10\nror A B\nhalt if A = $1\n",
+    "inc A $1\nneg A\nhalt if A = $0xff\n",
+    "inc B $3\ninc 3 $5\nmul *B $7\nneg 3\nrol *B $2\nhalt if 3 = $119\n",
+    "inc A $0x8000000000000001\nrol A $4\nhalt if A = $0x18\n",
+    "inc A $0x5555555555555556\nmul A $3\nhalt if A = $2\n",
+];
+
+#[test]
+fn arithmetic_matches() {
+    for bits in [8, 64] {
+        for overflow in [Overflow::Wrapping, Overflow::Trapping, Overflow::Saturating] {
+            for source in ARITH {
+                same(source, "", Word::new(bits, overflow).unwrap());
+            }
+        }
+    }
+}
+
+#[test]
+fn overflow_matches() {
+    for overflow in &[Overflow::Wrapping, Overflow::Trapping, Overflow::Saturating] {
+        same("inc A $250\ninc A $10\nhalt if A = $4\n", "", Word::new(8, *overflow).unwrap());
+        same("forwards\nhalt if A = $0xffff\ninc A $1 if backwards\nbackwards\n", "", Word::new(16, *overflow).unwrap());
+        same("inc A $0xffffffffffffffff\ninc A $2\n", "", Word::new(64, *overflow).unwrap());
+    }
+}
+
+#[test]
+fn text_and_binary_agree() {
+    let program = Program::from_source(include_str!("../hello.mw")).unwrap();
+    let settings = Settings::default();
+    let text = backend::compile(&program, Target::Wat, &settings).unwrap();
+    let binary = backend::compile(&program, Target::Wasm, &settings).unwrap();
+    assert!(text.starts_with(b";; generated by moonwalk"));
+    assert_eq!(&binary[..4], b"\0asm");
+}
@@ -0,0 +1,102 @@
+use moonwalk::ast::Register;
+use moonwalk::word::{Overflow, Word};
+use moonwalk::{Machine, Program, RuntimeError, Status};
+
+#[test]
+fn rejects_odd_sizes() {
+    assert!(Word::new(12, Overflow::Wrapping).is_err());
+    assert_eq!(Word::new(16, Overflow::Wrapping).unwrap().max(), 0xffff);
+}
+
+#[test]
+fn policies() {
+    let wrap = Word::new(8, Overflow::Wrapping).unwrap();
+    let trap = Word::new(8, Overflow::Trapping).unwrap();
+    let sat = Word::new(8, Overflow::Saturating).unwrap();
+    assert_eq!(wrap.add(250, 10), Some(4));
+    assert_eq!(wrap.sub(4, 10), Some(250));
+    assert_eq!(trap.add(250, 10), None);
+    assert_eq!(trap.sub(4, 10), None);
+    assert_eq!(trap.add(250, 5), Some(255));
+    assert_eq!(sat.add(250, 10), Some(255));
+    assert_eq!(sat.sub(4, 10), Some(0));
+    let wide = Word::default();
+    assert_eq!(wide.add(usize::MAX, 2), Some(1));
+    assert_eq!(wide.sub(0, 1), Some(usize::MAX));
+}
+
+fn machine(source: &str, word: Word) -> Machine {
+    let program = Program::from_source(source).unwrap();
+    let mut machine = Machine::with_io(program, Box::new(std::io::empty()), Box::new(std::io::sink()));
+    machine.set_word(word);
+    machine
+}
+
+#[test]
+fn wrapping_inc_is_exactly_reversible() {
+    // run inc forwards then backwards over the same lines
+    let source = "inc A $1\ninc A $200\ninc A $100\nreverse if forwards\n";
+    for bits in [8, 16, 32, 64] {
+        let mut m = machine(source, Word::new(bits, Overflow::Wrapping).unwrap());
+        assert_eq!(m.run(), Status::Finished);
+        // line 0 is never undone, running off the start stops first
+        assert_eq!(m.register(&Register::A), 1);
+    }
+}
+
+#[test]
+fn trapping_overflow_stops_the_program() {
+    let mut m = machine("inc A $200\ninc A $100\nhalt\n", Word::new(8, Overflow::Trapping).unwrap());
+    assert_eq!(m.run(), Status::Trapped);
+    assert_eq!(m.error(), Some(&RuntimeError::Overflow{pc: 1}));
+    assert_eq!(m.error_diagnostic().unwrap().line(), Some(2));
+    assert_eq!(m.register(&Register::A), 200);
+}
+
+#[test]
+fn backwards_inc_no_longer_panics_on_underflow() {
+    let mut m = machine("backwards\ninc A $1\n", Word::default());
+    m.context_mut().pc = 1;
+    m.context_mut().forward = false;
+    m.step();
+    assert_eq!(m.register(&Register::A), usize::MAX);
+}
+
+#[test]
+fn mul_and_div() {
+    let wrap = Word::new(8, Overflow::Wrapping).unwrap();
+    let trap = Word::new(8, Overflow::Trapping).unwrap();
+    let sat = Word::new(8, Overflow::Saturating).unwrap();
+    assert_eq!(wrap.mul(100, 3), Some(44));
+    assert_eq!(wrap.div(44, 3), Some(100));
+    assert_eq!(trap.mul(100, 3), None);
+    assert_eq!(trap.mul(85, 3), Some(255));
+    assert_eq!(trap.div(255, 3), Some(85));
+    assert_eq!(trap.div(44, 3), None);
+    assert_eq!(sat.mul(100, 3), Some(255));
+    assert_eq!(sat.div(44, 3), Some(14));
+    // every odd number undoes in every width
+    for word in [wrap, Word::default()] {
+        for k in [1, 3, 5, 0xff, 0x1235] {
+            let inv = word.inverse(k).unwrap();
+            assert_eq!(word.truncate(word.truncate(k).wrapping_mul(inv)), 1);
+            assert_eq!(word.div(word.mul(77, k).unwrap(), k), Some(77));
+        }
+    }
+    assert_eq!(wrap.inverse(4), None);
+}
+
+#[test]
+fn rotations_and_neg() {
+    let byte = Word::new(8, Overflow::Trapping).unwrap();
+    assert_eq!(byte.rol(0x81, 1), 0x03);
+    assert_eq!(byte.ror(0x03, 1), 0x81);
+    assert_eq!(byte.rol(0x81, 9), 0x03);
+    assert_eq!(byte.ror(0x81, 8), 0x81);
+    assert_eq!(byte.neg(1), 0xff);
+    assert_eq!(byte.neg(0), 0);
+    let wide = Word::default();
+    assert_eq!(wide.rol(1 << 63 | 1, 4), 0x18);
+    assert_eq!(wide.ror(0x10, 68), 1);
+    assert_eq!(wide.neg(wide.neg(12345)), 12345);
+}